@@ -0,0 +1,80 @@
+//! # IMU Noise Example
+//!
+//! This example characterises the noise of the onboard ICM42670 by the Allan deviation of
+//! each axis, giving the random walk and bias instability used to tune the gimbal's
+//! `AhrsConfig` for a batch of boards.
+//!
+//! Leave the board still, away from vibration and draughts, for the whole run. The LED
+//! breathes blue while sampling, and the curves so far are logged every minute. It turns
+//! green once the run is complete and the results are logged, or red if the board moved.
+
+#![no_std]
+#![no_main]
+
+use core::future::pending;
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Timer};
+use esp32c3_devkit_demo::{
+    bsp::Board,
+    imu::{Bandwidth, GyroRange, ImuConfig, ImuSensor, Odr, PowerMode},
+    led::{self, Repeat},
+};
+use log::{error, info};
+use smart_leds::{
+    RGB8,
+    colors::{BLUE, GREEN, RED},
+};
+
+use esp_backtrace as _;
+
+/// How long to sample for, long enough for the curves to reach their floor.
+const DURATION: Duration = Duration::from_secs(2 * 60 * 60);
+/// Shades of blue the LED breathes through while sampling.
+const BREATHE: [RGB8; 4] = [
+    RGB8::new(0, 0, 16),
+    RGB8::new(0, 0, 64),
+    BLUE,
+    RGB8::new(0, 0, 64),
+];
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let board = Board::init();
+    let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
+
+    let mut imu = ImuSensor::new(board.i2c_bus).unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+    // the narrowest range resolves the smallest changes in bias
+    imu.configure(ImuConfig {
+        odr: Odr::Hz100,
+        gyro_range: GyroRange::Dps250,
+        bandwidth: Bandwidth::Hz34,
+        ..Default::default()
+    })
+    .unwrap();
+    // let the sensor warm up before sampling
+    Timer::after_secs(5).await;
+
+    led.set_sequence(&BREATHE, Duration::from_millis(500), Repeat::Forever)
+        .unwrap();
+    match imu.characterise_noise(DURATION).await {
+        Ok(allan) => {
+            allan.log();
+            let noise = allan.noise();
+            for (name, axes) in [("accel", noise.accel), ("gyro", noise.gyro)] {
+                for (axis, noise) in ["X", "Y", "Z"].iter().zip(axes) {
+                    info!(
+                        "{} {}: random walk {:?}/√s, bias instability {:?} at τ {:?}s",
+                        name, axis, noise.random_walk, noise.bias_instability, noise.floor_tau
+                    );
+                }
+            }
+            led.set_colour(GREEN).unwrap();
+        }
+        Err(error) => {
+            error!("Noise characterisation stopped: {}", error);
+            led.set_colour(RED).unwrap();
+        }
+    }
+    pending().await
+}
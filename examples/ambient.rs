@@ -9,7 +9,7 @@
 use embassy_executor::Spawner;
 use embassy_futures::select::{Either, select};
 use embassy_time::{Duration, Timer};
-use esp32c3_devkit_demo::{ambient::AmbientSensor, bsp::Board};
+use esp32c3_devkit_demo::{ambient::BoardAmbient, bsp::Board};
 use log::info;
 use shtcx::PowerMode;
 
@@ -19,7 +19,7 @@ use esp_backtrace as _;
 async fn main(_spawner: Spawner) {
     let board = Board::init();
 
-    let mut ambient = AmbientSensor::new(board.i2c_bus);
+    let mut ambient = BoardAmbient::new(board.i2c_bus);
     ambient
         .set_power_mode(PowerMode::NormalMode, Duration::from_millis(1000))
         .unwrap();
@@ -36,9 +36,9 @@ async fn main(_spawner: Spawner) {
 /// Run the task to read the sensor for 10 seconds and then stop
 /// This will read the sensor every 2 seconds and print the result.
 /// If the sensor is not available, it will print an error message.
-async fn run_task(sensor: &mut AmbientSensor) {
+async fn run_task(sensor: &mut BoardAmbient) {
     let res = select(
-        sensor.start_task(Duration::from_secs(2), None),
+        sensor.start_task(Duration::from_secs(2), async |sample| sample.log()),
         Timer::after(Duration::from_secs(10)),
     )
     .await;
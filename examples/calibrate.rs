@@ -0,0 +1,97 @@
+//! # IMU Calibration Example
+//!
+//! This example walks through calibrating the onboard ICM42670 and stores the result in flash,
+//! where the main application picks it up on the next boot.
+//!
+//! Follow the instructions in the log, pressing the boot button once the board is in place:
+//! 1. Lay the board flat and still to capture the gyroscope bias.
+//! 2. Rest the board still on each of its six faces in turn to calibrate the accelerometer.
+//!
+//! The LED blinks blue while waiting for the button, turns red if the board moved during
+//! a capture, and turns green once the calibration is saved.
+
+#![no_std]
+#![no_main]
+
+use core::future::pending;
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::Input;
+use esp32c3_devkit_demo::{
+    bsp::{Board, CALIBRATION_OFFSET},
+    buttons,
+    imu::{Bandwidth, ImuConfig, ImuSensor, Odr, PowerMode, SixPosition},
+    led::{self, LedActor, Repeat},
+};
+use log::{info, warn};
+use smart_leds::colors::{BLACK, BLUE, GREEN, RED};
+
+use esp_backtrace as _;
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let mut board = Board::init();
+    let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
+    let mut button = board.button;
+
+    let mut imu = ImuSensor::new(board.i2c_bus).unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+    imu.configure(ImuConfig {
+        odr: Odr::Hz100,
+        bandwidth: Bandwidth::Hz34,
+        ..Default::default()
+    })
+    .unwrap();
+    Timer::after_secs(1).await;
+
+    loop {
+        info!("Lay the board flat and still, then press the boot button");
+        wait_for_button(&mut button, &led).await;
+        match imu.calibrate_gyro(Duration::from_secs(2)).await {
+            Ok(_) => break,
+            Err(error) => moved(&led, error).await,
+        }
+    }
+
+    let mut six = SixPosition::default();
+    loop {
+        let Some(face) = six.missing().next() else {
+            break;
+        };
+        info!(
+            "Rest the board still with {:?}, then press the boot button",
+            face
+        );
+        wait_for_button(&mut button, &led).await;
+        if let Err(error) = imu.capture_face(&mut six, Duration::from_secs(1)).await {
+            moved(&led, error).await;
+        }
+    }
+
+    let calibration = imu.calibration();
+    info!("Calibration: {:?}", calibration);
+    calibration
+        .save(&mut board.flash, CALIBRATION_OFFSET)
+        .expect("failed to save calibration");
+    info!("Calibration saved");
+    led.set_colour(GREEN).unwrap();
+
+    pending().await
+}
+
+/// Blink the LED until the boot button is pressed and released.
+async fn wait_for_button(button: &mut Input<'static>, led: &LedActor) {
+    led.set_sequence(&[BLUE, BLACK], Duration::from_millis(300), Repeat::Forever)
+        .unwrap();
+    buttons::wait_for_button(button).await;
+    led.off().unwrap();
+    // give the board time to settle after the press
+    Timer::after_millis(500).await;
+}
+
+/// Report a failed capture.
+async fn moved(led: &LedActor, error: esp32c3_devkit_demo::AppError) {
+    warn!("{}, try again", error);
+    led.set_colour(RED).unwrap();
+    Timer::after_secs(1).await;
+}
@@ -0,0 +1,86 @@
+//! # Data Capture Example
+//!
+//! This example records labelled motion data for training the gesture and activity models.
+//! Every IMU sample at 100Hz is streamed over the USB serial port in a compact frame,
+//! tagged with the current label, see [`esp32c3_devkit_demo::imu::capture`].
+//!
+//! Pressing the boot button cycles the label, which the LED shows: off for unlabelled,
+//! then red, green, blue and yellow for labels 1 to 4. Save a recording with
+//!
+//! ```bash
+//! python tools/capture_csv.py /dev/ttyACM0 recordings/ --labels none shake circle flick_left flick_right
+//! ```
+//!
+//! which writes a CSV file per label. The logs would share the serial port and could land
+//! in the middle of a frame, so they are turned off once the board is initialised.
+
+#![no_std]
+#![no_main]
+
+use core::cell::Cell;
+use core::future::pending;
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either3, select3};
+use embassy_time::Duration;
+use embedded_io_async::Write;
+use esp32c3_devkit_demo::{
+    bsp::Board,
+    buttons::wait_for_button,
+    imu::{self, ImuConfig, ImuSensor, Odr, PowerMode, capture::encode_frame},
+    led,
+};
+use log::{error, info};
+use smart_leds::{
+    RGB8,
+    colors::{BLACK, BLUE, GREEN, RED, YELLOW},
+};
+
+use esp_backtrace as _;
+
+/// Colour shown for each label.
+const LABELS: [RGB8; 5] = [BLACK, RED, GREEN, BLUE, YELLOW];
+/// How often the FIFO is drained.
+const PERIOD: Duration = Duration::from_millis(50);
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let board = Board::init();
+    log::set_max_level(log::LevelFilter::Off);
+    let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
+    let mut button = board.button;
+    let mut usb = board.usb_serial.into_async();
+
+    let mut imu = ImuSensor::new(board.i2c_bus).unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+    imu.configure(ImuConfig {
+        odr: Odr::Hz100,
+        ..Default::default()
+    })
+    .unwrap();
+    imu.enable_fifo(20).unwrap();
+
+    let label = Cell::new(0);
+    let cycle_label = async {
+        loop {
+            wait_for_button(&mut button).await;
+            let next = (label.get() + 1) % LABELS.len() as u8;
+            label.set(next);
+            led.set_colour(LABELS[next as usize]).unwrap();
+            info!("Recording label {}", next);
+        }
+    };
+    let stream = async {
+        let samples = imu::samples();
+        loop {
+            let frame = encode_frame(label.get(), &samples.receive().await);
+            if let Err(error) = usb.write_all(&frame).await {
+                error!("Failed to send a sample: {:?}", error);
+            }
+        }
+    };
+    let imu_task = imu.start_sampling(PERIOD);
+    if let Either3::First(Err(error)) = select3(imu_task, stream, cycle_label).await {
+        error!("IMU sampling stopped: {}", error);
+    }
+    pending().await
+}
@@ -10,7 +10,10 @@
 use core::future::pending;
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
-use esp32c3_devkit_demo::{bsp::Board, imu::ImuSensor};
+use esp32c3_devkit_demo::{
+    bsp::Board,
+    imu::{AccelRange, Bandwidth, GyroRange, ImuConfig, ImuSensor, Odr, PowerMode},
+};
 
 use esp_backtrace as _;
 
@@ -18,16 +21,24 @@ use esp_backtrace as _;
 async fn main(_spawner: Spawner) -> ! {
     let board = Board::init();
 
-    let mut imu = ImuSensor::new(board.i2c_bus);
+    let mut imu = ImuSensor::new(board.i2c_bus).unwrap();
 
     // Set the power mode to normal mode.
-    imu.set_power_mode(icm42670::PowerMode::SixAxisLowNoise)
-        .unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+
+    // Narrow the ranges for a device held in the hand, and sample at 100Hz.
+    imu.configure(ImuConfig {
+        accel_range: AccelRange::G4,
+        gyro_range: GyroRange::Dps500,
+        odr: Odr::Hz100,
+        bandwidth: Bandwidth::Hz34,
+    })
+    .unwrap();
 
     Timer::after_secs(1).await;
 
     // Start the imu to read the sensor every 20 milliseconds.
-    imu.start_task(Duration::from_millis(20), None)
+    imu.start_task(Duration::from_millis(20), |_| {}, async |meas| meas.log())
         .await
         .unwrap();
 
@@ -0,0 +1,83 @@
+//! # IMU Priority Example
+//!
+//! This example samples the IMU at 100Hz from an interrupt executor at a raised priority,
+//! so that the LED and anything else on the main executor cannot delay it. The board's
+//! I2C bus is shared across priorities for this, locked in a critical section.
+//! Samples are handed back to the main executor through a channel, which checks their
+//! sequence numbers for any that were dropped.
+//!
+//! The sampling task logs how late its wake-ups were every 10 seconds. The LED cycles
+//! through colours quickly meanwhile, to keep the main executor busy.
+
+#![no_std]
+#![no_main]
+
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Instant};
+use esp_hal::interrupt::Priority;
+use esp_hal_embassy::InterruptExecutor;
+use esp32c3_devkit_demo::{
+    bsp::{Board, SharedI2cBusDevice},
+    imu::{self, ImuConfig, ImuSensor, Odr, PowerMode},
+    led::{self, Repeat},
+};
+use log::{error, info, warn};
+use smart_leds::colors::{BLUE, GREEN, RED};
+use static_cell::StaticCell;
+
+use esp_backtrace as _;
+
+/// Time between samples.
+const PERIOD: Duration = Duration::from_millis(10);
+
+#[embassy_executor::task]
+async fn sampling_task(mut imu: ImuSensor<SharedI2cBusDevice<'static>>) {
+    if let Err(error) = imu.start_sampling(PERIOD).await {
+        error!("IMU sampling stopped: {}", error);
+    }
+}
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let board = Board::init_shared();
+    let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
+    led.set_sequence(
+        &[RED, GREEN, BLUE],
+        Duration::from_millis(20),
+        Repeat::Forever,
+    )
+    .unwrap();
+
+    let mut imu = ImuSensor::from_i2c(I2cDevice::new(board.i2c_bus)).unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+    imu.configure(ImuConfig {
+        odr: Odr::Hz100,
+        ..Default::default()
+    })
+    .unwrap();
+
+    static EXECUTOR: StaticCell<InterruptExecutor<2>> = StaticCell::new();
+    let interrupt = board.software_interrupts.software_interrupt2;
+    let executor = EXECUTOR.init(InterruptExecutor::new(interrupt));
+    let high_priority = executor.start(Priority::Priority3);
+    high_priority.must_spawn(sampling_task(imu));
+
+    let samples = imu::samples();
+    let mut expected = None;
+    let mut received = 0;
+    let mut since = Instant::now();
+    loop {
+        let meas = samples.receive().await;
+        if expected.is_some_and(|sequence| sequence != meas.sequence) {
+            warn!("Samples dropped before {}", meas.sequence);
+        }
+        expected = Some(meas.sequence.wrapping_add(1));
+        received += 1;
+        if since.elapsed() >= Duration::from_secs(1) {
+            info!("{} samples in the last second, latest {:?}", received, meas);
+            received = 0;
+            since = Instant::now();
+        }
+    }
+}
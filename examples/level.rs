@@ -0,0 +1,78 @@
+//! # Bubble Level Example
+//!
+//! This example turns the board into a bubble level for mounting it on the desk.
+//! The IMU buffers samples in its FIFO at 100Hz and the gimbal's inclination is
+//! compared with a reference attitude, which starts as the board lying flat.
+//!
+//! The LED is green while the board is level to within a degree, and shades through
+//! yellow to red as it tilts further. Pressing the boot button tares the level,
+//! taking the board's current attitude as the new reference.
+
+#![no_std]
+#![no_main]
+
+use core::future::pending;
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either3, select3};
+use embassy_time::{Duration, Timer};
+use esp32c3_devkit_demo::{
+    bsp::Board,
+    buttons::wait_for_button,
+    imu::{self, Detectors, Event, ImuConfig, ImuSensor, LevelConfig, Odr, PowerMode, tare_level},
+    led,
+};
+use log::{error, info};
+use smart_leds::RGB8;
+
+use esp_backtrace as _;
+
+/// Colour for a severity from 0 when level to 1 at full tilt, from green through yellow to red.
+fn shade(severity: f32) -> RGB8 {
+    let red = (severity * 2.0).min(1.0);
+    let green = ((1.0 - severity) * 2.0).min(1.0);
+    RGB8::new((red * 255.0) as u8, (green * 255.0) as u8, 0)
+}
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let board = Board::init();
+    let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
+    let mut button = board.button;
+
+    let mut imu = ImuSensor::new(board.i2c_bus).unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+    imu.configure(ImuConfig {
+        odr: Odr::Hz100,
+        ..Default::default()
+    })
+    .unwrap();
+    imu.enable_fifo(20).unwrap();
+    let mut detectors = Detectors::default();
+    detectors.enable_level(LevelConfig::default());
+    let mut events = imu::subscribe().expect("IMU event subscriber available");
+    Timer::after_secs(1).await;
+
+    let show_level = async {
+        loop {
+            if let Event::Level(level) = events.next_message_pure().await {
+                led.set_colour(shade(level.severity)).unwrap();
+            }
+        }
+    };
+    let tare = async {
+        loop {
+            wait_for_button(&mut button).await;
+            info!("Taring the level");
+            tare_level();
+        }
+    };
+    let imu_task = imu.start_task(
+        Duration::from_millis(50),
+        |meas| detectors.update(meas),
+        async |meas| meas.log(),
+    );
+    if let Either3::First(Err(error)) = select3(imu_task, show_level, tare).await {
+        error!("IMU task stopped: {}", error);
+    }
+    pending().await
+}
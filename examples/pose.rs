@@ -0,0 +1,71 @@
+//! # Mode Cube Example
+//!
+//! This example uses the board as a "mode cube", picking a mode by the side it rests on.
+//! The IMU buffers samples in its FIFO at 100Hz and each new pose is reported once the
+//! board has settled on it.
+//!
+//! The LED shows the mode: white face up, red on the left edge, green on the right,
+//! blue on the top and yellow on the bottom. It turns off while the board lies face down.
+
+#![no_std]
+#![no_main]
+
+use core::future::pending;
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Timer};
+use esp32c3_devkit_demo::{
+    bsp::Board,
+    imu::{self, Detectors, Event, ImuConfig, ImuSensor, Odr, Pose, PoseConfig, PowerMode},
+    led,
+};
+use log::{error, info};
+use smart_leds::colors::{BLUE, GREEN, RED, WHITE, YELLOW};
+
+use esp_backtrace as _;
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let board = Board::init();
+    let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
+
+    let mut imu = ImuSensor::new(board.i2c_bus).unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+    imu.configure(ImuConfig {
+        odr: Odr::Hz100,
+        ..Default::default()
+    })
+    .unwrap();
+    imu.enable_fifo(20).unwrap();
+    let mut detectors = Detectors::default();
+    detectors.enable_pose(PoseConfig::default());
+    let mut events = imu::subscribe().expect("IMU event subscriber available");
+    Timer::after_secs(1).await;
+
+    let show_pose = async {
+        loop {
+            let Event::Pose(pose) = events.next_message_pure().await else {
+                continue;
+            };
+            info!("Resting {:?}", pose);
+            match pose {
+                Pose::FaceUp => led.set_colour(WHITE),
+                Pose::FaceDown => led.off(),
+                Pose::Left => led.set_colour(RED),
+                Pose::Right => led.set_colour(GREEN),
+                Pose::Top => led.set_colour(BLUE),
+                Pose::Bottom => led.set_colour(YELLOW),
+            }
+            .unwrap();
+        }
+    };
+    let imu_task = imu.start_task(
+        Duration::from_millis(50),
+        |meas| detectors.update(meas),
+        async |meas| meas.log(),
+    );
+    if let Either::First(Err(error)) = select(imu_task, show_pose).await {
+        error!("IMU task stopped: {}", error);
+    }
+    pending().await
+}
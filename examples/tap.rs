@@ -0,0 +1,73 @@
+//! # Tap Example
+//!
+//! This example demonstrates using a tap on the board as an input alongside the boot button.
+//! The IMU buffers samples in its FIFO at 400Hz and every one is checked for taps.
+//!
+//! A single tap lights the LED in a colour for the axis it was tapped along,
+//! red for X, green for Y and blue for Z. A double tap flashes the LED,
+//! and pressing the boot button turns it off.
+
+#![no_std]
+#![no_main]
+
+use core::future::pending;
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Timer};
+use esp32c3_devkit_demo::{
+    bsp::Board,
+    buttons::{Press, wait_for_press},
+    imu::{self, Axis, Detectors, ImuConfig, ImuSensor, Odr, PowerMode, TapConfig, TapCount},
+    led::{self, Repeat},
+};
+use log::error;
+use smart_leds::colors::{BLACK, BLUE, GREEN, RED, WHITE};
+
+use esp_backtrace as _;
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) -> ! {
+    let board = Board::init();
+    let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
+    let mut button = board.button;
+
+    let mut imu = ImuSensor::new(board.i2c_bus).unwrap();
+    imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+    imu.configure(ImuConfig {
+        odr: Odr::Hz400,
+        ..Default::default()
+    })
+    .unwrap();
+    imu.enable_fifo(20).unwrap();
+    let mut detectors = Detectors::default();
+    detectors.enable_taps(TapConfig::default());
+    let mut events = imu::subscribe().expect("IMU event subscriber available");
+    Timer::after_secs(1).await;
+
+    let inputs = async {
+        loop {
+            match wait_for_press(&mut button, Some(&mut events)).await {
+                Press::Button => led.off().unwrap(),
+                Press::Tap(tap) if tap.count == TapCount::Double => led
+                    .set_sequence(&[WHITE, BLACK], Duration::from_millis(100), Repeat::N(3))
+                    .unwrap(),
+                Press::Tap(tap) => led
+                    .set_colour(match tap.axis {
+                        Axis::X => RED,
+                        Axis::Y => GREEN,
+                        Axis::Z => BLUE,
+                    })
+                    .unwrap(),
+            }
+        }
+    };
+    let imu_task = imu.start_task(
+        Duration::from_millis(50),
+        |meas| detectors.update(meas),
+        async |meas| meas.log(),
+    );
+    if let Either::First(Err(error)) = select(imu_task, inputs).await {
+        error!("IMU task stopped: {}", error);
+    }
+    pending().await
+}
@@ -1,34 +1,113 @@
 //! A simple struct to read from the Ambient Sensor.
+//!
+//! Like the IMU, the sensor is generic over any [`embedded_hal::i2c::I2c`] bus,
+//! and `BoardAmbient` is the sensor on the board's shared I2C bus.
+//!
+//! [`AmbientSensor::self_test`] checks the sensor's ID and that a measurement is plausible.
+
+use core::ops::RangeInclusive;
 
 use crate::AppError;
-use crate::ble::BleConnection;
-use crate::bsp::I2cBus;
-use crate::bsp::I2cBusDevice;
-use embassy_embedded_hal::shared_bus::{I2cDeviceError, blocking::i2c::I2cDevice};
+#[cfg(feature = "hardware")]
+use crate::bsp::{I2cBus, I2cBusDevice};
+#[cfg(feature = "hardware")]
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
 use embassy_time::Instant;
 use embassy_time::{Duration, Timer};
-use esp_hal::i2c::master::Error;
+use embedded_hal::i2c::{ErrorType, I2c};
 use log::info;
 use shtcx::ShtC3;
 use shtcx::{Measurement, PowerMode};
 
-pub struct AmbientSensor {
+/// Bits of the ID register that identify an SHTC3.
+const SHTC3_ID_MASK: u16 = 0x083F;
+/// Value of those bits on an SHTC3.
+const SHTC3_ID: u16 = 0x0807;
+/// Temperatures the board is expected to see, in °C.
+const PLAUSIBLE_TEMPERATURE: RangeInclusive<f32> = -20.0..=60.0;
+/// Humidity a working sensor reports, in %RH, as a stuck one tends to read 0 or 100.
+const PLAUSIBLE_HUMIDITY: RangeInclusive<f32> = 1.0..=99.0;
+
+/// Outcome of the SHTC3 self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AmbientSelfTest {
+    /// Contents of the ID register, `None` if the device did not answer
+    pub id: Option<u16>,
+    /// Temperature measured during the test in °C, `None` if it could not be read
+    pub temperature: Option<f32>,
+    /// Humidity measured during the test in %RH, `None` if it could not be read
+    pub humidity: Option<f32>,
+}
+
+impl AmbientSelfTest {
+    /// Whether the device answered as an SHTC3.
+    pub fn identified(&self) -> bool {
+        self.id.is_some_and(|id| id & SHTC3_ID_MASK == SHTC3_ID)
+    }
+    /// Whether the measurement was within the range the board is expected to see.
+    pub fn plausible(&self) -> bool {
+        self.temperature
+            .is_some_and(|t| PLAUSIBLE_TEMPERATURE.contains(&t))
+            && self
+                .humidity
+                .is_some_and(|h| PLAUSIBLE_HUMIDITY.contains(&h))
+    }
+    /// Whether the device was identified and its measurement was plausible.
+    pub fn passed(&self) -> bool {
+        self.identified() && self.plausible()
+    }
+}
+
+/// A measurement, numbered and timestamped so gaps and jitter can be seen downstream.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub measurement: Measurement,
+    /// When the measurement finished
+    pub at: Instant,
+    /// Number of measurements taken before this one, wrapping at `u32::MAX`
+    pub sequence: u32,
+}
+
+impl Sample {
+    /// Log the measurement, for when nothing else is listening.
+    pub fn log(&self) {
+        let meas = self.measurement;
+        info!("Temperature: {:?}°C", meas.temperature.as_degrees_celsius());
+        info!("Humidity: {:?}%RH", meas.humidity.as_percent());
+    }
+}
+
+/// The sensor on the board's shared I2C bus.
+#[cfg(feature = "hardware")]
+pub type BoardAmbient = AmbientSensor<I2cBusDevice<'static>>;
+
+pub struct AmbientSensor<I> {
     /// The Onboard temperature and humidity sensor
-    device: ShtC3<I2cBusDevice<'static>>,
+    device: ShtC3<I>,
     /// The power mode of the sensor
     power_mode: PowerMode,
     /// Length of time to take a sample
     read_time: Duration,
+    /// Number of the next sample
+    sequence: u32,
 }
 
-impl AmbientSensor {
-    /// Initialize the sensor.
+#[cfg(feature = "hardware")]
+impl BoardAmbient {
+    /// Initialize the sensor on the board's shared I2C bus.
     pub fn new(i2c_bus: &'static I2cBus<'static>) -> Self {
-        let i2c = I2cDevice::new(i2c_bus);
+        Self::from_i2c(I2cDevice::new(i2c_bus))
+    }
+}
+
+impl<I: I2c> AmbientSensor<I> {
+    /// Initialize the sensor from any I2C bus.
+    pub fn from_i2c(i2c: I) -> Self {
         Self {
             device: shtcx::shtc3(i2c),
             power_mode: PowerMode::LowPower,
             read_time: Duration::from_millis(100),
+            sequence: 0,
         }
     }
     /// Set the power mode of the sensor.
@@ -54,16 +133,34 @@ impl AmbientSensor {
         Ok(())
     }
 
-    /// Start reading the sensor at a given period in set Power Mode.
-    ///
-    /// Optionally Notify the BLE client with the latest measurement.
+    /// Check that the sensor is an SHTC3, and that a measurement from it is plausible.
+    pub async fn self_test(&mut self) -> AmbientSelfTest {
+        let mut result = AmbientSelfTest {
+            id: self.device.raw_id_register().ok(),
+            ..Default::default()
+        };
+        if !result.identified() {
+            return result;
+        }
+        match self.read_measurement(self.read_time, self.power_mode).await {
+            Ok(meas) => {
+                result.temperature = Some(meas.temperature.as_degrees_celsius());
+                result.humidity = Some(meas.humidity.as_percent());
+            }
+            Err(error) => log::warn!("Ambient self-test failed to measure: {:?}", error),
+        }
+        result
+    }
+
+    /// Start reading the sensor at a given period in set Power Mode, passing each sample
+    /// to `report`, such as to notify a BLE client or [`Sample::log`] it.
     ///
     /// The period must be greater than the read time, which is
     /// the time it takes to read from the sensor in the current power mode.
     pub async fn start_task(
         &mut self,
         period: Duration,
-        ble: Option<BleConnection<'_, '_>>,
+        mut report: impl AsyncFnMut(Sample),
     ) -> Result<(), AppError> {
         if self.read_time > period {
             return Err(AppError::InvalidReadPeriod(
@@ -74,32 +171,133 @@ impl AmbientSensor {
         info!("Taking measurement every {:?} seconds", period.as_secs());
         loop {
             let now = Instant::now();
-            let meas = self
-                .read_measurement(self.read_time, self.power_mode)
+            let sample = self
+                .read_sample(self.read_time, self.power_mode)
                 .await
                 .map_err(|_| AppError::AmbientI2cRead)?;
-            if let Some((server, conn)) = ble {
-                if let Err(error) = server.notify_ambient(conn, meas).await {
-                    log::error!("Error notifying BLE: {:?}", error);
-                }
-            } else {
-                info!("Temperature: {:?}°C", meas.temperature.as_degrees_celsius());
-                info!("Humidity: {:?}%RH", meas.humidity.as_percent());
-            }
+            report(sample).await;
             Timer::after(period.checked_sub(now.elapsed()).unwrap_or_default()).await;
         }
     }
 }
 
-impl AmbientSensor {
+impl<I: I2c> AmbientSensor<I> {
+    /// Read a measurement, numbering and timestamping it.
+    async fn read_sample(
+        &mut self,
+        read_time: Duration,
+        power_mode: PowerMode,
+    ) -> Result<Sample, shtcx::Error<<I as ErrorType>::Error>> {
+        let measurement = self.read_measurement(read_time, power_mode).await?;
+        let sequence = self.sequence;
+        self.sequence = sequence.wrapping_add(1);
+        Ok(Sample {
+            measurement,
+            at: Instant::now(),
+            sequence,
+        })
+    }
+
     /// Read the temperature and humidity from the sensor
     async fn read_measurement(
         &mut self,
         read_time: Duration,
         power_mode: PowerMode,
-    ) -> Result<Measurement, shtcx::Error<I2cDeviceError<Error>>> {
+    ) -> Result<Measurement, shtcx::Error<<I as ErrorType>::Error>> {
         self.device.start_measurement(power_mode)?;
         Timer::after(read_time).await;
         self.device.get_measurement_result()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockBus;
+    use embassy_futures::block_on;
+
+    fn sensor() -> (AmbientSensor<MockBus>, MockBus) {
+        let bus = MockBus::default();
+        (AmbientSensor::from_i2c(bus.clone()), bus)
+    }
+
+    #[test]
+    fn converts_raw_ticks() {
+        let (mut ambient, bus) = sensor();
+        // T = -45 + 175 * raw / 2^16, RH = 100 * raw / 2^16
+        bus.set_ambient(0x6666, 0x8000);
+        let meas =
+            block_on(ambient.read_measurement(Duration::from_millis(1), PowerMode::LowPower))
+                .unwrap();
+        assert_eq!(meas.temperature.as_millidegrees_celsius() / 100, 249);
+        assert_eq!(meas.humidity.as_millipercent(), 50_000);
+    }
+
+    #[test]
+    fn starts_measurement_in_requested_mode() {
+        let (mut ambient, bus) = sensor();
+        bus.set_ambient(0x6666, 0x8000);
+        block_on(ambient.read_measurement(Duration::from_millis(1), PowerMode::NormalMode))
+            .unwrap();
+        assert_eq!(bus.state().ambient_command, Some(0x7866));
+        block_on(ambient.read_measurement(Duration::from_millis(1), PowerMode::LowPower)).unwrap();
+        assert_eq!(bus.state().ambient_command, Some(0x609C));
+    }
+
+    #[test]
+    fn rejects_corrupted_data() {
+        let (mut ambient, bus) = sensor();
+        bus.set_ambient(0x6666, 0x8000);
+        bus.state().ambient[2] ^= 0xFF;
+        let result =
+            block_on(ambient.read_measurement(Duration::from_millis(1), PowerMode::LowPower));
+        assert!(matches!(result, Err(shtcx::Error::Crc)));
+    }
+
+    #[test]
+    fn numbers_samples() {
+        let (mut ambient, bus) = sensor();
+        bus.set_ambient(0x6666, 0x8000);
+        let read_time = Duration::from_millis(1);
+        let first = block_on(ambient.read_sample(read_time, PowerMode::LowPower)).unwrap();
+        let second = block_on(ambient.read_sample(read_time, PowerMode::LowPower)).unwrap();
+        assert_eq!((first.sequence, second.sequence), (0, 1));
+        assert!(second.at - first.at >= read_time);
+    }
+
+    #[test]
+    fn self_test_checks_id_and_measurement() {
+        let (mut ambient, bus) = sensor();
+        // the fake bus answers the ID read with the temperature bytes, so pick a value that
+        // is both an SHTC3 ID and a plausible temperature
+        bus.set_ambient(0x4807, 0x8000);
+        let result = block_on(ambient.self_test());
+        assert_eq!(result.id, Some(0x4807));
+        assert!(result.passed(), "{:?}", result);
+
+        bus.set_ambient(0x4807, 0);
+        let result = block_on(ambient.self_test());
+        assert!(result.identified() && !result.plausible());
+
+        bus.disconnect();
+        assert_eq!(block_on(ambient.self_test()), AmbientSelfTest::default());
+    }
+
+    #[test]
+    fn task_stops_on_bus_error() {
+        let (mut ambient, bus) = sensor();
+        ambient
+            .set_power_mode(PowerMode::LowPower, Duration::from_millis(1))
+            .unwrap();
+        bus.disconnect();
+        let result = block_on(ambient.start_task(Duration::from_millis(10), async |_| {}));
+        assert!(matches!(result, Err(AppError::AmbientI2cRead)));
+    }
+
+    #[test]
+    fn rejects_period_shorter_than_read_time() {
+        let (mut ambient, _bus) = sensor();
+        let result = block_on(ambient.start_task(Duration::from_millis(10), async |_| {}));
+        assert!(matches!(result, Err(AppError::InvalidReadPeriod(10, 100))));
+    }
+}
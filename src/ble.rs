@@ -1,3 +1,4 @@
+use esp_hal::rng::Trng;
 use esp_wifi::ble::controller::BleConnector;
 pub use gatt::GattServer;
 use log::{info, warn};
@@ -22,38 +23,46 @@ pub type BleController = bt_hci::controller::ExternalController<BleConnector<'st
 
 type BleResources = HostResources<CONN_MAX, L2CAP_CHANNELS_MAX, L2CAP_MTU>;
 
-/// Helper type that combines the long lived server struct with the more ephemeral current connection.
-pub type BleConnection<'values, 'server> = (
-    &'server GattServer<'values>,
-    &'server GattConnection<'values, 'server>,
-);
-
 #[embassy_executor::task]
 async fn ble_task(mut runner: Runner<'static, BleController>) {
     runner.run().await.expect("Error in BLE task");
 }
 
 /// Create an advertiser to use to connect to a BLE Central, and wait for it to connect.
+///
+/// The HID service is only advertised with `hid`, so that hosts only offer to pair the
+/// board as a mouse while the air mouse is in use.
 pub async fn advertise<'server, 'values, C: Controller>(
     name: &'values str,
+    hid: bool,
     peripheral: &mut Peripheral<'values, C>,
     server: &'server GattServer<'values>,
 ) -> Result<GattConnection<'values, 'server>, BleHostError<C::Error>> {
+    let services: &[[u8; 2]] = if hid {
+        &[[0x0f, 0x18], [0x14, 0x18], [0x12, 0x18]]
+    } else {
+        &[[0x0f, 0x18], [0x14, 0x18]]
+    };
     let mut advertiser_data = [0; 31];
-    AdStructure::encode_slice(
+    let len = AdStructure::encode_slice(
         &[
             AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-            AdStructure::ServiceUuids16(&[[0x0f, 0x18]]),
-            AdStructure::CompleteLocalName(name.as_bytes()),
+            AdStructure::ServiceUuids16(services),
         ],
         &mut advertiser_data[..],
     )?;
+    // the name does not fit alongside the services, so it is sent when scanned
+    let mut scan_data = [0; 31];
+    let scan_len = AdStructure::encode_slice(
+        &[AdStructure::CompleteLocalName(name.as_bytes())],
+        &mut scan_data[..],
+    )?;
     let advertiser = peripheral
         .advertise(
             &Default::default(),
             Advertisement::ConnectableScannableUndirected {
-                adv_data: &advertiser_data[..],
-                scan_data: &[],
+                adv_data: &advertiser_data[..len],
+                scan_data: &scan_data[..scan_len],
             },
         )
         .await?;
@@ -65,11 +74,14 @@ pub async fn advertise<'server, 'values, C: Controller>(
 
 impl<'values> GattServer<'values> {
     /// Build the stack for the GATT server and start background tasks required.
+    ///
+    /// The security manager's keys for pairing are seeded from `trng`.
     pub fn start(
         name: &'values str,
         appearance: impl Into<&'static BluetoothUuid16>,
         spawner: embassy_executor::Spawner,
         controller: BleController,
+        trng: &mut Trng<'_>,
     ) -> (&'static Self, Peripheral<'values, BleController>) {
         let address = Address::random([0x42, 0x5A, 0xE3, 0x1E, 0x83, 0xE7]);
         info!("Our address = {:?}", address);
@@ -80,7 +92,11 @@ impl<'values> GattServer<'values> {
         };
         let stack = {
             static STACK: StaticCell<Stack<'_, BleController>> = StaticCell::new();
-            STACK.init(trouble_host::new(controller, resources).set_random_address(address))
+            STACK.init(
+                trouble_host::new(controller, resources)
+                    .set_random_address(address)
+                    .set_random_generator_seed(trng),
+            )
         };
         let host = stack.build();
         let server = {
@@ -103,13 +119,22 @@ impl<'values> GattServer<'values> {
         &self,
         conn: &GattConnection<'values, 'server>,
     ) -> Result<(), trouble_host::Error> {
+        self.show_shock();
+        self.show_alarm();
+        self.show_self_test();
+        self.show_pose();
         let reason = loop {
             match conn.next().await {
                 GattConnectionEvent::Disconnected { reason } => break reason,
                 GattConnectionEvent::Gatt { event: Err(e) } => {
                     warn!("[gatt] error processing event: {:?}", e)
                 }
+                GattConnectionEvent::Bonded { .. } => info!("[gatt] bonded with the host"),
                 GattConnectionEvent::Gatt { event: Ok(event) } => {
+                    let (handle, written) = match &event {
+                        GattEvent::Write(event) => (event.handle(), Some(event.handle())),
+                        GattEvent::Read(event) => (event.handle(), None),
+                    };
                     match &event {
                         GattEvent::Read(event) => {
                             info!("[gatt] Read event occured for handle: {}", event.handle());
@@ -118,10 +143,21 @@ impl<'values> GattServer<'values> {
                             info!("[gatt] Write event occured for handle: {}", event.handle());
                         }
                     }
-                    match event.accept() {
+                    let reply = if self.needs_encryption(handle) && !conn.raw().encrypted() {
+                        warn!("[gatt] handle {} needs an encrypted link", handle);
+                        event.reject(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+                    } else {
+                        event.accept()
+                    };
+                    match reply {
                         Ok(reply) => reply.send().await,
                         Err(e) => warn!("[gatt] error sending response: {:?}", e),
                     }
+                    if written == Some(self.shocks.select.handle) {
+                        self.show_shock();
+                    } else if written == Some(self.alarm.state.handle) {
+                        self.apply_alarm_state();
+                    }
                 }
                 _ => {} // ignore other events
             }
@@ -129,4 +165,16 @@ impl<'values> GattServer<'values> {
         info!("[gatt] disconnected: {:?}", reason);
         Ok(())
     }
+
+    /// Whether a handle belongs to the HID service, which only a paired host may use.
+    fn needs_encryption(&self, handle: u16) -> bool {
+        let hid = &self.hid;
+        [
+            hid.report_map.handle,
+            hid.control_point.handle,
+            hid.report.handle,
+        ]
+        .contains(&handle)
+            || hid.report.cccd_handle == Some(handle)
+    }
 }
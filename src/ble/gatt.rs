@@ -1,5 +1,10 @@
 use trouble_host::prelude::*;
 
+/// Temperature and humidity from the ambient sensor.
+///
+/// Each measurement is preceded by a `sample` header numbering and timestamping it:
+/// a little-endian u32 sequence number, then the time it was taken as a u64 in µs since
+/// boot. A gap in the sequence numbers means samples were lost.
 #[gatt_service(uuid = service::ENVIRONMENTAL_SENSING)]
 pub struct AmbientService {
     #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Temperature °C")]
@@ -8,8 +13,15 @@ pub struct AmbientService {
     #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Humidity %")]
     #[characteristic(uuid = characteristic::HUMIDITY, read, notify)]
     pub humidity: i16,
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Sample number, µs since boot")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c76", read, notify)]
+    pub sample: [u8; 12],
 }
 
+/// Acceleration from the IMU.
+///
+/// Each IMU sample is preceded by a `sample` header laid out as for the ambient
+/// service, which also applies to the gyroscope, inclination and orientation that follow.
 #[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647dd")]
 pub struct AccelerationService {
     #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Accelerometer X m/s²")]
@@ -21,6 +33,9 @@ pub struct AccelerationService {
     #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Accelerometer Z m/s²")]
     #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c62", read, notify)]
     pub z: f32,
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Sample number, µs since boot")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c75", read, notify)]
+    pub sample: [u8; 12],
 }
 
 #[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647dc")]
@@ -34,6 +49,10 @@ pub struct GyroscopeService {
     #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Gyroscope Z °/s")]
     #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c65", read, notify)]
     pub z: f32,
+    /// Die temperature of the IMU, which the gyroscope bias drifts with
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "IMU die temperature °C")]
+    #[characteristic(uuid = characteristic::TEMPERATURE, read, notify)]
+    pub temperature: i16,
 }
 #[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647dd")]
 pub struct InclinationService {
@@ -48,10 +67,215 @@ pub struct InclinationService {
     pub z: f32,
 }
 
-#[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647de")]
+/// Orientation packed into a single notification, all little-endian i16:
+/// quaternion `w, x, y, z` scaled by 2^14, then roll, pitch and yaw in centidegrees.
+///
+/// Linear acceleration is in the earth frame with gravity removed, little-endian i16
+/// `x, y, z` in mg, with Z pointing up.
+#[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647df")]
+pub struct OrientationService {
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Quaternion WXYZ Q14, Roll Pitch Yaw 0.01°")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c69", read, notify)]
+    pub attitude: [u8; 14],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Linear acceleration earth XYZ mg")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c74", read, notify)]
+    pub linear_acceleration: [u8; 6],
+}
+
+/// Events detected by the IMU.
+///
+/// A tap is notified as three bytes: the axis (0 = X, 1 = Y, 2 = Z), the direction
+/// along it (1 or -1 as i8) and the number of taps.
+///
+/// A fall is indicated as three bytes: the kind, then a little-endian u16.
+/// Kind 1 is a free fall with its duration in ms, kind 2 an impact followed by
+/// stillness with its peak in 0.01g.
+///
+/// A change of activity is notified as two bytes: the activity (0 = still, 1 = walking,
+/// 2 = running, 3 = vehicle) and the confidence in percent.
+///
+/// A gesture is notified as two bytes: the gesture (0 = shake, 1 = circle,
+/// 2 = flick left, 3 = flick right) and the confidence in percent.
+///
+/// The side the board rests on is a single byte: 0 until it has settled, then 1 = face up,
+/// 2 = face down, 3 = left, 4 = right, 5 = top and 6 = bottom.
+#[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647e0")]
+pub struct ImuEventService {
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Tap axis, direction, count")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c6a", read, notify)]
+    pub tap: [u8; 3],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Fall kind, duration ms or peak 0.01g")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c6b", read, indicate)]
+    pub fall: [u8; 3],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Activity, confidence %")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c6f", read, notify)]
+    pub activity: [u8; 2],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Gesture, confidence %")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c70", read, notify)]
+    pub gesture: [u8; 2],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Pose: 1 face up, 2 down, 3 left, 4 right, 5 top, 6 bottom")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c7a", read, notify)]
+    pub pose: u8,
+}
+
+/// Vibration of each axis over the last window, all little-endian u16: RMS and
+/// peak-to-peak in mg, dominant frequency in 0.1 Hz, then the energy of each band in 1e-4 g².
+#[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647e1")]
+pub struct VibrationService {
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Vibration X: RMS, p-p, peak Hz, bands")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c6c", read, notify)]
+    pub x: [u8; 14],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Vibration Y: RMS, p-p, peak Hz, bands")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c6d", read, notify)]
+    pub y: [u8; 14],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Vibration Z: RMS, p-p, peak Hz, bands")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c6e", read, notify)]
+    pub z: [u8; 14],
+}
+
+/// The shock log, read back one record at a time.
+///
+/// Writing an index to `select` shows the shock that many places before the most
+/// recent in `record`, and writing 255 clears the log. A record is little-endian:
+/// the shock's number since boot (u32), when it started in ms since boot (u32), its
+/// peak in 0.01g (u16), its duration in 0.1ms (u16), the axis (0 = X, 1 = Y, 2 = Z)
+/// and the direction along it (1 or -1 as i8), or all zero if there is no such shock.
+/// `count` holds the number of shocks in the log (u16) and since boot (u32).
+#[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647e2")]
+pub struct ShockService {
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Shock: number, ms, peak 0.01g, 0.1ms, axis")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c71", read, notify)]
+    pub record: [u8; 14],
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Shock index, 255 clears")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c72", read, write)]
+    pub select: u8,
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Shocks logged, since boot")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c73", read, notify)]
+    pub count: [u8; 6],
+}
+
+/// The anti-tamper alarm.
+///
+/// `state` is 0 when disarmed, 1 when armed and 2 once triggered. Writing 1 arms the
+/// alarm and writing 0 disarms it, silencing it if triggered. When triggered, `alarm`
+/// is indicated with the movement, little-endian: when it was triggered in ms since boot
+/// (u32), the change of acceleration in mg (u16) and the angular rate in °/s (u16).
+#[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647e3")]
+pub struct AlarmService {
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Alarm state, write 1 to arm, 0 to disarm")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c77", read, write, notify)]
+    pub state: u8,
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Alarm: ms, acceleration change mg, rotation °/s")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c78", read, indicate)]
+    pub alarm: [u8; 8],
+}
+
+/// The power-on self-test report.
+///
+/// `report` holds, little-endian: the failed checks (u8, 0 if all passed, bit 0 for the
+/// IMU's `WHO_AM_I`, bit 1 for its self-test and bit 2 for the ambient sensor), the IMU's
+/// `WHO_AM_I` (u8), the accelerometer and gyroscope axes that passed (u8 each, X in bit 0),
+/// the SHTC3 ID register (u16), and the temperature in 0.01°C (i16) and humidity in 0.01%
+/// (u16) it measured. Anything the self-test could not read is 0.
+#[gatt_service(uuid = "911fd452-297b-408f-8f53-ada4e57647e4")]
+pub struct SelfTestService {
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Self-test: failed, IMU ID, axes, ambient ID, 0.01°C, 0.01%")]
+    #[characteristic(uuid = "17bc0927-4de9-4d62-b234-7e1bde9f0c79", read)]
+    pub report: [u8; 10],
+}
+
+/// The standard Running Speed and Cadence service, fed by the pedometer.
+///
+/// The measurement holds the flags, speed in 1/256 m/s, cadence in steps per minute
+/// and stride length in cm. Total distance is left out, as the service would then need
+/// an SC Control Point to reset it.
+#[gatt_service(uuid = service::RUNNING_SPEED_AND_CADENCE)]
+pub struct RunningSpeedCadenceService {
+    #[characteristic(uuid = characteristic::RSC_MEASUREMENT, read, notify)]
+    pub measurement: [u8; 6],
+    /// Stride length measurement, and walking or running status
+    #[characteristic(uuid = characteristic::RSC_FEATURE, read, value = 0b0101)]
+    pub feature: u16,
+}
+
+/// Report descriptor of a mouse with three buttons, sent as report 1: a byte of
+/// button bits, then the X, Y and wheel movement as i8.
+#[rustfmt::skip]
+pub const MOUSE_REPORT_MAP: [u8; 54] = [
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xA1, 0x01, // Collection (Application)
+    0x85, 0x01, //   Report ID (1)
+    0x09, 0x01, //   Usage (Pointer)
+    0xA1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x03, //     Input (Constant), padding the buttons to a byte
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x09, 0x38, //     Usage (Wheel)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7F, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x03, //     Report Count (3)
+    0x81, 0x06, //     Input (Data, Variable, Relative)
+    0xC0,       //   End Collection
+    0xC0,       // End Collection
+];
+
+/// PnP ID of the board: USB-IF assigned vendor ID source, then the little-endian vendor
+/// ID, product ID and version. These are the IDs of the ESP32-C3's own USB serial port.
+pub const PNP_ID: [u8; 7] = [0x02, 0x3A, 0x30, 0x01, 0x10, 0x00, 0x01];
+
+/// The standard Device Information service, which a HID host reads the PnP ID from to
+/// pick a driver.
+#[gatt_service(uuid = service::DEVICE_INFORMATION)]
+pub struct DeviceInformationService {
+    #[characteristic(uuid = characteristic::PNP_ID, read, value = PNP_ID)]
+    pub pnp_id: [u8; 7],
+}
+
+/// The standard Battery service, which HID over GATT requires.
+///
+/// The devkit is powered over USB with no battery to measure, so the level is always full.
+#[gatt_service(uuid = service::BATTERY)]
+pub struct BatteryService {
+    /// Battery level in %
+    #[characteristic(uuid = characteristic::BATTERY_LEVEL, read, notify, value = 100)]
+    pub level: u8,
+}
+
+/// The standard HID service, as a mouse driven by the air mouse.
+///
+/// The board reports in report protocol only, with the mouse report described by
+/// [`MOUSE_REPORT_MAP`] notified as `report`.
+///
+/// The GATT macros cannot set attribute permissions, so the server rejects reads and
+/// writes of this service with insufficient encryption until the link is encrypted,
+/// which prompts the host to pair. Bonds are only kept until the board resets.
+#[gatt_service(uuid = service::HUMAN_INTERFACE_DEVICE)]
 pub struct HidService {
-    #[characteristic(uuid = characteristic::BOOLEAN, read, notify)]
-    pub state: bool,
+    /// HID version 1.11, not localised, normally connectable
+    #[characteristic(uuid = characteristic::HID_INFORMATION, read, value = [0x11, 0x01, 0x00, 0x02])]
+    pub information: [u8; 4],
+    #[characteristic(uuid = characteristic::REPORT_MAP, read, value = MOUSE_REPORT_MAP)]
+    pub report_map: [u8; 54],
+    /// Suspend and exit suspend from the host, which the board has no use for
+    #[characteristic(uuid = characteristic::HID_CONTROL_POINT, write_without_response)]
+    pub control_point: u8,
+    /// Input report 1
+    #[descriptor(uuid = descriptors::REPORT_REFERENCE, read, value = [0x01, 0x01])]
+    #[characteristic(uuid = characteristic::REPORT, read, notify)]
+    pub report: [u8; 4],
 }
 
 #[gatt_server]
@@ -60,5 +284,14 @@ pub struct GattServer {
     pub acceleration: AccelerationService,
     pub gyroscope: GyroscopeService,
     pub inclination: InclinationService,
+    pub orientation: OrientationService,
+    pub imu_events: ImuEventService,
+    pub running: RunningSpeedCadenceService,
+    pub vibration: VibrationService,
+    pub shocks: ShockService,
+    pub alarm: AlarmService,
+    pub self_test: SelfTestService,
+    pub device_information: DeviceInformationService,
+    pub battery: BatteryService,
     pub hid: HidService,
 }
@@ -1,4 +1,19 @@
+use icm42670::accelerometer::vector::F32x3;
+use log::{info, warn};
+
 use super::GattServer;
+use crate::imu::{
+    Activity, ActivityUpdate, AlarmState, Axis, AxisVibration, Direction, Event, EventSubscriber,
+    FallEvent, Gesture, GestureEvent, PointerReceiver, PointerReport, Pose, Shock, Steps, Tamper,
+    Tap, TapCount, alarm_state, arm_alarm, clear_shocks, current_pose, disarm_alarm,
+    recorded_shock, shock_count,
+};
+use crate::selftest::{Report, latest_report};
+
+/// Cadence above which the RSC measurement reports running rather than walking.
+const RUNNING_CADENCE: f32 = 140.0;
+/// Index written to the shock select characteristic to clear the log.
+const CLEAR_SHOCKS: u8 = 255;
 
 impl GattServer<'_> {
     /// Notify the BLE central with the latest IMU data.
@@ -10,25 +25,193 @@ impl GattServer<'_> {
         let accel = measurement.accel;
         let gyro = measurement.gyro;
         let incl = measurement.inclination;
+        let orientation = measurement.orientation;
+        let sample = encode_sample(measurement.at, measurement.sequence);
+        self.acceleration.sample.notify(conn, &sample).await?;
         self.acceleration.x.notify(conn, &accel.x).await?;
         self.acceleration.y.notify(conn, &accel.y).await?;
         self.acceleration.z.notify(conn, &accel.z).await?;
         self.gyroscope.x.notify(conn, &gyro.x).await?;
         self.gyroscope.y.notify(conn, &gyro.y).await?;
         self.gyroscope.z.notify(conn, &gyro.z).await?;
+        // 0x2a6e expects an i16 in centidegrees, as for the ambient temperature
+        let temperature = (measurement.temperature * 100.0) as i16;
+        self.gyroscope
+            .temperature
+            .notify(conn, &temperature)
+            .await?;
         if let Some(incl) = incl {
             self.inclination.x.notify(conn, &incl.x).await?;
             self.inclination.y.notify(conn, &incl.y).await?;
             self.inclination.z.notify(conn, &incl.z).await?;
         }
+        if let Some(orientation) = orientation {
+            let attitude = encode_orientation(&orientation);
+            self.orientation.attitude.notify(conn, &attitude).await?;
+        }
+        if let Some(linear) = measurement.linear_accel {
+            let linear = encode_linear_acceleration(&linear);
+            self.orientation
+                .linear_acceleration
+                .notify(conn, &linear)
+                .await?;
+        }
         Ok(())
     }
+    /// Notify the BLE central of IMU events as they are published, logging any that
+    /// fail to send rather than giving up on the rest.
+    pub async fn notify_imu_events(
+        &self,
+        conn: &trouble_host::gatt::GattConnection<'_, '_>,
+        events: &mut EventSubscriber,
+    ) -> ! {
+        loop {
+            let event = events.next_message_pure().await;
+            if let Err(error) = self.notify_imu_event(conn, event).await {
+                warn!("Failed to notify IMU event {:?}: {:?}", event, error);
+            }
+        }
+    }
+    /// Notify the BLE central of the air mouse's reports as they are queued, logging any
+    /// that fail to send rather than giving up on the rest.
+    pub async fn notify_pointer(
+        &self,
+        conn: &trouble_host::gatt::GattConnection<'_, '_>,
+        reports: &PointerReceiver,
+    ) -> ! {
+        loop {
+            let report = encode_mouse_report(&reports.receive().await);
+            if let Err(error) = self.hid.report.notify(conn, &report).await {
+                warn!("Failed to notify mouse report {:?}: {:?}", report, error);
+            }
+        }
+    }
+    /// Notify the BLE central of a single IMU event.
+    async fn notify_imu_event(
+        &self,
+        conn: &trouble_host::gatt::GattConnection<'_, '_>,
+        event: Event,
+    ) -> Result<(), trouble_host::Error> {
+        match event {
+            Event::Tap(tap) => self.imu_events.tap.notify(conn, &encode_tap(&tap)).await?,
+            Event::Fall(fall) => {
+                let fall = encode_fall(&fall);
+                self.imu_events.fall.indicate(conn, &fall).await?
+            }
+            Event::Steps(steps) => {
+                let measurement = encode_rsc_measurement(&steps);
+                self.running.measurement.notify(conn, &measurement).await?
+            }
+            Event::Vibration(vibration) => {
+                let x = encode_vibration(&vibration.x);
+                let y = encode_vibration(&vibration.y);
+                let z = encode_vibration(&vibration.z);
+                self.vibration.x.notify(conn, &x).await?;
+                self.vibration.y.notify(conn, &y).await?;
+                self.vibration.z.notify(conn, &z).await?;
+            }
+            Event::Activity(update) => {
+                let activity = encode_activity(&update);
+                self.imu_events.activity.notify(conn, &activity).await?
+            }
+            Event::Shock(shock) => {
+                let (record, count) = (encode_shock(0, &shock), encode_shock_count());
+                self.shocks.record.notify(conn, &record).await?;
+                self.shocks.count.notify(conn, &count).await?;
+                self.set(&self.shocks.select, &0)?;
+            }
+            Event::Gesture(gesture) => {
+                let gesture = encode_gesture(&gesture);
+                self.imu_events.gesture.notify(conn, &gesture).await?
+            }
+            // the inclination is already notified with every measurement
+            Event::Level(_) => {}
+            Event::Pose(pose) => {
+                let pose = encode_pose(Some(pose));
+                self.imu_events.pose.notify(conn, &pose).await?
+            }
+            Event::Alarm(state) => {
+                self.alarm
+                    .state
+                    .notify(conn, &encode_alarm_state(&state))
+                    .await?;
+                if let AlarmState::Triggered(tamper) = state {
+                    let alarm = encode_tamper(&tamper);
+                    self.alarm.alarm.indicate(conn, &alarm).await?
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Show the shock chosen by the select characteristic, clearing the log if asked to.
+    pub(super) fn show_shock(&self) {
+        let index = self.get(&self.shocks.select).unwrap_or(0);
+        if index == CLEAR_SHOCKS {
+            clear_shocks();
+            info!("Shock log cleared");
+            if let Err(error) = self.set(&self.shocks.select, &0) {
+                warn!("Failed to reset the shock index: {:?}", error);
+            }
+        }
+        let record = match recorded_shock(index as usize) {
+            Some(shock) => encode_shock(index, &shock),
+            None => [0; 14],
+        };
+        if let Err(error) = self
+            .set(&self.shocks.record, &record)
+            .and_then(|()| self.set(&self.shocks.count, &encode_shock_count()))
+        {
+            warn!("Failed to show shock: {:?}", error);
+        }
+    }
+    /// Arm or disarm the alarm as written to its state characteristic, then show
+    /// the state it is in.
+    pub(super) fn apply_alarm_state(&self) {
+        match self.get(&self.alarm.state) {
+            Ok(0) => disarm_alarm(),
+            Ok(1) => arm_alarm(),
+            _ => {}
+        }
+        self.show_alarm();
+    }
+    /// Show the alarm's state, and what triggered it if it has been.
+    pub(super) fn show_alarm(&self) {
+        let state = alarm_state();
+        let alarm = match state {
+            AlarmState::Triggered(tamper) => encode_tamper(&tamper),
+            _ => [0; 8],
+        };
+        if let Err(error) = self
+            .set(&self.alarm.state, &encode_alarm_state(&state))
+            .and_then(|()| self.set(&self.alarm.alarm, &alarm))
+        {
+            warn!("Failed to show the alarm: {:?}", error);
+        }
+    }
+    /// Show the side the board rests on, as of connecting.
+    pub(super) fn show_pose(&self) {
+        if let Err(error) = self.set(&self.imu_events.pose, &encode_pose(current_pose())) {
+            warn!("Failed to show the pose: {:?}", error);
+        }
+    }
+    /// Show the report of the power-on self-test, once it has run.
+    pub(super) fn show_self_test(&self) {
+        let Some(report) = latest_report() else {
+            return;
+        };
+        if let Err(error) = self.set(&self.self_test.report, &encode_self_test(&report)) {
+            warn!("Failed to show the self-test report: {:?}", error);
+        }
+    }
     /// Notify the BLE central with the latest Temperature and Humidity data.
     pub async fn notify_ambient(
         &self,
         conn: &trouble_host::gatt::GattConnection<'_, '_>,
-        measurement: shtcx::Measurement,
+        sample: crate::ambient::Sample,
     ) -> Result<(), trouble_host::Error> {
+        let measurement = sample.measurement;
+        let header = encode_sample(sample.at, sample.sequence);
+        self.ambient.sample.notify(conn, &header).await?;
         // measurements come in as f32 but Gatt Characteristics 0x2a6e and 0x2a6f
         // expect i16 values with a scale factor of 0.01 (centipercent & centidegrees).
         let humidity = (measurement.humidity.as_millipercent() / 10) as i16;
@@ -37,3 +220,225 @@ impl GattServer<'_> {
         self.ambient.temperature.notify(conn, &temperature).await
     }
 }
+
+/// Pack a sequence number and timestamp into the layout of the sample characteristics.
+fn encode_sample(at: embassy_time::Instant, sequence: u32) -> [u8; 12] {
+    let mut bytes = [0; 12];
+    bytes[..4].copy_from_slice(&sequence.to_le_bytes());
+    bytes[4..].copy_from_slice(&at.as_micros().to_le_bytes());
+    bytes
+}
+
+/// Pack an orientation into the layout of the attitude characteristic.
+fn encode_orientation(orientation: &crate::imu::Orientation) -> [u8; 14] {
+    let crate::imu::Quaternion { w, x, y, z } = orientation.quaternion;
+    let euler = orientation.euler;
+    let values = [
+        (w * 16384.0) as i16,
+        (x * 16384.0) as i16,
+        (y * 16384.0) as i16,
+        (z * 16384.0) as i16,
+        (euler.roll * 100.0) as i16,
+        (euler.pitch * 100.0) as i16,
+        (euler.yaw * 100.0) as i16,
+    ];
+    let mut bytes = [0; 14];
+    for (chunk, value) in bytes.chunks_exact_mut(2).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+/// Pack a linear acceleration into the layout of the linear acceleration characteristic.
+fn encode_linear_acceleration(accel: &F32x3) -> [u8; 6] {
+    let mut bytes = [0; 6];
+    for (chunk, value) in bytes.chunks_exact_mut(2).zip([accel.x, accel.y, accel.z]) {
+        // the float to int cast saturates
+        chunk.copy_from_slice(&((value * 1000.0) as i16).to_le_bytes());
+    }
+    bytes
+}
+
+/// Pack a tap into the layout of the tap characteristic.
+fn encode_tap(tap: &Tap) -> [u8; 3] {
+    let axis = match tap.axis {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+    };
+    let direction: i8 = match tap.direction {
+        Direction::Positive => 1,
+        Direction::Negative => -1,
+    };
+    let count = match tap.count {
+        TapCount::Single => 1,
+        TapCount::Double => 2,
+    };
+    [axis, direction as u8, count]
+}
+
+/// Pack a fall into the layout of the fall characteristic.
+fn encode_fall(fall: &FallEvent) -> [u8; 3] {
+    let (kind, value) = match *fall {
+        FallEvent::FreeFall { duration } => (1, duration.as_millis().min(u16::MAX as u64) as u16),
+        FallEvent::Impact { peak } => (2, (peak * 100.0) as u16),
+    };
+    let [low, high] = value.to_le_bytes();
+    [kind, low, high]
+}
+
+/// Pack steps into an RSC Measurement (0x2A53), with the stride length and walking or
+/// running status present:
+///
+/// | Byte | Content                              |
+/// | ---- | ------------------------------------ |
+/// | 0    | Flags                                |
+/// | 1-2  | Speed in 1/256 m/s                   |
+/// | 3    | Cadence in steps per minute          |
+/// | 4-5  | Stride length in cm                  |
+fn encode_rsc_measurement(steps: &Steps) -> [u8; 6] {
+    let running = steps.cadence > RUNNING_CADENCE;
+    let flags = 0b001 | ((running as u8) << 2);
+    let speed = (steps.speed * 256.0) as u16;
+    let cadence = steps.cadence.min(u8::MAX as f32) as u8;
+    let stride = match steps.count {
+        0 => 0,
+        count => (steps.distance * 100.0 / count as f32) as u16,
+    };
+    let mut bytes = [0; 6];
+    bytes[0] = flags;
+    bytes[1..3].copy_from_slice(&speed.to_le_bytes());
+    bytes[3] = cadence;
+    bytes[4..].copy_from_slice(&stride.to_le_bytes());
+    bytes
+}
+
+/// Pack the vibration of an axis into the layout of the vibration characteristics.
+fn encode_vibration(vibration: &AxisVibration) -> [u8; 14] {
+    let values = [
+        vibration.rms * 1000.0,
+        vibration.peak_to_peak * 1000.0,
+        vibration.dominant_frequency * 10.0,
+    ]
+    .into_iter()
+    .chain(vibration.band_energy.iter().map(|energy| energy * 10_000.0));
+    let mut bytes = [0; 14];
+    for (chunk, value) in bytes.chunks_exact_mut(2).zip(values) {
+        // the float to int cast saturates
+        chunk.copy_from_slice(&(value as u16).to_le_bytes());
+    }
+    bytes
+}
+
+/// Pack an activity change into the layout of the activity characteristic.
+fn encode_activity(update: &ActivityUpdate) -> [u8; 2] {
+    let activity = match update.activity {
+        Activity::Still => 0,
+        Activity::Walking => 1,
+        Activity::Running => 2,
+        Activity::Vehicle => 3,
+    };
+    [activity, (update.confidence * 100.0) as u8]
+}
+
+/// Pack a gesture into the layout of the gesture characteristic.
+fn encode_gesture(event: &GestureEvent) -> [u8; 2] {
+    let gesture = match event.gesture {
+        Gesture::Shake => 0,
+        Gesture::Circle => 1,
+        Gesture::FlickLeft => 2,
+        Gesture::FlickRight => 3,
+    };
+    [gesture, (event.confidence * 100.0) as u8]
+}
+
+/// Pack the shock `index` places before the most recent into the layout of the shock record.
+fn encode_shock(index: u8, shock: &Shock) -> [u8; 14] {
+    let (_, total) = shock_count();
+    let number = total.wrapping_sub(index as u32);
+    let at = shock.at.as_millis() as u32;
+    let peak = (shock.peak * 100.0) as u16;
+    let duration = (shock.duration.as_micros() / 100).min(u16::MAX as u64) as u16;
+    let axis = match shock.axis {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+    };
+    let direction: i8 = match shock.direction {
+        Direction::Positive => 1,
+        Direction::Negative => -1,
+    };
+    let mut bytes = [0; 14];
+    bytes[0..4].copy_from_slice(&number.to_le_bytes());
+    bytes[4..8].copy_from_slice(&at.to_le_bytes());
+    bytes[8..10].copy_from_slice(&peak.to_le_bytes());
+    bytes[10..12].copy_from_slice(&duration.to_le_bytes());
+    bytes[12] = axis;
+    bytes[13] = direction as u8;
+    bytes
+}
+
+/// Pack the number of shocks in the log and since boot into the layout of the count.
+fn encode_shock_count() -> [u8; 6] {
+    let (logged, total) = shock_count();
+    let mut bytes = [0; 6];
+    bytes[0..2].copy_from_slice(&(logged as u16).to_le_bytes());
+    bytes[2..6].copy_from_slice(&total.to_le_bytes());
+    bytes
+}
+
+/// The value of the alarm state characteristic.
+fn encode_alarm_state(state: &AlarmState) -> u8 {
+    match state {
+        AlarmState::Disarmed => 0,
+        AlarmState::Armed => 1,
+        AlarmState::Triggered(_) => 2,
+    }
+}
+
+/// Pack the movement that triggered the alarm into the layout of the alarm characteristic.
+fn encode_tamper(tamper: &Tamper) -> [u8; 8] {
+    let at = tamper.at.as_millis() as u32;
+    // the float to int casts saturate
+    let change = (tamper.accel_change * 1000.0) as u16;
+    let rotation = tamper.rotation as u16;
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&at.to_le_bytes());
+    bytes[4..6].copy_from_slice(&change.to_le_bytes());
+    bytes[6..].copy_from_slice(&rotation.to_le_bytes());
+    bytes
+}
+
+/// The value of the pose characteristic.
+fn encode_pose(pose: Option<Pose>) -> u8 {
+    match pose {
+        None => 0,
+        Some(Pose::FaceUp) => 1,
+        Some(Pose::FaceDown) => 2,
+        Some(Pose::Left) => 3,
+        Some(Pose::Right) => 4,
+        Some(Pose::Top) => 5,
+        Some(Pose::Bottom) => 6,
+    }
+}
+
+/// Pack a self-test report into the layout of the self-test characteristic.
+fn encode_self_test(report: &Report) -> [u8; 10] {
+    let (imu, ambient) = (report.imu, report.ambient);
+    let temperature = ambient.temperature.map_or(0, |t| (t * 100.0) as i16);
+    let humidity = ambient.humidity.map_or(0, |h| (h * 100.0) as u16);
+    let mut bytes = [0; 10];
+    bytes[0] = report.failed();
+    bytes[1] = imu.device_id.unwrap_or(0);
+    bytes[2] = imu.accel_axes;
+    bytes[3] = imu.gyro_axes;
+    bytes[4..6].copy_from_slice(&ambient.id.unwrap_or(0).to_le_bytes());
+    bytes[6..8].copy_from_slice(&temperature.to_le_bytes());
+    bytes[8..].copy_from_slice(&humidity.to_le_bytes());
+    bytes
+}
+
+/// Pack an air mouse report into the layout of the mouse input report, with no wheel.
+fn encode_mouse_report(report: &PointerReport) -> [u8; 4] {
+    [report.buttons, report.dx as u8, report.dy as u8, 0]
+}
@@ -27,20 +27,33 @@
 //! | WS2812 LED  | GPIO2 |
 //! | LED         | GPIO7 |
 //! | Button/Boot | GPIO9 |
+//!
+//! ### Flash
+//!
+//! The IMU calibration is kept in the first sector of the `nvs` partition of the
+//! default partition table, which this firmware does not otherwise use.
+//! The gesture model follows in the next sectors, and can be replaced on its own with
+//! `espflash write-bin 0xa000 gesture.bin`.
 
 use core::cell::RefCell;
 use embassy_embedded_hal::shared_bus;
-use embassy_sync::blocking_mutex::{NoopMutex, raw::NoopRawMutex};
+use embassy_sync::blocking_mutex::{
+    Mutex, NoopMutex,
+    raw::{CriticalSectionRawMutex, NoopRawMutex},
+};
 use esp_hal::{
     clock::CpuClock,
     gpio::{Input, InputConfig, Pull},
     i2c::master::{Config, I2c},
+    interrupt::software::SoftwareInterruptControl,
     rmt::Rmt,
-    rng::Rng,
+    rng::{Rng, Trng},
     time::Rate,
     timer::systimer::SystemTimer,
+    usb_serial_jtag::UsbSerialJtag,
 };
 use esp_hal_smartled::{SmartLedsAdapterAsync, buffer_size_async};
+use esp_storage::FlashStorage;
 
 use esp_wifi::EspWifiController;
 use log::info;
@@ -52,23 +65,72 @@ pub type I2cType<'a> = I2c<'a, esp_hal::Async>;
 pub type I2cBus<'a> = NoopMutex<RefCell<I2cType<'a>>>;
 pub type I2cBusDevice<'a> = shared_bus::blocking::i2c::I2cDevice<'a, NoopRawMutex, I2cType<'a>>;
 
-/// Board-specific peripherals.
-pub struct Board {
+// the shared bus is blocking and locked in a critical section, so that devices on it can
+// be sent to tasks on an interrupt executor, which async drivers cannot
+pub type SharedI2cType<'a> = I2c<'a, esp_hal::Blocking>;
+pub type SharedI2cBus<'a> = Mutex<CriticalSectionRawMutex, RefCell<SharedI2cType<'a>>>;
+pub type SharedI2cBusDevice<'a> =
+    shared_bus::blocking::i2c::I2cDevice<'a, CriticalSectionRawMutex, SharedI2cType<'a>>;
+
+/// Flash offset of the stored IMU calibration.
+pub const CALIBRATION_OFFSET: u32 = 0x9000;
+/// Flash offset of the gesture model blob.
+pub const GESTURE_MODEL_OFFSET: u32 = 0xA000;
+
+/// Board-specific peripherals, with the I2C bus as `B`.
+pub struct Board<B: 'static = I2cBus<'static>> {
     /// Onboard RGB LED
     pub led: Led,
     /// Random number generator
     pub rng: Rng,
+    /// True random number generator, holding on to the ADC for its entropy
+    pub trng: Trng<'static>,
     /// I2c Bus, shared between peripherals
-    pub i2c_bus: &'static I2cBus<'static>,
+    pub i2c_bus: &'static B,
     /// BLE controller
     pub ble_controller: BleController,
     /// Boot button
     pub button: Input<'static>,
+    /// Onboard SPI flash
+    pub flash: FlashStorage,
+    /// Software interrupts, for running an interrupt executor at a raised priority
+    pub software_interrupts: SoftwareInterruptControl,
+    /// USB serial port, shared with the logs
+    pub usb_serial: UsbSerialJtag<'static, esp_hal::Blocking>,
 }
 
 impl Board {
     /// Initialize the board.
     pub fn init() -> Self {
+        Self::init_with(Config::default(), |i2c| {
+            static BUS: StaticCell<I2cBus<'static>> = StaticCell::new();
+            BUS.init(NoopMutex::new(RefCell::new(i2c.into_async())))
+        })
+    }
+}
+
+impl Board<SharedI2cBus<'static>> {
+    /// Initialize the board with its I2C bus shared across priorities, so that a device on
+    /// it can be driven from an interrupt executor.
+    ///
+    /// Interrupts are disabled for the whole of every transaction, including while the BLE
+    /// controller is running, so the bus is only shared this way when it has to be.
+    pub fn init_shared() -> Self {
+        // fast mode keeps the critical section of each transaction short
+        let config = Config::default().with_frequency(Rate::from_khz(400));
+        Self::init_with(config, |i2c| {
+            static BUS: StaticCell<SharedI2cBus<'static>> = StaticCell::new();
+            BUS.init(Mutex::new(RefCell::new(i2c)))
+        })
+    }
+}
+
+impl<B> Board<B> {
+    /// Initialize the board, wrapping the I2C bus for sharing with `share`.
+    fn init_with(
+        i2c_config: Config,
+        share: impl FnOnce(SharedI2cType<'static>) -> &'static B,
+    ) -> Self {
         esp_println::logger::init_logger_from_env();
 
         let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
@@ -87,17 +149,16 @@ impl Board {
         info!("Initialized WS2812 LED");
 
         let i2c_bus = {
-            static BUS: StaticCell<I2cBus<'static>> = StaticCell::new();
-            let i2c = I2c::new(p.I2C0, Config::default())
+            let i2c = I2c::new(p.I2C0, i2c_config)
                 .expect("Failed to initialize I2C0")
                 .with_scl(p.GPIO8)
-                .with_sda(p.GPIO10)
-                .into_async();
-            BUS.init(NoopMutex::new(RefCell::new(i2c)))
+                .with_sda(p.GPIO10);
+            share(i2c)
         };
         info!("Initialized I2C bus");
 
-        let rng = Rng::new(p.RNG);
+        let trng = Trng::new(p.RNG, p.ADC1);
+        let rng = trng.rng;
 
         let timer0 = SystemTimer::new(p.SYSTIMER);
         esp_hal_embassy::init(timer0.alarm0);
@@ -119,9 +180,13 @@ impl Board {
         Self {
             led,
             rng,
+            trng,
             i2c_bus,
             ble_controller: controller,
             button: Input::new(p.GPIO9, pull_up),
+            flash: FlashStorage::new(),
+            software_interrupts: SoftwareInterruptControl::new(p.SW_INTERRUPT),
+            usb_serial: UsbSerialJtag::new(p.USB_DEVICE),
         }
     }
 }
@@ -1 +1,64 @@
 //! Buttons are controlled via GPIO pins.
+//!
+//! A tap on the board, picked up by the IMU, can be used as an input alongside the
+//! boot button, see [`wait_for_press`]. Holding the button down can be told apart from a
+//! press with [`wait_for_release`].
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::gpio::Input;
+
+use crate::imu::{Event, EventSubscriber, Tap};
+
+/// Time for the button contacts to settle.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// An input from the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Press {
+    /// The button was pressed and released
+    Button,
+    /// The board was tapped
+    Tap(Tap),
+}
+
+/// Wait for the button to be pressed and released.
+pub async fn wait_for_button(button: &mut Input<'static>) {
+    button.wait_for_low().await;
+    Timer::after(DEBOUNCE).await;
+    button.wait_for_high().await;
+    Timer::after(DEBOUNCE).await;
+}
+
+/// Wait for the button to be pressed and released, returning how long it was held down.
+pub async fn wait_for_release(button: &mut Input<'static>) -> Duration {
+    button.wait_for_low().await;
+    let pressed = Instant::now();
+    Timer::after(DEBOUNCE).await;
+    button.wait_for_high().await;
+    let held = pressed.elapsed();
+    Timer::after(DEBOUNCE).await;
+    held
+}
+
+/// Wait for the button to be pressed and released, or for a tap on the board if
+/// subscribed to the IMU's events.
+pub async fn wait_for_press(
+    button: &mut Input<'static>,
+    events: Option<&mut EventSubscriber>,
+) -> Press {
+    let tap = async {
+        match events {
+            Some(events) => loop {
+                if let Event::Tap(tap) = events.next_message_pure().await {
+                    break tap;
+                }
+            },
+            None => core::future::pending().await,
+        }
+    };
+    match select(wait_for_button(button), tap).await {
+        Either::First(()) => Press::Button,
+        Either::Second(tap) => Press::Tap(tap),
+    }
+}
@@ -1,186 +1,950 @@
 //! A simple struct to read from the 6-axis IMU.
 //!
 //! This can be used to read from the onboard IMU sensor.
-//! at a set rate or on demand.
+//! at a set rate or on demand. Events are picked out of the measurements by [`Detectors`].
 
-use embassy_embedded_hal::shared_bus::I2cDeviceError;
+#[cfg(feature = "hardware")]
 use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
 use embassy_time::{Duration, Instant, Timer};
-use esp_hal::i2c::master::Error as I2cError;
+use embedded_hal::i2c::{ErrorType, I2c};
 use gimbal::Gimbal;
-use icm42670::accelerometer::Error as AccelError;
 use icm42670::accelerometer::vector::F32x3;
-use icm42670::{Address, prelude::*};
-use icm42670::{Icm42670, PowerMode};
-use log::info;
+use log::{info, warn};
+use sampler::JitterMeter;
 
-use crate::ble::BleConnection;
+use crate::AppError;
+#[cfg(feature = "hardware")]
 use crate::bsp::{I2cBus, I2cBusDevice};
+use registers::{self as reg, Registers};
 
-pub struct ImuSensor {
+pub use activity::{
+    Activity, ActivityClassifier, ActivityConfig, ActivityUpdate, Features, classify,
+};
+pub use allan::{
+    AllanAxis, AllanDeviation, AxisNoise, MOTION_THRESHOLD as NOISE_MOTION_THRESHOLD, Noise,
+    OCTAVES as ALLAN_OCTAVES,
+};
+pub use calibration::{Calibration, Face, SixPosition};
+pub use config::{AccelRange, Bandwidth, GyroRange, ImuConfig, Odr, WakeOnMotion};
+pub use detectors::Detectors;
+pub use events::{Event, EventSubscriber, subscribe};
+pub use fall::{FallConfig, FallDetector, FallEvent};
+pub use gesture::{
+    Gesture, GestureConfig, GestureEvent, GestureModel, GestureRecognizer, MAX_MODEL_LEN,
+};
+pub use gimbal::{AhrsConfig, Euler, Orientation, Quaternion};
+pub use level::{BubbleLevel, Level, LevelConfig, tare_level};
+pub use pedometer::{Pedometer, PedometerConfig, Steps};
+pub use pointer::{
+    AirMouse, LEFT_BUTTON, PointerConfig, PointerReceiver, PointerReport,
+    REPORT_QUEUE_LEN as POINTER_QUEUE_LEN, click, pointer_reports, set_buttons,
+};
+pub use pose::{Pose, PoseConfig, PoseDetector, current_pose};
+pub use registers::{Error, PowerMode};
+pub use sampler::{
+    Jitter, QUEUE_LEN as SAMPLE_QUEUE_LEN, REPORT_INTERVAL as SAMPLING_REPORT_INTERVAL,
+    SampleReceiver, samples, sampling_jitter,
+};
+pub use selftest::{ALL_AXES as SELF_TEST_AXES, ImuSelfTest, self_test};
+pub use shock::{
+    LOG_LEN as SHOCK_LOG_LEN, Shock, ShockConfig, ShockDetector, ShockLog, clear_shocks,
+    recorded_shock, shock_count,
+};
+pub use spectrum::{AxisVibration, BANDS, SpectrumAnalyzer, SpectrumConfig, Vibration};
+pub use tamper::{
+    AlarmState, Tamper, TamperConfig, TamperDetector, alarm_state, arm_alarm, disarm_alarm,
+    trip_alarm,
+};
+pub use tap::{Axis, Direction, Tap, TapConfig, TapCount, TapDetector};
+
+mod activity;
+mod allan;
+mod calibration;
+pub mod capture;
+mod config;
+mod detectors;
+mod events;
+mod fall;
+pub mod fifo;
+mod gesture;
+mod gimbal;
+mod level;
+mod math;
+mod pedometer;
+mod pointer;
+mod pose;
+pub mod registers;
+mod sampler;
+mod selftest;
+mod shock;
+mod spectrum;
+mod tamper;
+mod tap;
+
+/// Error returned by the ICM42670 for a given I2C bus.
+pub type DeviceError<I> = Error<<I as ErrorType>::Error>;
+
+/// Number of packets read from the FIFO per I2C transaction.
+const FIFO_CHUNK: usize = 8;
+/// How far the gyroscope may wander during a still period, in °/s.
+const STILL_GYRO_RANGE: f32 = 2.0;
+/// How far the accelerometer may wander during a still period, in g.
+const STILL_ACCEL_RANGE: f32 = 0.05;
+/// How often the FIFO is drained while characterising the noise.
+const NOISE_DRAIN: Duration = Duration::from_millis(50);
+/// How often the Allan deviation so far is logged while characterising the noise.
+const NOISE_LOG_INTERVAL: Duration = Duration::from_secs(60);
+/// Time for the accelerometer to settle before wake on motion is enabled.
+const WOM_SETTLE: Duration = Duration::from_millis(50);
+/// How often the motion status is checked while waiting for motion.
+const WOM_POLL: Duration = Duration::from_millis(100);
+/// The wake-on-motion status bits of every axis.
+const WOM_INT: u8 = reg::WOM_X_INT | reg::WOM_Y_INT | reg::WOM_Z_INT;
+
+/// The sensor on the board's shared I2C bus.
+#[cfg(feature = "hardware")]
+pub type BoardImu = ImuSensor<I2cBusDevice<'static>>;
+
+pub struct ImuSensor<I> {
     /// The Onboard gyroscope and accelerometer.
-    device: Icm42670<I2cBusDevice<'static>>,
+    device: Registers<I>,
     /// The gimbal to calculate inclination
     gimbal: Option<Gimbal>,
     /// The power mode of the sensor
     power_mode: PowerMode,
+    /// The measurement range, rate and filtering
+    config: ImuConfig,
+    /// FIFO state, if burst sampling is enabled
+    fifo: Option<Fifo>,
+    /// Corrections applied to every measurement
+    calibration: Calibration,
+    /// Sequence number of the next measurement
+    sequence: u32,
+    /// Tuning of the gimbal's fusion filter
+    ahrs: AhrsConfig,
 }
 
+/// State of the FIFO while burst sampling is enabled.
+#[derive(Default)]
+struct Fifo {
+    /// Host time the sensor clock started from, when the first sample was taken
+    epoch: Option<Instant>,
+    /// Sensor clock, unwrapping the FIFO timestamps
+    clock: fifo::Clock,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Measurement {
+    /// When the sample was taken, from the sensor's own clock in FIFO mode
+    pub at: Instant,
+    /// Count of samples since the sensor was created, wrapping. In FIFO mode only the
+    /// latest sample of each drain is reported, so reported numbers skip the rest.
+    pub sequence: u32,
     /// 3 axis acceleration
     pub accel: F32x3,
     /// 3 axis gyroscope
     pub gyro: F32x3,
     /// 3 axis inclination
     pub inclination: Option<F32x3>,
+    /// Quaternion and Euler angles from the gimbal
+    pub orientation: Option<Orientation>,
+    /// Acceleration in the earth frame with gravity removed, in g, from the gimbal
+    pub linear_accel: Option<F32x3>,
+    /// Die temperature of the sensor in °C
+    pub temperature: f32,
 }
 
-impl ImuSensor {
-    /// Create a new actor with a spawner and a configuration.
-    pub fn new(i2c_bus: &'static I2cBus<'static>) -> Self {
-        let i2c = I2cDevice::new(i2c_bus);
-        let mut device =
-            Icm42670::new(i2c, Address::Primary).expect("Failed to initialize ICM42670");
-        let power_mode = PowerMode::Standby;
-        device
-            .set_power_mode(power_mode)
-            .expect("Failed to set power mode");
-        info!("Sample rate is: {:?}", device.sample_rate());
-        device.soft_reset().expect("Failed to reset device");
-        Self {
-            gimbal: None,
-            device,
-            power_mode,
-        }
+impl Measurement {
+    /// Log the measurement, for when nothing else is listening.
+    pub fn log(&self) {
+        let euler = self.orientation.map(|o| o.euler);
+        info!(
+            "G: ({:.3}, {:.3}, {:.3}) | A: ({:.3}, {:.3}, {:.3}) | I: ({:.3}, {:.3}, {:.3}) | RPY: ({:.1}, {:.1}, {:.1})",
+            self.gyro.x,
+            self.gyro.y,
+            self.gyro.z,
+            self.accel.x,
+            self.accel.y,
+            self.accel.z,
+            self.inclination.map(|incl| incl.x).unwrap_or_default(),
+            self.inclination.map(|incl| incl.y).unwrap_or_default(),
+            self.inclination.map(|incl| incl.z).unwrap_or_default(),
+            euler.map(|e| e.roll).unwrap_or_default(),
+            euler.map(|e| e.pitch).unwrap_or_default(),
+            euler.map(|e| e.yaw).unwrap_or_default()
+        );
+    }
+}
+
+#[cfg(feature = "hardware")]
+impl BoardImu {
+    /// Create a new sensor on the board's shared I2C bus.
+    ///
+    /// Fails if the ICM42670 does not answer, which [`self_test`] reports in more detail.
+    pub fn new(i2c_bus: &'static I2cBus<'static>) -> Result<Self, AppError> {
+        Self::from_i2c(I2cDevice::new(i2c_bus))
+    }
+}
+
+impl<I: I2c> ImuSensor<I> {
+    /// Create a new sensor from any I2C bus, such as a mock bus in host-side tests.
+    pub fn from_i2c(i2c: I) -> Result<Self, AppError> {
+        Self::init(Registers::new(i2c)).map_err(|error| match error {
+            Error::BadChip(id) => AppError::ImuBadChip(id),
+            Error::Bus(_) => AppError::ImuI2c,
+        })
     }
     /// Set the power mode of the sensor.
-    pub fn set_power_mode(
-        &mut self,
-        power_mode: PowerMode,
-    ) -> Result<(), icm42670::Error<I2cDeviceError<I2cError>>> {
+    pub fn set_power_mode(&mut self, power_mode: PowerMode) -> Result<(), DeviceError<I>> {
         self.power_mode = power_mode;
-        self.device.set_power_mode(power_mode)
+        self.device.update(reg::PWR_MGMT0, 0x0F, power_mode.bits())
     }
-    /// Start reading the sensor at a given period.
+    /// The current measurement configuration.
+    pub fn config(&self) -> ImuConfig {
+        self.config
+    }
+    /// Set the measurement range, output data rate and filter bandwidth.
+    ///
+    /// The configuration is checked against the current power mode before it is applied.
+    pub fn configure(&mut self, config: ImuConfig) -> Result<(), AppError> {
+        config.validate(self.power_mode)?;
+        Self::write_config(&mut self.device, &config).map_err(|_| AppError::ImuI2c)?;
+        info!("IMU configured: {:?}", config);
+        self.config = config;
+        Ok(())
+    }
+    /// The corrections applied to every measurement.
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+    /// Replace the corrections applied to every measurement, e.g. with ones loaded from flash.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+    /// Measure the gyroscope bias of the [`Calibration`] while the board is held still.
     ///
-    /// Optionally Notify the BLE client with the latest measurement.
+    /// Returns [`AppError::ImuNotStill`] if the board moved during the capture,
+    /// in which case the previous bias is kept.
+    ///
+    /// The die temperature during the capture is kept with the bias, so that it can be
+    /// corrected as the temperature changes.
+    pub async fn calibrate_gyro(&mut self, duration: Duration) -> Result<[f32; 3], AppError> {
+        let (_, gyro, temperature) = self.still_period(duration).await?;
+        let bias = gyro.mean(STILL_GYRO_RANGE)?;
+        info!("Gyroscope bias: {:?} °/s at {} °C", bias, temperature);
+        self.calibration.gyro_bias = bias;
+        self.calibration.gyro_bias_temperature = temperature;
+        Ok(bias)
+    }
+    /// Capture the mean acceleration with one face of the board pointing up.
+    ///
+    /// Once all six faces are captured the accelerometer correction of the [`Calibration`]
+    /// is updated, [`SixPosition::missing`] lists the faces still to go.
+    pub async fn capture_face(
+        &mut self,
+        six: &mut SixPosition,
+        duration: Duration,
+    ) -> Result<Face, AppError> {
+        let (accel, _, _) = self.still_period(duration).await?;
+        let face = six.add(accel.mean(STILL_ACCEL_RANGE)?);
+        info!("Captured face {:?}", face);
+        if let Some((offset, scale)) = six.solve() {
+            info!("Accelerometer offset: {:?} g, scale: {:?}", offset, scale);
+            self.calibration.accel_offset = offset;
+            self.calibration.accel_scale = scale;
+        }
+        Ok(face)
+    }
+    /// Characterise the noise of the accelerometer and gyroscope by their Allan deviation,
+    /// while the board is left still for `duration`.
+    ///
+    /// The random walk and bias instability help tune the gimbal's [`AhrsConfig`].
+    /// Runs of an hour or more are needed for the bias instability. Every sample at the
+    /// output data rate is used, so the FIFO is enabled if it is not already, and left
+    /// enabled. The curves so far are logged every minute, and the run stops with
+    /// [`AppError::ImuNotStill`] if the board is moved.
+    pub async fn characterise_noise(
+        &mut self,
+        duration: Duration,
+    ) -> Result<AllanDeviation, AppError> {
+        if self.fifo.is_none() {
+            self.enable_fifo(20).map_err(|_| AppError::ImuI2c)?;
+        }
+        let mut allan = AllanDeviation::new(self.config.odr.period());
+        info!(
+            "Characterising IMU noise for {} s, keep the board still",
+            duration.as_secs()
+        );
+        let start = Instant::now();
+        let mut logged = start;
+        while start.elapsed() < duration {
+            Timer::after(NOISE_DRAIN).await;
+            self.read_fifo(|meas| allan.add(meas.accel, meas.gyro))
+                .await
+                .map_err(|_| AppError::ImuI2c)?;
+            if allan.moved() {
+                return Err(AppError::ImuNotStill);
+            }
+            if logged.elapsed() >= NOISE_LOG_INTERVAL {
+                allan.log();
+                logged = Instant::now();
+            }
+        }
+        Ok(allan)
+    }
+    /// The tuning of the gimbal's fusion filter.
+    pub fn ahrs_config(&self) -> AhrsConfig {
+        self.ahrs
+    }
+    /// Tune the gimbal's fusion filter, restarting it if it is running.
+    pub fn set_ahrs_config(&mut self, config: AhrsConfig) {
+        info!("AHRS configured: {:?}", config);
+        if let Some(gimbal) = self.gimbal.as_mut() {
+            gimbal.set_config(&config);
+        }
+        self.ahrs = config;
+    }
+    /// Idle with the accelerometer in low power mode until the board is moved, so the
+    /// application can stop other work until someone picks it up.
+    ///
+    /// The sensor's interrupt pins are not used, so the motion status is polled over I2C.
+    /// Once motion is detected the previous power mode and configuration are restored.
+    pub async fn wait_for_motion(&mut self, wake: WakeOnMotion) -> Result<(), AppError> {
+        let (power_mode, config) = (self.power_mode, self.config);
+        let low_power = ImuConfig {
+            odr: wake.odr,
+            ..config
+        };
+        low_power.validate(PowerMode::AccelLowPower)?;
+        self.start_wake_on_motion(low_power, wake)
+            .map_err(|_| AppError::ImuI2c)?;
+        // let the accelerometer settle, so that its first samples do not count as motion
+        Timer::after(WOM_SETTLE).await;
+        let enable = reg::WOM_MODE | reg::WOM_EN;
+        self.device
+            .update(reg::WOM_CONFIG, enable, enable)
+            .and_then(|_| self.device.read(reg::INT_STATUS2))
+            .map_err(|_| AppError::ImuI2c)?;
+        info!("Waiting for motion above {}g", wake.threshold);
+        loop {
+            Timer::after(WOM_POLL).await;
+            let status = self
+                .device
+                .read(reg::INT_STATUS2)
+                .map_err(|_| AppError::ImuI2c)?;
+            if status & WOM_INT != 0 {
+                break;
+            }
+        }
+        info!("Motion detected");
+        self.device
+            .update(reg::WOM_CONFIG, reg::WOM_EN, 0)
+            .and_then(|_| Self::write_config(&mut self.device, &config))
+            .and_then(|_| self.set_power_mode(power_mode))
+            .map_err(|_| AppError::ImuI2c)?;
+        self.config = config;
+        Ok(())
+    }
+    /// Switch to FIFO burst sampling.
+    ///
+    /// By default each measurement is polled from the data registers, so the sample timing
+    /// follows the executor. In FIFO mode samples are instead drained in bursts with
+    /// [`read_fifo`](Self::read_fifo), each tagged with the sensor's own timestamp.
+    ///
+    /// The FIFO is filled at the sensor's output data rate, and raises its threshold
+    /// status once `watermark` packets are buffered. The watermark is clamped to what
+    /// fits in the FIFO.
+    pub fn enable_fifo(&mut self, watermark: usize) -> Result<(), DeviceError<I>> {
+        let max_packets = fifo::FIFO_SIZE / fifo::PACKET_LEN;
+        let watermark = (watermark.clamp(1, max_packets) * fifo::PACKET_LEN) as u16;
+        let [wm_high, wm_low] = watermark.to_be_bytes();
+        let device = &mut self.device;
+        device.write(reg::FIFO_CONFIG1, reg::FIFO_BYPASS)?;
+        device.write_mreg1(reg::TMST_CONFIG1, reg::TMST_EN | reg::TMST_RES)?;
+        device.write_mreg1(
+            reg::FIFO_CONFIG5,
+            reg::FIFO_ACCEL_EN | reg::FIFO_GYRO_EN | reg::FIFO_TMST_FSYNC_EN,
+        )?;
+        device.write(reg::FIFO_CONFIG2, wm_low)?;
+        device.write(reg::FIFO_CONFIG3, wm_high & 0x0F)?;
+        device.write(reg::FIFO_CONFIG1, 0x00)?;
+        device.write(reg::SIGNAL_PATH_RESET, reg::FIFO_FLUSH)?;
+        self.fifo = Some(Fifo::default());
+        info!("FIFO enabled with a watermark of {} bytes", watermark);
+        Ok(())
+    }
+    /// Return to polling the data registers.
+    pub fn disable_fifo(&mut self) -> Result<(), DeviceError<I>> {
+        self.fifo = None;
+        self.device.write(reg::FIFO_CONFIG1, reg::FIFO_BYPASS)
+    }
+    /// Start reading the sensor at a given period, passing the latest measurement to
+    /// `report` each time, such as to notify a BLE client or [`Measurement::log`] it.
+    ///
+    /// Every sample is also passed to `on_sample` as it is read, such as to feed the
+    /// application's [`Detectors`].
+    ///
+    /// In FIFO mode the period is how often the FIFO is drained, rather than the sample rate.
+    /// When polling, the period must be no shorter than the output data rate allows.
     pub async fn start_task(
         &mut self,
         period: Duration,
-        ble: Option<BleConnection<'_, '_>>,
-    ) -> Result<(), AccelError<icm42670::Error<I2cDeviceError<I2cError>>>> {
-        self.read_inner(period, ble).await
+        on_sample: impl FnMut(&Measurement),
+        report: impl AsyncFnMut(Measurement),
+    ) -> Result<(), AppError> {
+        self.validate_period(period)?;
+        self.read_inner(period, on_sample, report)
+            .await
+            .map_err(|_| AppError::ImuI2c)
+    }
+
+    /// Sample the sensor at a given period, sending every sample to [`samples`] and
+    /// summarising how late each wake-up was as a [`Jitter`] every [`SAMPLING_REPORT_INTERVAL`].
+    /// The samples can be framed with [`capture::encode_frame`] to record labelled training
+    /// data on a host.
+    ///
+    /// This is meant to run in a task of its own, which can be spawned on an interrupt
+    /// executor at a raised priority so that other tasks do not delay it. The board's I2C
+    /// bus must then be shared across priorities, with [`Board::init_shared`].
+    ///
+    /// [`Board::init_shared`]: crate::bsp::Board::init_shared
+    ///
+    /// In FIFO mode the period is how often the FIFO is drained, and every sample is sent.
+    pub async fn start_sampling(&mut self, period: Duration) -> Result<(), AppError> {
+        self.validate_period(period)?;
+        self.sample_inner(period)
+            .await
+            .map_err(|_| AppError::ImuI2c)
+    }
+
+    /// Check the configuration can be read at a given period.
+    fn validate_period(&self, period: Duration) -> Result<(), AppError> {
+        self.config.validate(self.power_mode)?;
+        let read_time = self.config.odr.period();
+        if self.fifo.is_none() && period < read_time {
+            return Err(AppError::InvalidReadPeriod(
+                period.as_millis(),
+                read_time.as_millis(),
+            ));
+        }
+        Ok(())
     }
 
     /// Read the accelerometer and gyroscope from the sensor.
     ///
     /// Calculate the inclination if a gymbal has been set up.
-    pub async fn read_measurement(
+    pub async fn read_measurement(&mut self) -> Result<Measurement, DeviceError<I>> {
+        let (accel, gyro, temperature) = self.read_sample()?;
+        Ok(self.measurement(accel, gyro, temperature, Instant::now()))
+    }
+
+    /// Drain every complete packet from the FIFO, oldest first.
+    ///
+    /// Each sample is passed through the gimbal using the sensor's timestamps,
+    /// and handed to `on_sample`. Returns the number of samples read.
+    pub async fn read_fifo(
+        &mut self,
+        mut on_sample: impl FnMut(Measurement),
+    ) -> Result<usize, DeviceError<I>> {
+        let drained_at = Instant::now();
+        let mut count = [0; 2];
+        self.device.read_into(reg::FIFO_COUNTH, &mut count)?;
+        let available = u16::from_be_bytes(count) as usize;
+        let mut remaining = available - available % fifo::PACKET_LEN;
+        // packets left to read after the current one, which was taken that many
+        // sample periods before the newest
+        let mut newer = (remaining / fifo::PACKET_LEN) as u32;
+        let odr_period = self.config.odr.period();
+        let mut samples = 0;
+        let mut buf = [0; FIFO_CHUNK * fifo::PACKET_LEN];
+        while remaining > 0 {
+            let chunk = &mut buf[..remaining.min(FIFO_CHUNK * fifo::PACKET_LEN)];
+            self.device.read_into(reg::FIFO_DATA, chunk)?;
+            remaining -= chunk.len();
+            for packet in fifo::decode(chunk) {
+                newer = newer.saturating_sub(1);
+                let (Some(accel), Some(gyro)) = (packet.accel, packet.gyro) else {
+                    continue;
+                };
+                let at = match (self.fifo.as_mut(), packet.timestamp) {
+                    (Some(state), Some(timestamp)) => {
+                        let elapsed = state.clock.elapsed(timestamp);
+                        // the newest packet was taken as the FIFO was drained, so
+                        // anchor the sensor clock by the age of this one
+                        let epoch = state.epoch.get_or_insert_with(|| {
+                            drained_at
+                                .checked_sub(odr_period * newer + elapsed)
+                                .unwrap_or(Instant::MIN)
+                        });
+                        *epoch + elapsed
+                    }
+                    _ => drained_at,
+                };
+                on_sample(self.measurement(accel, gyro, packet.temperature, at));
+                samples += 1;
+            }
+        }
+        Ok(samples)
+    }
+}
+
+impl<I: I2c> ImuSensor<I> {
+    /// Reset the device and check that it is answering.
+    fn init(mut device: Registers<I>) -> Result<Self, DeviceError<I>> {
+        let id = device.read(reg::WHO_AM_I)?;
+        if id != reg::DEVICE_ID {
+            return Err(Error::BadChip(id));
+        }
+        device.write(reg::SIGNAL_PATH_RESET, reg::SOFT_RESET_DEVICE_CONFIG)?;
+        embassy_time::block_for(Duration::from_millis(1));
+        let power_mode = PowerMode::Standby;
+        device.update(reg::PWR_MGMT0, 0x0F, power_mode.bits())?;
+        let config = ImuConfig::default();
+        Self::write_config(&mut device, &config)?;
+        info!("Sample rate is: {:?}", config.odr.hz());
+        Ok(Self {
+            gimbal: None,
+            device,
+            power_mode,
+            config,
+            fifo: None,
+            calibration: Calibration::default(),
+            sequence: 0,
+            ahrs: AhrsConfig::default(),
+        })
+    }
+
+    /// Poll uncorrected samples at the output data rate for a while, returning them
+    /// with the mean die temperature.
+    async fn still_period(
+        &mut self,
+        duration: Duration,
+    ) -> Result<(calibration::StillPeriod, calibration::StillPeriod, f32), AppError> {
+        let mut accel = calibration::StillPeriod::default();
+        let mut gyro = calibration::StillPeriod::default();
+        let (mut temperature, mut count) = (0.0, 0);
+        let period = self.config.odr.period();
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            let (raw_accel, raw_gyro, sample_temperature) =
+                self.read_sample().map_err(|_| AppError::ImuI2c)?;
+            let (sample_accel, sample_gyro) = self.scale(raw_accel, raw_gyro);
+            accel.add(sample_accel);
+            gyro.add(sample_gyro);
+            temperature += sample_temperature;
+            count += 1;
+            Timer::after(period).await;
+        }
+        Ok((accel, gyro, temperature / count.max(1) as f32))
+    }
+
+    /// Read the die temperature in °C and the raw accelerometer and gyroscope in one burst,
+    /// as their registers follow on from each other.
+    fn read_sample(&mut self) -> Result<([i16; 3], [i16; 3], f32), DeviceError<I>> {
+        let mut buf = [0; 14];
+        self.device.read_into(reg::TEMP_DATA1, &mut buf)?;
+        let word = |i: usize| i16::from_be_bytes([buf[2 * i], buf[2 * i + 1]]);
+        let temperature = word(0) as f32 / 128.0 + 25.0;
+        Ok((
+            core::array::from_fn(|i| word(1 + i)),
+            core::array::from_fn(|i| word(4 + i)),
+            temperature,
+        ))
+    }
+
+    /// Convert raw readings to g and °/s, without any calibration.
+    fn scale(&self, accel: [i16; 3], gyro: [i16; 3]) -> (F32x3, F32x3) {
+        let scale = |raw: [i16; 3], lsb: f32| F32x3 {
+            x: raw[0] as f32 / lsb,
+            y: raw[1] as f32 / lsb,
+            z: raw[2] as f32 / lsb,
+        };
+        (
+            scale(accel, self.config.accel_range.sensitivity()),
+            scale(gyro, self.config.gyro_range.sensitivity()),
+        )
+    }
+
+    /// Put the accelerometer in low power mode and set the wake-on-motion thresholds.
+    fn start_wake_on_motion(
+        &mut self,
+        low_power: ImuConfig,
+        wake: WakeOnMotion,
+    ) -> Result<(), DeviceError<I>> {
+        self.device.update(reg::WOM_CONFIG, reg::WOM_EN, 0)?;
+        Self::write_config(&mut self.device, &low_power)?;
+        self.config = low_power;
+        self.set_power_mode(PowerMode::AccelLowPower)?;
+        let threshold = wake.threshold_bits();
+        for axis in [
+            reg::ACCEL_WOM_X_THR,
+            reg::ACCEL_WOM_Y_THR,
+            reg::ACCEL_WOM_Z_THR,
+        ] {
+            self.device.write_mreg1(axis, threshold)?;
+        }
+        self.device.update(reg::INT_SOURCE1, WOM_INT, WOM_INT)
+    }
+
+    /// Write a configuration to the sensor's registers.
+    fn write_config(device: &mut Registers<I>, config: &ImuConfig) -> Result<(), DeviceError<I>> {
+        let odr = config.odr.bits();
+        device.write(reg::ACCEL_CONFIG0, (config.accel_range.bits() << 5) | odr)?;
+        device.write(reg::GYRO_CONFIG0, (config.gyro_range.bits() << 5) | odr)?;
+        let bandwidth = config.bandwidth.bits();
+        device.update(reg::ACCEL_CONFIG1, 0x07, bandwidth)?;
+        device.update(reg::GYRO_CONFIG1, 0x07, bandwidth)
+    }
+
+    /// Scale and correct a raw sample, and pass it through the gimbal if one has been set up.
+    fn measurement(
         &mut self,
-    ) -> Result<Measurement, AccelError<icm42670::Error<I2cDeviceError<I2cError>>>> {
-        let accel = self.device.accel_norm()?;
-        let gyro = self.device.gyro_norm()?;
-        let inclination = self.gimbal.as_mut().map(|g| g.read(gyro, accel));
-        Ok(Measurement {
+        accel: [i16; 3],
+        gyro: [i16; 3],
+        temperature: f32,
+        at: Instant,
+    ) -> Measurement {
+        let (accel, gyro) = self.scale(accel, gyro);
+        let (accel, gyro) = self.calibration.apply(accel, gyro, temperature);
+        let fused = self.gimbal.as_mut().map(|g| g.read_at(gyro, accel, at));
+        let sequence = self.sequence;
+        self.sequence = sequence.wrapping_add(1);
+        Measurement {
+            at,
+            sequence,
             accel,
             gyro,
-            inclination,
-        })
+            inclination: fused.map(|(inclination, _)| inclination),
+            orientation: fused.map(|(_, orientation)| orientation),
+            linear_accel: fused.map(|(_, o)| o.quaternion.linear_acceleration(accel)),
+            temperature,
+        }
+    }
+
+    /// Start the gimbal for reading the sensor at a given period, flushing the FIFO if
+    /// it is enabled.
+    fn start_gimbal(&mut self, period: Duration) -> Result<(), DeviceError<I>> {
+        if self.fifo.is_some() {
+            // Anything buffered since the last run is stale, and its timestamps may have wrapped.
+            self.device.write(reg::SIGNAL_PATH_RESET, reg::FIFO_FLUSH)?;
+            self.fifo = Some(Fifo::default());
+            // The gimbal is fed every sample in the FIFO, not once per period.
+            self.gimbal = Some(Gimbal::new(self.config.odr.period(), &self.ahrs));
+            info!(
+                "Draining FIFO every {:?} milliseconds at {} Hz",
+                period.as_millis(),
+                self.config.odr.hz()
+            );
+        } else {
+            self.gimbal = Some(Gimbal::new(period, &self.ahrs));
+            info!(
+                "Starting measurement every {:?} milliseconds",
+                period.as_millis()
+            );
+        }
+        Ok(())
     }
-}
 
-impl ImuSensor {
     /// Start reading the sensor at a given period.
     async fn read_inner(
         &mut self,
         period: Duration,
-        ble: Option<BleConnection<'_, '_>>,
-    ) -> Result<(), AccelError<icm42670::Error<I2cDeviceError<I2cError>>>> {
-        self.gimbal = Some(Gimbal::new(period));
-        info!(
-            "Starting measurement every {:?} milliseconds",
-            period.as_millis()
-        );
-        let max_rate = self.device.sample_rate()?;
+        mut on_sample: impl FnMut(&Measurement),
+        mut report: impl AsyncFnMut(Measurement),
+    ) -> Result<(), DeviceError<I>> {
+        self.start_gimbal(period)?;
+        loop {
+            let now = Instant::now();
+            let meas = if self.fifo.is_some() {
+                let mut latest = None;
+                let samples = self
+                    .read_fifo(|meas| {
+                        on_sample(&meas);
+                        latest = Some(meas);
+                    })
+                    .await?;
+                if samples == 0 {
+                    warn!("FIFO was empty");
+                }
+                latest
+            } else {
+                let meas = self.read_measurement().await?;
+                on_sample(&meas);
+                Some(meas)
+            };
+            if let Some(meas) = meas {
+                report(meas).await;
+            }
+            Timer::after(period.checked_sub(now.elapsed()).unwrap_or_default()).await;
+        }
+    }
 
-        let read_time = Duration::from_secs(1) / max_rate as u32;
-        assert!(period > read_time, "Period must be greater than read time");
+    /// Sample the sensor on a fixed schedule, sending every sample to the channel.
+    async fn sample_inner(&mut self, period: Duration) -> Result<(), DeviceError<I>> {
+        self.start_gimbal(period)?;
+        let sender = sampler::SAMPLES.sender();
+        let mut meter = JitterMeter::default();
+        let mut deadline = Instant::now();
+        let mut next_report = deadline + sampler::REPORT_INTERVAL;
         loop {
+            Timer::at(deadline).await;
             let now = Instant::now();
-            let meas = self.read_measurement().await?;
-            if let Some((server, conn)) = ble {
-                if let Err(error) = server.notify_imu(conn, meas).await {
-                    log::error!("Error notifying BLE: {:?}", error);
+            meter.wakeup(now.saturating_duration_since(deadline), period);
+            let mut dropped = 0;
+            let mut send = |meas: Measurement| {
+                if sender.try_send(meas).is_err() {
+                    dropped += 1;
                 }
+            };
+            if self.fifo.is_some() {
+                self.read_fifo(&mut send).await?;
             } else {
-                log::info!(
-                    "G: ({:.3}, {:.3}, {:.3}) | A: ({:.3}, {:.3}, {:.3}) | I: ({:.3}, {:.3}, {:.3})",
-                    meas.gyro.x,
-                    meas.gyro.y,
-                    meas.gyro.z,
-                    meas.accel.x,
-                    meas.accel.y,
-                    meas.accel.z,
-                    meas.inclination.map(|incl| incl.x).unwrap_or_default(),
-                    meas.inclination.map(|incl| incl.y).unwrap_or_default(),
-                    meas.inclination.map(|incl| incl.z).unwrap_or_default()
-                );
+                send(self.read_measurement().await?);
+            }
+            meter.dropped(dropped);
+            if now >= next_report {
+                let jitter = meter.summary();
+                info!("Sampling jitter: {:?}", jitter);
+                sampler::report(jitter);
+                next_report = now + sampler::REPORT_INTERVAL;
+            }
+            deadline += period;
+            if deadline < Instant::now() {
+                // start the schedule again rather than catching up in a burst
+                deadline = Instant::now();
             }
-            Timer::after(period - now.elapsed()).await;
         }
     }
 }
 
-mod gimbal {
-    use embassy_time::{Duration, Instant};
-    use icm42670::accelerometer::vector::F32x3;
-    use imu_fusion::{Fusion, FusionAhrsSettings, FusionQuaternion, FusionVector};
-    use micromath::F32Ext;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockBus;
+    use embassy_futures::block_on;
+
+    fn sensor() -> (ImuSensor<MockBus>, MockBus) {
+        let bus = MockBus::default();
+        (ImuSensor::from_i2c(bus.clone()).unwrap(), bus)
+    }
+
+    fn assert_close(actual: F32x3, expected: (f32, f32, f32)) {
+        let (x, y, z) = expected;
+        assert!((actual.x - x).abs() < 1e-3, "x: {} != {}", actual.x, x);
+        assert!((actual.y - y).abs() < 1e-3, "y: {} != {}", actual.y, y);
+        assert!((actual.z - z).abs() < 1e-3, "z: {} != {}", actual.z, z);
+    }
 
-    pub struct Gimbal(Fusion);
+    #[test]
+    fn scales_raw_readings_by_default_range() {
+        let (mut imu, bus) = sensor();
+        // ±16g => 2048 LSB/g, ±2000dps => 16.4 LSB/dps
+        bus.set_vector(reg::ACCEL_DATA_X1, [2048, -2048, 1024]);
+        bus.set_vector(reg::GYRO_DATA_X1, [164, 0, -328]);
+        let meas = block_on(imu.read_measurement()).unwrap();
+        assert_close(meas.accel, (1.0, -1.0, 0.5));
+        assert_close(meas.gyro, (10.0, 0.0, -20.0));
+    }
+
+    #[test]
+    fn reads_die_temperature() {
+        let (mut imu, bus) = sensor();
+        // 128 LSB/°C from 25°C in the registers, 2 LSB/°C in the FIFO
+        bus.set_register(reg::TEMP_DATA1, 0x0A);
+        let meas = block_on(imu.read_measurement()).unwrap();
+        assert_eq!(meas.temperature, 45.0);
+        imu.enable_fifo(4).unwrap();
+        bus.push_fifo_packet([0; 3], [0; 3], 10, 1000);
+        let mut samples = std::vec::Vec::new();
+        block_on(imu.read_fifo(|meas| samples.push(meas))).unwrap();
+        assert_eq!(samples[0].temperature, 30.0);
+    }
 
-    impl Gimbal {
-        /// Create a new gimbal with a period between measurements
-        pub fn new(period: Duration) -> Self {
-            Self(Fusion::new(
-                period.as_millis() as u32,
-                FusionAhrsSettings::new(),
-            ))
+    #[test]
+    fn numbers_and_timestamps_every_sample() {
+        let (mut imu, bus) = sensor();
+        let first = block_on(imu.read_measurement()).unwrap();
+        let second = block_on(imu.read_measurement()).unwrap();
+        assert_eq!((first.sequence, second.sequence), (0, 1));
+        assert!(second.at >= first.at);
+        imu.enable_fifo(4).unwrap();
+        for i in 1..=3 {
+            bus.push_fifo_packet([0; 3], [0; 3], 0, 1000 * i);
         }
+        let mut samples = std::vec::Vec::new();
+        block_on(imu.read_fifo(|meas| samples.push(meas))).unwrap();
+        let sequences: std::vec::Vec<_> = samples.iter().map(|meas| meas.sequence).collect();
+        assert_eq!(sequences, [2, 3, 4]);
+        // timestamps follow the sensor's clock, at 16µs per tick
+        assert_eq!(samples[2].at - samples[0].at, Duration::from_micros(32_000));
+    }
 
-        /// Read the sensor data and calculate the inclination
-        pub fn read(&mut self, gyro: F32x3, accel: F32x3) -> F32x3 {
-            let now = Instant::now();
-            // our sensor does not have a magnetometer
-            self.0.update_no_mag(
-                FusionVector::new(gyro.x, gyro.y, gyro.z),
-                FusionVector::new(accel.x, accel.y, accel.z),
-                (now.as_millis() as f64 / 1000.0) as f32,
-            );
-            let quaternion = self.0.quaternion();
-            let fusion_accel = Self::quaternion_to_acceleration(&quaternion);
-            Self::inclination(fusion_accel)
-        }
-
-        /// Calculate the inclination of the sensor in degrees
-        fn inclination(accel: F32x3) -> F32x3 {
-            let F32x3 { x, y, z } = accel;
-            let (x_sq, y_sq, z_sq) = (x.powi(2), y.powi(2), z.powi(2));
-            F32x3 {
-                x: (x.atan2((y_sq + z_sq).sqrt())).to_degrees(),
-                y: (y.atan2((x_sq + z_sq).sqrt())).to_degrees(),
-                z: (z.atan2((x_sq + y_sq).sqrt())).to_degrees(),
-            }
+    #[test]
+    fn no_inclination_without_gimbal() {
+        let (mut imu, _bus) = sensor();
+        let meas = block_on(imu.read_measurement()).unwrap();
+        assert!(meas.inclination.is_none());
+    }
+
+    #[test]
+    fn set_power_mode_writes_pwr_mgmt0() {
+        let (mut imu, bus) = sensor();
+        imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+        assert_eq!(bus.register(reg::PWR_MGMT0) & 0x0F, 0x0F);
+        imu.set_power_mode(PowerMode::Sleep).unwrap();
+        assert_eq!(bus.register(reg::PWR_MGMT0) & 0x0F, 0x00);
+    }
+
+    #[test]
+    fn bus_errors_are_propagated() {
+        let (mut imu, bus) = sensor();
+        bus.disconnect();
+        assert!(block_on(imu.read_measurement()).is_err());
+        assert!(imu.set_power_mode(PowerMode::SixAxisLowNoise).is_err());
+    }
+
+    #[test]
+    fn drains_fifo_in_order() {
+        let (mut imu, bus) = sensor();
+        imu.enable_fifo(4).unwrap();
+        assert_eq!(bus.register(reg::FIFO_CONFIG1), 0x00);
+        assert_eq!(bus.register(reg::FIFO_CONFIG2), 64);
+        assert_eq!(bus.mreg1(reg::FIFO_CONFIG5) & 0x03, 0x03);
+        for i in 1..=3 {
+            bus.push_fifo_packet([2048 * i, 0, 0], [164 * i, 0, 0], 0, 1000 * i as u16);
+        }
+        let mut samples = std::vec::Vec::new();
+        let count = block_on(imu.read_fifo(|meas| samples.push(meas))).unwrap();
+        assert_eq!(count, 3);
+        for (i, meas) in samples.iter().enumerate() {
+            let n = (i + 1) as f32;
+            assert_close(meas.accel, (n, 0.0, 0.0));
+            assert_close(meas.gyro, (10.0 * n, 0.0, 0.0));
         }
+        assert_eq!(block_on(imu.read_fifo(|_| {})).unwrap(), 0);
+    }
 
-        /// Convert a quaternion to a 3D acceleration vector.
-        fn quaternion_to_acceleration(quaternion: &FusionQuaternion) -> F32x3 {
-            let FusionQuaternion { x, y, z, w } = quaternion;
-            F32x3 {
-                x: 2.0 * (x * z - w * y),
-                y: 2.0 * (y * z + w * x),
-                z: w.powi(2) - x.powi(2) - y.powi(2) + z.powi(2),
-            }
+    #[test]
+    fn anchors_fifo_timestamps_at_the_newest_sample() {
+        let (mut imu, bus) = sensor();
+        imu.configure(ImuConfig {
+            odr: Odr::Hz100,
+            ..Default::default()
+        })
+        .unwrap();
+        imu.enable_fifo(4).unwrap();
+        // a backlog of 5 samples 10ms apart, at 16µs per tick
+        for i in 0..5 {
+            bus.push_fifo_packet([0; 3], [0; 3], 0, 625 * i);
         }
+        // the host clock starts with the test process, so leave room for the backlog
+        embassy_time::block_for(Duration::from_millis(50));
+        let before = Instant::now();
+        let mut samples = std::vec::Vec::new();
+        block_on(imu.read_fifo(|meas| samples.push(meas))).unwrap();
+        let after = Instant::now();
+        let newest = samples[4].at;
+        assert!(before <= newest && newest <= after, "{:?}", newest);
+        assert_eq!(newest - samples[0].at, Duration::from_millis(40));
+    }
+
+    #[test]
+    fn configure_sets_range_and_rate() {
+        let (mut imu, bus) = sensor();
+        imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+        let config = ImuConfig {
+            accel_range: AccelRange::G2,
+            gyro_range: GyroRange::Dps250,
+            odr: Odr::Hz100,
+            bandwidth: Bandwidth::Hz34,
+        };
+        imu.configure(config).unwrap();
+        assert_eq!(bus.register(reg::ACCEL_CONFIG0), 0x69);
+        assert_eq!(bus.register(reg::GYRO_CONFIG0), 0x69);
+        assert_eq!(bus.register(reg::ACCEL_CONFIG1) & 0x07, 5);
+        bus.set_vector(reg::ACCEL_DATA_X1, [16384, 0, 0]);
+        bus.set_vector(reg::GYRO_DATA_X1, [131, 0, 0]);
+        let meas = block_on(imu.read_measurement()).unwrap();
+        assert_close(meas.accel, (1.0, 0.0, 0.0));
+        assert_close(meas.gyro, (1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn configure_rejects_invalid_settings() {
+        let (mut imu, _bus) = sensor();
+        imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+        let slow = ImuConfig {
+            odr: Odr::Hz6_25,
+            ..Default::default()
+        };
+        assert!(matches!(
+            imu.configure(slow),
+            Err(AppError::InvalidImuOdr(_))
+        ));
+        let aliased = ImuConfig {
+            odr: Odr::Hz100,
+            bandwidth: Bandwidth::Hz73,
+            ..Default::default()
+        };
+        assert!(matches!(
+            imu.configure(aliased),
+            Err(AppError::InvalidImuBandwidth(..))
+        ));
+        assert_eq!(imu.config(), ImuConfig::default());
+    }
+
+    #[test]
+    fn polling_period_must_cover_odr() {
+        let (mut imu, _bus) = sensor();
+        imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+        imu.configure(ImuConfig {
+            odr: Odr::Hz12_5,
+            bandwidth: Bandwidth::Bypass,
+            ..Default::default()
+        })
+        .unwrap();
+        let result = block_on(imu.start_task(Duration::from_millis(20), |_| {}, async |_| {}));
+        assert!(matches!(result, Err(AppError::InvalidReadPeriod(20, 80))));
+        let result = block_on(imu.start_sampling(Duration::from_millis(20)));
+        assert!(matches!(result, Err(AppError::InvalidReadPeriod(20, 80))));
+    }
+
+    #[test]
+    fn wake_on_motion_restores_previous_mode() {
+        let (mut imu, bus) = sensor();
+        imu.set_power_mode(PowerMode::SixAxisLowNoise).unwrap();
+        bus.set_register(reg::INT_STATUS2, reg::WOM_Y_INT);
+        let wake = WakeOnMotion {
+            threshold: 0.0625,
+            odr: Odr::Hz25,
+        };
+        block_on(imu.wait_for_motion(wake)).unwrap();
+        assert_eq!(bus.mreg1(reg::ACCEL_WOM_Y_THR), 16);
+        assert_eq!(bus.register(reg::INT_SOURCE1), 0b111);
+        assert_eq!(bus.register(reg::WOM_CONFIG) & reg::WOM_EN, 0);
+        assert_eq!(bus.register(reg::PWR_MGMT0) & 0x0F, 0x0F);
+        assert_eq!(bus.register(reg::ACCEL_CONFIG0), 0x06);
+        assert_eq!(imu.config(), ImuConfig::default());
+    }
+
+    #[test]
+    fn wake_on_motion_needs_a_low_power_rate() {
+        let (mut imu, _bus) = sensor();
+        let wake = WakeOnMotion {
+            odr: Odr::Hz800,
+            ..Default::default()
+        };
+        let result = block_on(imu.wait_for_motion(wake));
+        assert!(matches!(result, Err(AppError::InvalidImuOdr(800.0))));
+    }
+
+    #[test]
+    fn new_fails_on_wrong_device() {
+        let bus = MockBus::default();
+        bus.set_register(reg::WHO_AM_I, 0x00);
+        let result = ImuSensor::from_i2c(bus.clone());
+        assert!(matches!(result, Err(AppError::ImuBadChip(0x00))));
+        bus.disconnect();
+        assert!(matches!(ImuSensor::from_i2c(bus), Err(AppError::ImuI2c)));
     }
 }
@@ -0,0 +1,325 @@
+//! Classification of what the wearer is doing, from windows of measurements.
+//!
+//! Each window is reduced to a few [`Features`]: how much the total acceleration varies,
+//! how often it bounces (the step frequency when walking), and how fast the board turns.
+//! Features are kept as running sums, so a window of any length needs no sample buffer.
+//!
+//! [`classify`] then picks the nearest of a centroid per [`Activity`], with a confidence
+//! from how much nearer it is than the others. Both are plain functions, so recorded
+//! traces can be replayed through [`ActivityClassifier`] on the host.
+
+use embassy_time::{Duration, Instant};
+use micromath::F32Ext;
+
+use super::{Measurement, math};
+
+/// Time constant of the smoothing of the magnitude, in seconds.
+const SMOOTHING_TIME_CONSTANT: f32 = 0.05;
+/// Time constant of the mean of the magnitude, in seconds.
+const MEAN_TIME_CONSTANT: f32 = 1.0;
+/// Bounce either side of the mean needed to count a crossing, in g.
+const HYSTERESIS: f32 = 0.05;
+
+/// What the wearer is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Still,
+    Walking,
+    Running,
+    /// Riding in a vehicle, which vibrates without stepping
+    Vehicle,
+}
+
+/// A change of activity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActivityUpdate {
+    pub activity: Activity,
+    /// How sure the classifier is, from 0 to 1
+    pub confidence: f32,
+}
+
+/// Features of a window of measurements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    /// Standard deviation of the total acceleration, in g
+    pub accel_std: f32,
+    /// Rate the total acceleration bounces about its mean, in Hz
+    pub bounce_frequency: f32,
+    /// RMS of the angular rate, in °/s
+    pub gyro_rms: f32,
+}
+
+/// Typical features of each activity.
+const CENTROIDS: [(Activity, Features); 4] = [
+    (
+        Activity::Still,
+        Features {
+            accel_std: 0.003,
+            bounce_frequency: 0.0,
+            gyro_rms: 0.3,
+        },
+    ),
+    (
+        Activity::Vehicle,
+        Features {
+            accel_std: 0.04,
+            bounce_frequency: 0.0,
+            gyro_rms: 3.0,
+        },
+    ),
+    (
+        Activity::Walking,
+        Features {
+            accel_std: 0.2,
+            bounce_frequency: 1.8,
+            gyro_rms: 30.0,
+        },
+    ),
+    (
+        Activity::Running,
+        Features {
+            accel_std: 0.6,
+            bounce_frequency: 2.8,
+            gyro_rms: 100.0,
+        },
+    ),
+];
+
+impl Features {
+    /// Position in the space the centroids are compared in. The magnitudes span
+    /// orders of magnitude between activities, so they are compared on a log scale.
+    fn position(&self) -> [f32; 3] {
+        [
+            F32Ext::ln(self.accel_std + 0.001),
+            self.bounce_frequency / 0.5,
+            F32Ext::ln(self.gyro_rms + 0.1),
+        ]
+    }
+}
+
+/// Classify the features of a window, returning the most likely activity and the confidence.
+pub fn classify(features: &Features) -> ActivityUpdate {
+    let position = features.position();
+    let distances = CENTROIDS.map(|(activity, centroid)| {
+        let distance = position
+            .iter()
+            .zip(centroid.position())
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f32>();
+        (activity, distance)
+    });
+    let (activity, nearest) = distances
+        .iter()
+        .copied()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((Activity::Still, 0.0));
+    // softmax of the negative distances, relative to the nearest to keep exp in range
+    let total: f32 = distances
+        .iter()
+        .map(|(_, d)| F32Ext::exp(nearest - d))
+        .sum();
+    ActivityUpdate {
+        activity,
+        confidence: 1.0 / total,
+    }
+}
+
+/// Tuning of the activity classifier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActivityConfig {
+    /// Length of each window
+    pub window: Duration,
+    /// Confidence needed to change activity
+    pub min_confidence: f32,
+}
+
+impl Default for ActivityConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(2),
+            min_confidence: 0.5,
+        }
+    }
+}
+
+/// Running sums over the current window.
+#[derive(Default)]
+struct Window {
+    start: Option<Instant>,
+    count: u32,
+    accel_sum: f32,
+    accel_sum_sq: f32,
+    gyro_sum_sq: f32,
+    crossings: u32,
+}
+
+/// Classifies each window of measurements, reporting changes of activity.
+pub struct ActivityClassifier {
+    config: ActivityConfig,
+    window: Window,
+    /// Smoothed and mean magnitude of the acceleration
+    filtered: Option<(f32, f32)>,
+    /// Whether the bounce has dropped below the mean since the last crossing
+    below: bool,
+    last_sample: Option<Instant>,
+    current: Option<ActivityUpdate>,
+}
+
+impl ActivityClassifier {
+    /// Create a classifier with the given tuning.
+    pub fn new(config: ActivityConfig) -> Self {
+        Self {
+            config,
+            window: Window::default(),
+            filtered: None,
+            below: false,
+            last_sample: None,
+            current: None,
+        }
+    }
+
+    /// The classifier's tuning.
+    pub fn config(&self) -> ActivityConfig {
+        self.config
+    }
+
+    /// The current activity, once a window has been classified.
+    pub fn activity(&self) -> Option<ActivityUpdate> {
+        self.current
+    }
+
+    /// Feed a measurement, returning the activity when it changes.
+    pub fn update(&mut self, meas: &Measurement) -> Option<ActivityUpdate> {
+        let at = meas.at;
+        let (accel, gyro) = (meas.accel, meas.gyro);
+        let magnitude = math::sqrt(accel.x * accel.x + accel.y * accel.y + accel.z * accel.z);
+        let dt = self
+            .last_sample
+            .and_then(|last| at.checked_duration_since(last))
+            .map_or(0.0, |dt| dt.as_micros() as f32 / 1_000_000.0);
+        self.last_sample = Some(at);
+        let (smoothed, mean) = self.filtered.unwrap_or((magnitude, magnitude));
+        let smoothed = smoothed + dt / (SMOOTHING_TIME_CONSTANT + dt) * (magnitude - smoothed);
+        let mean = mean + dt / (MEAN_TIME_CONSTANT + dt) * (magnitude - mean);
+        self.filtered = Some((smoothed, mean));
+
+        let window = &mut self.window;
+        let start = *window.start.get_or_insert(at);
+        window.count += 1;
+        window.accel_sum += magnitude;
+        window.accel_sum_sq += magnitude * magnitude;
+        window.gyro_sum_sq += gyro.x * gyro.x + gyro.y * gyro.y + gyro.z * gyro.z;
+        let bounce = smoothed - mean;
+        if bounce < -HYSTERESIS {
+            self.below = true;
+        } else if self.below && bounce > HYSTERESIS {
+            self.below = false;
+            window.crossings += 1;
+        }
+
+        let elapsed = at.saturating_duration_since(start);
+        if elapsed < self.config.window {
+            return None;
+        }
+        let features = window.features(elapsed);
+        self.window = Window::default();
+        let update = classify(&features);
+        let changed = self.current.is_none_or(|c| c.activity != update.activity);
+        if changed && update.confidence >= self.config.min_confidence {
+            self.current = Some(update);
+            return Some(update);
+        }
+        None
+    }
+}
+
+impl Window {
+    fn features(&self, elapsed: Duration) -> Features {
+        let count = self.count.max(1) as f32;
+        let mean = self.accel_sum / count;
+        let variance = (self.accel_sum_sq / count - mean * mean).max(0.0);
+        Features {
+            accel_std: math::sqrt(variance),
+            bounce_frequency: self.crossings as f32 * 1_000_000.0 / elapsed.as_micros() as f32,
+            gyro_rms: math::sqrt(self.gyro_sum_sq / count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample period at 100 Hz.
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    /// A trace of `seconds` at 100 Hz, with a vertical bounce of `accel` g at `hz`
+    /// and a rotation of `gyro` °/s at the same rate.
+    fn trace(seconds: usize, hz: f32, accel: f32, gyro: f32) -> std::vec::Vec<Measurement> {
+        (0..seconds * 100)
+            .map(|i| {
+                let phase = 2.0 * core::f32::consts::PI * hz * i as f32 / 100.0;
+                Measurement::still()
+                    .with_accel([0.0, 0.0, 1.0 + accel * phase.sin()])
+                    .with_gyro([gyro * phase.cos(), 0.0, 0.0])
+            })
+            .collect()
+    }
+
+    fn run(trace: &[Measurement]) -> std::vec::Vec<ActivityUpdate> {
+        let mut classifier = ActivityClassifier::new(ActivityConfig::default());
+        let start = Instant::from_secs(1);
+        trace
+            .iter()
+            .enumerate()
+            .filter_map(|(i, meas)| classifier.update(&meas.at(start + PERIOD * i as u32)))
+            .collect()
+    }
+
+    #[test]
+    fn classifies_activities() {
+        for (trace, expected) in [
+            (trace(10, 13.0, 0.002, 0.2), Activity::Still),
+            (trace(10, 15.0, 0.04, 3.0), Activity::Vehicle),
+            (trace(10, 1.8, 0.3, 30.0), Activity::Walking),
+            (trace(10, 2.9, 0.8, 120.0), Activity::Running),
+        ] {
+            let updates = run(&trace);
+            assert_eq!(updates.len(), 1, "{:?}: {:?}", expected, updates);
+            assert_eq!(updates[0].activity, expected);
+            assert!(updates[0].confidence > 0.5, "{:?}", updates[0]);
+        }
+    }
+
+    #[test]
+    fn reports_changes_only() {
+        let mut walk_then_stop = trace(10, 1.8, 0.3, 30.0);
+        walk_then_stop.extend(trace(10, 13.0, 0.002, 0.2));
+        let activities: std::vec::Vec<_> = run(&walk_then_stop)
+            .iter()
+            .map(|update| update.activity)
+            .collect();
+        assert_eq!(activities, [Activity::Walking, Activity::Still]);
+    }
+
+    #[test]
+    fn extracts_features() {
+        let mut classifier = ActivityClassifier::new(ActivityConfig::default());
+        let start = Instant::from_secs(1);
+        let trace = trace(3, 2.0, 0.3, 30.0);
+        for (i, meas) in trace.iter().enumerate().take(201) {
+            classifier.update(&meas.at(start + PERIOD * i as u32));
+            if i == 199 {
+                let features = classifier.window.features(Duration::from_secs(2));
+                assert!((features.accel_std - 0.212).abs() < 0.01, "{:?}", features);
+                assert!((features.gyro_rms - 21.2).abs() < 0.5, "{:?}", features);
+                // the first bounce comes before the mean has settled
+                assert!(
+                    (features.bounce_frequency - 2.0).abs() <= 0.5,
+                    "{:?}",
+                    features
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Noise characterisation of the IMU by its Allan deviation.
+//!
+//! The samples of each axis are averaged over clusters of 2, 4, 8... samples, and the
+//! Allan deviation at each cluster time τ is the RMS difference between consecutive
+//! cluster averages, divided by √2. Each octave of clusters is built from pairs of the
+//! octave below, so the curves of every axis fit in a fixed amount of memory however long
+//! the board is left to run.
+//!
+//! Two numbers are read off the curves for tuning the [`AhrsConfig`](super::AhrsConfig):
+//!
+//! * The random walk, the deviation at τ = 1s on the curve's -½ slope, in units/√s. For
+//!   the gyroscope this is the angle random walk in °/√s, multiply by 60 for °/√h.
+//! * The bias instability, the floor of the curve divided by 0.664, in the sensor's units.
+//!   If the run was too short for the curve to turn back up this is an upper bound.
+//!
+//! The curves are only meaningful while the board is stationary, so any movement of the
+//! accelerometer beyond [`MOTION_THRESHOLD`] is flagged.
+//!
+//! To compute the curves off the board instead, record the raw samples with the capture
+//! example, see [`capture`](super::capture).
+
+use core::f32::consts::{FRAC_1_SQRT_2, SQRT_2};
+
+use embassy_time::Duration;
+use icm42670::accelerometer::vector::F32x3;
+use log::info;
+
+use super::math;
+
+/// Number of octaves of cluster time, from one sample up.
+pub const OCTAVES: usize = 20;
+/// How far the acceleration may move from its first sample before the board is treated
+/// as moved, in g.
+pub const MOTION_THRESHOLD: f32 = 0.1;
+/// Fewest differences between clusters for an octave to be used in the analysis.
+const MIN_DIFFERENCES: u32 = 8;
+/// Ratio of the floor of the Allan deviation to the bias instability, √(2 ln 2 / π).
+const BIAS_INSTABILITY_FACTOR: f32 = 0.664;
+
+/// Clusters of one size.
+#[derive(Debug, Clone, Copy, Default)]
+struct Octave {
+    /// First of a pair of cluster averages, waiting to be passed up to the next octave
+    pending: Option<f32>,
+    /// Previous cluster average
+    previous: Option<f32>,
+    /// Sum of the squared differences between consecutive cluster averages
+    sum_squares: f64,
+    /// Number of differences summed
+    differences: u32,
+}
+
+/// Allan deviation of a single axis.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllanAxis {
+    octaves: [Octave; OCTAVES],
+}
+
+impl AllanAxis {
+    /// Add the next sample.
+    pub fn add(&mut self, value: f32) {
+        let mut average = value;
+        for octave in &mut self.octaves {
+            if let Some(previous) = octave.previous {
+                let difference = (average - previous) as f64;
+                octave.sum_squares += difference * difference;
+                octave.differences += 1;
+            }
+            octave.previous = Some(average);
+            match octave.pending.take() {
+                Some(first) => average = (first + average) / 2.0,
+                None => {
+                    octave.pending = Some(average);
+                    return;
+                }
+            }
+        }
+    }
+    /// Allan deviation over clusters of 2^`octave` samples, once two clusters are complete.
+    pub fn deviation(&self, octave: usize) -> Option<f32> {
+        let octave = self.octaves.get(octave)?;
+        let variance = octave.sum_squares / (2 * octave.differences.max(1)) as f64;
+        (octave.differences > 0).then(|| math::sqrt(variance as f32))
+    }
+    /// Deviation of an octave with enough clusters to rely on.
+    fn reliable(&self, octave: usize) -> Option<f32> {
+        self.octaves
+            .get(octave)
+            .filter(|o| o.differences >= MIN_DIFFERENCES)
+            .and_then(|_| self.deviation(octave))
+    }
+}
+
+/// Noise of a single axis, read off its Allan deviation.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AxisNoise {
+    /// Random walk in units/√s, if the curve is reliable near τ = 1s
+    pub random_walk: Option<f32>,
+    /// Bias instability in the sensor's units
+    pub bias_instability: Option<f32>,
+    /// Cluster time of the curve's floor, where the bias instability was read, in s
+    pub floor_tau: Option<f32>,
+}
+
+/// Noise of every axis.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Noise {
+    /// Accelerometer X, Y and Z, random walk in g/√s
+    pub accel: [AxisNoise; 3],
+    /// Gyroscope X, Y and Z, random walk in °/√s
+    pub gyro: [AxisNoise; 3],
+}
+
+/// Allan deviation of every axis of the accelerometer and gyroscope.
+#[derive(Debug, Clone)]
+pub struct AllanDeviation {
+    /// Time between samples, in s
+    period: f32,
+    accel: [AllanAxis; 3],
+    gyro: [AllanAxis; 3],
+    /// Number of samples added
+    samples: u32,
+    /// First acceleration, to tell whether the board moved
+    rest: Option<F32x3>,
+    /// Whether the board has moved
+    moved: bool,
+}
+
+impl AllanDeviation {
+    /// Start with no samples, taken every `period`.
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period: period.as_micros() as f32 / 1e6,
+            accel: Default::default(),
+            gyro: Default::default(),
+            samples: 0,
+            rest: None,
+            moved: false,
+        }
+    }
+    /// Add the next sample, in g and °/s.
+    pub fn add(&mut self, accel: F32x3, gyro: F32x3) {
+        let rest = *self.rest.get_or_insert(accel);
+        self.moved |= [accel.x - rest.x, accel.y - rest.y, accel.z - rest.z]
+            .iter()
+            .any(|change| change.abs() > MOTION_THRESHOLD);
+        let (accel, gyro) = ([accel.x, accel.y, accel.z], [gyro.x, gyro.y, gyro.z]);
+        for (axis, value) in self.accel.iter_mut().zip(accel) {
+            axis.add(value);
+        }
+        for (axis, value) in self.gyro.iter_mut().zip(gyro) {
+            axis.add(value);
+        }
+        self.samples += 1;
+    }
+    /// Number of samples added.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+    /// Whether the board moved while the samples were taken.
+    pub fn moved(&self) -> bool {
+        self.moved
+    }
+    /// Cluster time of an octave, in s.
+    pub fn tau(&self, octave: usize) -> f32 {
+        self.period * (1u32 << octave) as f32
+    }
+    /// Allan deviation of the accelerometer X, Y and Z axes, in g.
+    pub fn accel(&self) -> &[AllanAxis; 3] {
+        &self.accel
+    }
+    /// Allan deviation of the gyroscope X, Y and Z axes, in °/s.
+    pub fn gyro(&self) -> &[AllanAxis; 3] {
+        &self.gyro
+    }
+    /// Read the random walk and bias instability of every axis off its curve.
+    pub fn noise(&self) -> Noise {
+        Noise {
+            accel: self.accel.map(|axis| self.axis_noise(&axis)),
+            gyro: self.gyro.map(|axis| self.axis_noise(&axis)),
+        }
+    }
+    /// Log the curve of every axis and the noise read off them.
+    pub fn log(&self) {
+        info!(
+            "Allan deviation over {} samples, accelerometer in g and gyroscope in °/s",
+            self.samples
+        );
+        for octave in 0..OCTAVES {
+            if self.accel[0].deviation(octave).is_none() {
+                break;
+            }
+            let deviation =
+                |axes: &[AllanAxis; 3]| axes.map(|a| a.deviation(octave).unwrap_or(0.0));
+            info!(
+                "τ {:.3}s: accel {:?}, gyro {:?}",
+                self.tau(octave),
+                deviation(&self.accel),
+                deviation(&self.gyro)
+            );
+        }
+        info!("{:?}", self.noise());
+    }
+
+    fn axis_noise(&self, axis: &AllanAxis) -> AxisNoise {
+        let mut noise = AxisNoise::default();
+        let reliable = (0..OCTAVES).filter_map(|octave| Some((octave, axis.reliable(octave)?)));
+        for (octave, deviation) in reliable {
+            let tau = self.tau(octave);
+            if noise.bias_instability.is_none_or(|floor| deviation < floor) {
+                noise.bias_instability = Some(deviation);
+                noise.floor_tau = Some(tau);
+            }
+            // follow the -½ slope from the octave nearest 1s
+            if (FRAC_1_SQRT_2..SQRT_2).contains(&tau) {
+                noise.random_walk = Some(deviation * math::sqrt(tau));
+            }
+        }
+        noise.bias_instability = noise
+            .bias_instability
+            .map(|floor| floor / BIAS_INSTABILITY_FACTOR);
+        noise
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STILL: F32x3 = F32x3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+
+    #[test]
+    fn averages_each_octave() {
+        let mut axis = AllanAxis::default();
+        for value in [1.0, 3.0].repeat(16) {
+            axis.add(value);
+        }
+        // consecutive samples differ by 2, but every pair averages to 2
+        assert!((axis.deviation(0).unwrap() - math::sqrt(2.0)).abs() < 1e-6);
+        assert_eq!(axis.deviation(1), Some(0.0));
+        assert_eq!(axis.deviation(4), Some(0.0));
+        assert_eq!(axis.deviation(5), None);
+    }
+
+    #[test]
+    fn reads_random_walk_from_white_noise() {
+        let mut allan = AllanDeviation::new(Duration::from_millis(10));
+        // uniform noise of ±0.5°/s has a deviation of 0.5/√3 per sample, which averages
+        // down to 0.5/√3 × √0.01 at 1s
+        let mut seed = 1u32;
+        for _ in 0..60_000 {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let noise = (seed >> 8) as f32 / (1 << 24) as f32 - 0.5;
+            let gyro = F32x3 {
+                x: noise,
+                y: 0.0,
+                z: 0.0,
+            };
+            allan.add(STILL, gyro);
+        }
+        assert!(!allan.moved());
+        let noise = allan.noise();
+        let expected = 0.5 / math::sqrt(3.0) * 0.1;
+        let random_walk = noise.gyro[0].random_walk.unwrap();
+        assert!(
+            (random_walk - expected).abs() < 0.15 * expected,
+            "{}",
+            random_walk
+        );
+        // white noise keeps averaging down, so the floor is at the longest reliable cluster
+        assert!(noise.gyro[0].floor_tau.unwrap() > 10.0);
+        assert_eq!(noise.gyro[1].bias_instability, Some(0.0));
+    }
+
+    #[test]
+    fn flags_movement() {
+        let mut allan = AllanDeviation::new(Duration::from_millis(10));
+        allan.add(STILL, STILL);
+        allan.add(F32x3 { z: 1.05, ..STILL }, STILL);
+        assert!(!allan.moved());
+        allan.add(F32x3 { x: 0.2, ..STILL }, STILL);
+        assert!(allan.moved());
+    }
+}
@@ -0,0 +1,369 @@
+//! Calibration of the IMU's gyroscope bias and accelerometer offset and scale.
+//!
+//! The gyroscope bias is the mean rate measured over a still period.
+//! The accelerometer is calibrated with the six-position method: the board is held still
+//! with each axis pointing up and then down, so that each axis sees +1g and -1g.
+//! Halfway between the two readings is the offset, and half the difference is the scale.
+//!
+//! The gyroscope bias drifts with the die temperature, so it is corrected along a slope
+//! from the temperature it was measured at. The slope defaults to zero, and can be set
+//! from biases measured at two temperatures.
+//!
+//! Coefficients can be stored in flash so that they survive a reboot.
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use icm42670::accelerometer::vector::F32x3;
+
+use crate::AppError;
+
+/// Marks a stored calibration record, and its layout version. Records of any other
+/// version are rejected, so that the board is calibrated again.
+const MAGIC: u32 = 0x4341_4c33; // "CAL3"
+/// Size of a stored calibration record in bytes.
+const RECORD_LEN: usize = 4 + 13 * 4 + 4;
+/// Smallest temperature difference a gyroscope temperature slope is fitted over, in °C.
+const MIN_SLOPE_SPAN: f32 = 5.0;
+
+/// Correction coefficients applied to every measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// Gyroscope zero-rate offset in °/s
+    pub gyro_bias: [f32; 3],
+    /// Die temperature the gyroscope bias was measured at, in °C
+    pub gyro_bias_temperature: f32,
+    /// Change in the gyroscope bias with die temperature, in °/s per °C
+    pub gyro_temperature_slope: [f32; 3],
+    /// Accelerometer zero-g offset in g
+    pub accel_offset: [f32; 3],
+    /// Accelerometer scale, as a fraction of 1g
+    pub accel_scale: [f32; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            gyro_bias: [0.0; 3],
+            gyro_bias_temperature: 25.0,
+            gyro_temperature_slope: [0.0; 3],
+            accel_offset: [0.0; 3],
+            accel_scale: [1.0; 3],
+        }
+    }
+}
+
+impl Calibration {
+    /// Correct a raw accelerometer and gyroscope reading, taken at a die temperature in °C.
+    pub fn apply(&self, accel: F32x3, gyro: F32x3, temperature: f32) -> (F32x3, F32x3) {
+        let accel = [accel.x, accel.y, accel.z];
+        let gyro = [gyro.x, gyro.y, gyro.z];
+        let accel: [f32; 3] =
+            core::array::from_fn(|i| (accel[i] - self.accel_offset[i]) / self.accel_scale[i]);
+        let bias = self.gyro_bias_at(temperature);
+        let gyro: [f32; 3] = core::array::from_fn(|i| gyro[i] - bias[i]);
+        (vector(accel), vector(gyro))
+    }
+
+    /// The gyroscope bias at a die temperature in °C.
+    pub fn gyro_bias_at(&self, temperature: f32) -> [f32; 3] {
+        let change = temperature - self.gyro_bias_temperature;
+        core::array::from_fn(|i| self.gyro_bias[i] + self.gyro_temperature_slope[i] * change)
+    }
+
+    /// Set the temperature slope from a second bias measured at another temperature,
+    /// keeping the first as the reference. Returns `false` if the temperatures are too
+    /// close together to tell the slope apart from noise.
+    pub fn fit_gyro_temperature_slope(&mut self, bias: [f32; 3], temperature: f32) -> bool {
+        let change = temperature - self.gyro_bias_temperature;
+        if change.abs() < MIN_SLOPE_SPAN {
+            return false;
+        }
+        self.gyro_temperature_slope =
+            core::array::from_fn(|i| (bias[i] - self.gyro_bias[i]) / change);
+        true
+    }
+
+    /// Load the calibration stored at `offset`, if there is a valid one.
+    pub fn load<F: ReadNorFlash>(flash: &mut F, offset: u32) -> Option<Self> {
+        let mut record = [0; RECORD_LEN];
+        flash.read(offset, &mut record).ok()?;
+        Self::from_bytes(&record)
+    }
+
+    /// Store the calibration at `offset`, erasing the sector it starts in.
+    pub fn save<F: NorFlash>(&self, flash: &mut F, offset: u32) -> Result<(), AppError> {
+        let sector = offset - offset % F::ERASE_SIZE as u32;
+        flash
+            .erase(sector, sector + F::ERASE_SIZE as u32)
+            .map_err(|_| AppError::CalibrationStore)?;
+        flash
+            .write(offset, &self.to_bytes())
+            .map_err(|_| AppError::CalibrationStore)
+    }
+
+    /// Serialise to a record with a magic number and checksum.
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let mut record = [0; RECORD_LEN];
+        record[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        let values = self
+            .gyro_bias
+            .iter()
+            .chain(&self.accel_offset)
+            .chain(&self.accel_scale)
+            .chain([&self.gyro_bias_temperature])
+            .chain(&self.gyro_temperature_slope);
+        let (chunks, _) = record[4..RECORD_LEN - 4].as_chunks_mut::<4>();
+        for (chunk, value) in chunks.iter_mut().zip(values) {
+            *chunk = value.to_le_bytes();
+        }
+        let checksum = fnv1a(&record[..RECORD_LEN - 4]);
+        record[RECORD_LEN - 4..].copy_from_slice(&checksum.to_le_bytes());
+        record
+    }
+
+    /// Parse a record, rejecting erased flash, an older layout or a corrupted record.
+    fn from_bytes(record: &[u8]) -> Option<Self> {
+        let record = record.get(..RECORD_LEN)?;
+        let word =
+            |i: usize| u32::from_le_bytes([record[i], record[i + 1], record[i + 2], record[i + 3]]);
+        if word(0) != MAGIC || word(RECORD_LEN - 4) != fnv1a(&record[..RECORD_LEN - 4]) {
+            return None;
+        }
+        let value = |n: usize| f32::from_bits(word(4 + 4 * n));
+        let calibration = Self {
+            gyro_bias: core::array::from_fn(value),
+            accel_offset: core::array::from_fn(|i| value(3 + i)),
+            accel_scale: core::array::from_fn(|i| value(6 + i)),
+            gyro_bias_temperature: value(9),
+            gyro_temperature_slope: core::array::from_fn(|i| value(10 + i)),
+        };
+        let valid = calibration
+            .accel_scale
+            .iter()
+            .all(|scale| scale.is_finite() && *scale > 0.0)
+            && calibration.gyro_bias_temperature.is_finite();
+        valid.then_some(calibration)
+    }
+}
+
+/// A face of the board, named by the axis pointing up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+    ZUp,
+    ZDown,
+}
+
+impl Face {
+    /// The face pointing up for a mean acceleration, taken from its dominant axis.
+    pub fn from_gravity(accel: [f32; 3]) -> Self {
+        let axis = (0..3)
+            .max_by(|&a, &b| accel[a].abs().total_cmp(&accel[b].abs()))
+            .unwrap_or(2);
+        let up = accel[axis] > 0.0;
+        match (axis, up) {
+            (0, true) => Face::XUp,
+            (0, false) => Face::XDown,
+            (1, true) => Face::YUp,
+            (1, false) => Face::YDown,
+            (_, true) => Face::ZUp,
+            (_, false) => Face::ZDown,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Collects the mean acceleration with each face of the board pointing up.
+#[derive(Debug, Default)]
+pub struct SixPosition {
+    faces: [Option<[f32; 3]>; 6],
+}
+
+impl SixPosition {
+    /// Record the mean acceleration of a still period, returning the face it was taken on.
+    pub fn add(&mut self, mean: [f32; 3]) -> Face {
+        let face = Face::from_gravity(mean);
+        self.faces[face.index()] = Some(mean);
+        face
+    }
+
+    /// The faces that still need to be recorded.
+    pub fn missing(&self) -> impl Iterator<Item = Face> + '_ {
+        [
+            Face::XUp,
+            Face::XDown,
+            Face::YUp,
+            Face::YDown,
+            Face::ZUp,
+            Face::ZDown,
+        ]
+        .into_iter()
+        .filter(|face| self.faces[face.index()].is_none())
+    }
+
+    /// Solve for the accelerometer offset and scale once every face has been recorded.
+    pub fn solve(&self) -> Option<([f32; 3], [f32; 3])> {
+        let mut offset = [0.0; 3];
+        let mut scale = [1.0; 3];
+        for axis in 0..3 {
+            let up = self.faces[2 * axis]?[axis];
+            let down = self.faces[2 * axis + 1]?[axis];
+            offset[axis] = (up + down) / 2.0;
+            scale[axis] = (up - down) / 2.0;
+        }
+        Some((offset, scale))
+    }
+}
+
+/// Running mean of a still period, which also tracks whether the board moved.
+#[derive(Debug)]
+pub struct StillPeriod {
+    sum: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+    count: u32,
+}
+
+impl Default for StillPeriod {
+    fn default() -> Self {
+        Self {
+            sum: [0.0; 3],
+            min: [f32::MAX; 3],
+            max: [f32::MIN; 3],
+            count: 0,
+        }
+    }
+}
+
+impl StillPeriod {
+    /// Add a sample.
+    pub fn add(&mut self, sample: F32x3) {
+        for (axis, value) in [sample.x, sample.y, sample.z].into_iter().enumerate() {
+            self.sum[axis] += value;
+            self.min[axis] = self.min[axis].min(value);
+            self.max[axis] = self.max[axis].max(value);
+        }
+        self.count += 1;
+    }
+
+    /// The mean of the samples, if they all stayed within `tolerance` of each other.
+    pub fn mean(&self, tolerance: f32) -> Result<[f32; 3], AppError> {
+        if self.count == 0 || (0..3).any(|axis| self.max[axis] - self.min[axis] > tolerance) {
+            return Err(AppError::ImuNotStill);
+        }
+        Ok(self.sum.map(|sum| sum / self.count as f32))
+    }
+}
+
+fn vector([x, y, z]: [f32; 3]) -> F32x3 {
+    F32x3 { x, y, z }
+}
+
+/// 32 bit FNV-1a hash, used as the record checksum.
+pub(super) fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn six_position_solves_offset_and_scale() {
+        let mut six = SixPosition::default();
+        // each axis reads 1.02g up, -0.98g down: offset 0.02, scale 1.0
+        // except Z which reads 1.1g / -1.1g: offset 0, scale 1.1
+        assert_eq!(six.add([1.02, 0.01, 0.0]), Face::XUp);
+        assert_eq!(six.add([-0.98, 0.0, 0.01]), Face::XDown);
+        six.add([0.0, 1.02, 0.0]);
+        six.add([0.0, -0.98, 0.0]);
+        six.add([0.0, 0.0, 1.1]);
+        assert!(six.solve().is_none());
+        assert_eq!(six.missing().collect::<std::vec::Vec<_>>(), [Face::ZDown]);
+        six.add([0.0, 0.0, -1.1]);
+        let (offset, scale) = six.solve().unwrap();
+        for (actual, expected) in offset.iter().zip([0.02, 0.02, 0.0]) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+        for (actual, expected) in scale.iter().zip([1.0, 1.0, 1.1]) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn applies_correction() {
+        let calibration = Calibration {
+            gyro_bias: [0.5, -0.5, 1.0],
+            accel_offset: [0.02, 0.0, 0.0],
+            accel_scale: [1.0, 1.0, 1.1],
+            ..Default::default()
+        };
+        let (accel, gyro) =
+            calibration.apply(vector([1.02, 0.0, 1.1]), vector([0.5, 0.0, 1.0]), 25.0);
+        assert!((accel.x - 1.0).abs() < 1e-6 && (accel.z - 1.0).abs() < 1e-6);
+        assert_eq!((gyro.x, gyro.y, gyro.z), (0.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn corrects_gyro_bias_for_temperature() {
+        let mut calibration = Calibration {
+            gyro_bias: [0.5, 0.0, 0.0],
+            gyro_bias_temperature: 20.0,
+            ..Default::default()
+        };
+        assert!(!calibration.fit_gyro_temperature_slope([0.6, 0.0, 0.0], 22.0));
+        assert!(calibration.fit_gyro_temperature_slope([0.7, 0.0, -0.2], 30.0));
+        let bias = calibration.gyro_bias_at(40.0);
+        for (actual, expected) in bias.iter().zip([0.9, 0.0, -0.4]) {
+            assert!((actual - expected).abs() < 1e-5, "{:?}", bias);
+        }
+        let (_, gyro) = calibration.apply(vector([0.0; 3]), vector([0.9, 0.0, -0.4]), 40.0);
+        assert!(gyro.x.abs() < 1e-5 && gyro.z.abs() < 1e-5, "{:?}", gyro);
+    }
+
+    #[test]
+    fn still_period_rejects_motion() {
+        let mut still = StillPeriod::default();
+        still.add(vector([0.1, 0.0, -0.1]));
+        still.add(vector([0.3, 0.0, -0.1]));
+        let mean = still.mean(0.5).unwrap();
+        for (actual, expected) in mean.iter().zip([0.2, 0.0, -0.1]) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+        still.add(vector([5.0, 0.0, 0.0]));
+        assert!(matches!(still.mean(0.5), Err(AppError::ImuNotStill)));
+    }
+
+    #[test]
+    fn record_round_trips_and_detects_corruption() {
+        let calibration = Calibration {
+            gyro_bias: [0.1, 0.2, 0.3],
+            accel_offset: [0.01, 0.02, 0.03],
+            accel_scale: [1.01, 0.99, 1.0],
+            gyro_bias_temperature: 31.5,
+            gyro_temperature_slope: [0.01, -0.02, 0.0],
+        };
+        let mut record = calibration.to_bytes();
+        assert_eq!(Calibration::from_bytes(&record), Some(calibration));
+        record[8] ^= 1;
+        assert_eq!(Calibration::from_bytes(&record), None);
+        assert_eq!(Calibration::from_bytes(&[0xFF; RECORD_LEN]), None);
+    }
+
+    #[test]
+    fn rejects_older_records() {
+        // a "CAL2" record, from before the magic was bumped, with a valid checksum
+        let mut record = Calibration::default().to_bytes();
+        record[..4].copy_from_slice(&0x4341_4c32u32.to_le_bytes());
+        let checksum = fnv1a(&record[..RECORD_LEN - 4]);
+        record[RECORD_LEN - 4..].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(Calibration::from_bytes(&record), None);
+    }
+}
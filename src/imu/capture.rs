@@ -0,0 +1,78 @@
+//! Framing of labelled samples, for streaming training data to a host.
+//!
+//! Each [`Measurement`] is sent in a frame of its own, tagged with the label of what the
+//! board is doing while it is recorded. Logs may share the serial port with the frames,
+//! so each starts with a sync word and ends with a checksum, for the host to find
+//! the frames among the text. All values are little-endian:
+//!
+//! | Byte | Content                                        |
+//! | ---- | ---------------------------------------------- |
+//! | 0    | Sync word `0xA5 0x5A`                          |
+//! | 2    | Length of the label to the temperature (u8)    |
+//! | 3    | Label (u8)                                     |
+//! | 4    | Sequence number (u32)                          |
+//! | 8    | Time the sample was taken, in µs (u64)         |
+//! | 16   | Acceleration X, Y, Z in g (f32)                |
+//! | 28   | Angular rate X, Y, Z in °/s (f32)              |
+//! | 40   | Die temperature in °C (f32)                    |
+//! | 44   | FNV-1a checksum of the length to here (u32)    |
+//!
+//! `tools/capture_csv.py` turns a recording into a CSV file per label.
+
+use super::{Measurement, calibration::fnv1a};
+
+/// Start of every frame.
+pub const SYNC: [u8; 2] = [0xA5, 0x5A];
+/// Length of a frame.
+pub const FRAME_LEN: usize = 48;
+
+/// Frame a sample recorded under `label`.
+pub fn encode_frame(label: u8, meas: &Measurement) -> [u8; FRAME_LEN] {
+    let mut frame = [0; FRAME_LEN];
+    frame[..2].copy_from_slice(&SYNC);
+    frame[2] = (FRAME_LEN - 7) as u8;
+    frame[3] = label;
+    frame[4..8].copy_from_slice(&meas.sequence.to_le_bytes());
+    frame[8..16].copy_from_slice(&meas.at.as_micros().to_le_bytes());
+    let (accel, gyro) = (meas.accel, meas.gyro);
+    let values = [accel.x, accel.y, accel.z, gyro.x, gyro.y, gyro.z];
+    let (chunks, _) = frame[16..44].as_chunks_mut::<4>();
+    for (chunk, value) in chunks
+        .iter_mut()
+        .zip(values.into_iter().chain([meas.temperature]))
+    {
+        *chunk = value.to_le_bytes();
+    }
+    let checksum = fnv1a(&frame[2..44]);
+    frame[44..].copy_from_slice(&checksum.to_le_bytes());
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_time::Instant;
+
+    #[test]
+    fn frames_samples() {
+        let meas = Measurement::still()
+            .with_accel([0.0, -0.5, 1.0])
+            .with_gyro([10.0, 0.0, -2.5])
+            .with_temperature(31.25)
+            .numbered(7)
+            .at(Instant::from_micros(1_500_000));
+        let frame = encode_frame(3, &meas);
+        assert_eq!(frame[..4], [0xA5, 0x5A, 41, 3]);
+        assert_eq!(frame[4..8], 7u32.to_le_bytes());
+        assert_eq!(frame[8..16], 1_500_000u64.to_le_bytes());
+        assert_eq!(frame[20..24], (-0.5f32).to_le_bytes());
+        assert_eq!(frame[36..40], (-2.5f32).to_le_bytes());
+        assert_eq!(frame[40..44], 31.25f32.to_le_bytes());
+        let checksum = u32::from_le_bytes(frame[44..].try_into().unwrap());
+        assert_eq!(checksum, fnv1a(&frame[2..44]));
+        // the checksum covers the length, so a corrupted length is caught
+        let mut corrupted = frame;
+        corrupted[2] = 40;
+        assert_ne!(checksum, fnv1a(&corrupted[2..44]));
+    }
+}
@@ -0,0 +1,264 @@
+//! Measurement range, output data rate and filtering of the ICM42670.
+
+use super::PowerMode;
+use crate::AppError;
+
+/// Full-scale range of the accelerometer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccelRange {
+    /// ±2 g
+    G2,
+    /// ±4 g
+    G4,
+    /// ±8 g
+    G8,
+    /// ±16 g
+    G16,
+}
+
+impl AccelRange {
+    /// The `ACCEL_UI_FS_SEL` bits of `ACCEL_CONFIG0`.
+    pub fn bits(self) -> u8 {
+        match self {
+            AccelRange::G16 => 0,
+            AccelRange::G8 => 1,
+            AccelRange::G4 => 2,
+            AccelRange::G2 => 3,
+        }
+    }
+    /// Sensitivity in LSB/g.
+    pub fn sensitivity(self) -> f32 {
+        match self {
+            AccelRange::G16 => 2048.0,
+            AccelRange::G8 => 4096.0,
+            AccelRange::G4 => 8192.0,
+            AccelRange::G2 => 16384.0,
+        }
+    }
+}
+
+/// Full-scale range of the gyroscope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GyroRange {
+    /// ±250 °/s
+    Dps250,
+    /// ±500 °/s
+    Dps500,
+    /// ±1000 °/s
+    Dps1000,
+    /// ±2000 °/s
+    Dps2000,
+}
+
+impl GyroRange {
+    /// The `GYRO_UI_FS_SEL` bits of `GYRO_CONFIG0`.
+    pub fn bits(self) -> u8 {
+        match self {
+            GyroRange::Dps2000 => 0,
+            GyroRange::Dps1000 => 1,
+            GyroRange::Dps500 => 2,
+            GyroRange::Dps250 => 3,
+        }
+    }
+    /// Sensitivity in LSB/°/s.
+    pub fn sensitivity(self) -> f32 {
+        match self {
+            GyroRange::Dps2000 => 16.4,
+            GyroRange::Dps1000 => 32.8,
+            GyroRange::Dps500 => 65.5,
+            GyroRange::Dps250 => 131.0,
+        }
+    }
+    /// The full-scale range in °/s.
+    pub fn dps(self) -> f32 {
+        match self {
+            GyroRange::Dps2000 => 2000.0,
+            GyroRange::Dps1000 => 1000.0,
+            GyroRange::Dps500 => 500.0,
+            GyroRange::Dps250 => 250.0,
+        }
+    }
+}
+
+/// Output data rate, shared by the accelerometer and gyroscope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Odr {
+    Hz1600,
+    Hz800,
+    Hz400,
+    Hz200,
+    Hz100,
+    Hz50,
+    Hz25,
+    Hz12_5,
+    /// Accelerometer low power mode only
+    Hz6_25,
+    /// Accelerometer low power mode only
+    Hz3_125,
+    /// Accelerometer low power mode only
+    Hz1_5625,
+}
+
+impl Odr {
+    /// The `ODR` bits of `ACCEL_CONFIG0` and `GYRO_CONFIG0`.
+    pub fn bits(self) -> u8 {
+        match self {
+            Odr::Hz1600 => 0x05,
+            Odr::Hz800 => 0x06,
+            Odr::Hz400 => 0x07,
+            Odr::Hz200 => 0x08,
+            Odr::Hz100 => 0x09,
+            Odr::Hz50 => 0x0A,
+            Odr::Hz25 => 0x0B,
+            Odr::Hz12_5 => 0x0C,
+            Odr::Hz6_25 => 0x0D,
+            Odr::Hz3_125 => 0x0E,
+            Odr::Hz1_5625 => 0x0F,
+        }
+    }
+    /// The rate in Hz.
+    pub fn hz(self) -> f32 {
+        match self {
+            Odr::Hz1600 => 1600.0,
+            Odr::Hz800 => 800.0,
+            Odr::Hz400 => 400.0,
+            Odr::Hz200 => 200.0,
+            Odr::Hz100 => 100.0,
+            Odr::Hz50 => 50.0,
+            Odr::Hz25 => 25.0,
+            Odr::Hz12_5 => 12.5,
+            Odr::Hz6_25 => 6.25,
+            Odr::Hz3_125 => 3.125,
+            Odr::Hz1_5625 => 1.5625,
+        }
+    }
+    /// The time between two samples.
+    pub fn period(self) -> embassy_time::Duration {
+        embassy_time::Duration::from_micros((1_000_000.0 / self.hz()) as u64)
+    }
+}
+
+/// Bandwidth of the low-pass filter in the low noise signal path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bandwidth {
+    /// Filter bypassed
+    Bypass,
+    Hz180,
+    Hz121,
+    Hz73,
+    Hz53,
+    Hz34,
+    Hz25,
+    Hz16,
+}
+
+impl Bandwidth {
+    /// The `UI_FILT_BW` bits of `ACCEL_CONFIG1` and `GYRO_CONFIG1`.
+    pub fn bits(self) -> u8 {
+        match self {
+            Bandwidth::Bypass => 0,
+            Bandwidth::Hz180 => 1,
+            Bandwidth::Hz121 => 2,
+            Bandwidth::Hz73 => 3,
+            Bandwidth::Hz53 => 4,
+            Bandwidth::Hz34 => 5,
+            Bandwidth::Hz25 => 6,
+            Bandwidth::Hz16 => 7,
+        }
+    }
+    /// The cut-off frequency in Hz, if the filter is in use.
+    pub fn hz(self) -> Option<f32> {
+        match self {
+            Bandwidth::Bypass => None,
+            Bandwidth::Hz180 => Some(180.0),
+            Bandwidth::Hz121 => Some(121.0),
+            Bandwidth::Hz73 => Some(73.0),
+            Bandwidth::Hz53 => Some(53.0),
+            Bandwidth::Hz34 => Some(34.0),
+            Bandwidth::Hz25 => Some(25.0),
+            Bandwidth::Hz16 => Some(16.0),
+        }
+    }
+}
+
+/// Measurement configuration of the IMU.
+///
+/// The default matches the sensor's reset state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuConfig {
+    /// Accelerometer full-scale range
+    pub accel_range: AccelRange,
+    /// Gyroscope full-scale range
+    pub gyro_range: GyroRange,
+    /// Output data rate of both sensors
+    pub odr: Odr,
+    /// Low-pass filter bandwidth of both sensors
+    pub bandwidth: Bandwidth,
+}
+
+impl Default for ImuConfig {
+    fn default() -> Self {
+        Self {
+            accel_range: AccelRange::G16,
+            gyro_range: GyroRange::Dps2000,
+            odr: Odr::Hz800,
+            bandwidth: Bandwidth::Hz180,
+        }
+    }
+}
+
+/// Wake-on-motion configuration of the accelerometer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WakeOnMotion {
+    /// Change in acceleration between two samples that counts as motion, up to 1g
+    /// in steps of about 4mg
+    pub threshold: f32,
+    /// Rate the accelerometer samples at while waiting, in low power mode
+    pub odr: Odr,
+}
+
+impl Default for WakeOnMotion {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            odr: Odr::Hz25,
+        }
+    }
+}
+
+impl WakeOnMotion {
+    /// The `ACCEL_WOM_*_THR` value, 1g/256 per LSB.
+    pub fn threshold_bits(self) -> u8 {
+        (self.threshold * 256.0).clamp(1.0, 255.0) as u8
+    }
+}
+
+impl ImuConfig {
+    /// Check that the configuration can be used in a given power mode.
+    pub fn validate(&self, power_mode: PowerMode) -> Result<(), AppError> {
+        let hz = self.odr.hz();
+        let low_noise = matches!(
+            power_mode,
+            PowerMode::AccelLowNoise
+                | PowerMode::GyroLowNoise
+                | PowerMode::SixAxisLowNoise
+                | PowerMode::SixAxisLowPower
+        );
+        let low_power = matches!(
+            power_mode,
+            PowerMode::AccelLowPower | PowerMode::SixAxisLowPower
+        );
+        // the gyroscope and the low noise path only run between 12.5 Hz and 1.6 kHz,
+        // the low power path tops out at 400 Hz.
+        if (low_noise && hz < 12.5) || (low_power && hz > 400.0) {
+            return Err(AppError::InvalidImuOdr(hz));
+        }
+        // the filter only sits in the low noise path.
+        if let Some(bandwidth) = self.bandwidth.hz().filter(|_| low_noise)
+            && bandwidth >= hz / 2.0
+        {
+            return Err(AppError::InvalidImuBandwidth(bandwidth, hz));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+//! The detectors that pick events out of the IMU's measurements.
+//!
+//! They are not part of the sensor: the application owns a [`Detectors`], enables the
+//! ones it wants and feeds it every measurement, from [`ImuSensor::start_task`] or from
+//! [`samples`]. What they find is published as an [`Event`], received through [`subscribe`].
+//!
+//! Taps and shocks only last a few milliseconds, so they are best detected in FIFO mode
+//! at a high output data rate. Shocks are also kept in a bounded log, read back with
+//! [`recorded_shock`], and the pose with [`current_pose`]. The bubble level measures its
+//! tilt from an attitude captured with [`tare_level`], and needs the gimbal's inclination.
+//! The tamper detector triggers the alarm itself when the board is moved while armed
+//! with [`arm_alarm`].
+//!
+//! [`ImuSensor::start_task`]: super::ImuSensor::start_task
+//! [`samples`]: super::samples
+//! [`subscribe`]: super::subscribe
+//! [`recorded_shock`]: super::recorded_shock
+//! [`current_pose`]: super::current_pose
+//! [`tare_level`]: super::tare_level
+//! [`arm_alarm`]: super::arm_alarm
+
+use log::{info, warn};
+
+use super::{
+    ActivityClassifier, ActivityConfig, ActivityUpdate, AirMouse, BubbleLevel, Event, FallConfig,
+    FallDetector, GestureConfig, GestureModel, GestureRecognizer, LevelConfig, Measurement,
+    Pedometer, PedometerConfig, PointerConfig, PoseConfig, PoseDetector, ShockConfig,
+    ShockDetector, SpectrumAnalyzer, SpectrumConfig, Steps, TamperConfig, TamperDetector,
+    TapConfig, TapDetector, events, pointer, pose, shock,
+};
+
+/// The enabled detectors, all disabled by default.
+#[derive(Default)]
+pub struct Detectors {
+    /// Tap detector, if enabled
+    taps: Option<TapDetector>,
+    /// Fall detector, if enabled
+    falls: Option<FallDetector>,
+    /// Step counter, if enabled
+    pedometer: Option<Pedometer>,
+    /// Vibration analysis, if enabled
+    spectrum: Option<SpectrumAnalyzer>,
+    /// Activity classifier, if enabled
+    activity: Option<ActivityClassifier>,
+    /// Gesture recogniser, if enabled
+    gestures: Option<GestureRecognizer>,
+    /// Shock detector, if enabled
+    shocks: Option<ShockDetector>,
+    /// Bubble level, if enabled
+    level: Option<BubbleLevel>,
+    /// Tamper detector, if enabled
+    tamper: Option<TamperDetector>,
+    /// Air mouse, if enabled
+    pointer: Option<AirMouse>,
+    /// Pose detector, if enabled
+    pose: Option<PoseDetector>,
+}
+
+impl Detectors {
+    /// Start detecting taps, which are published as [`Event::Tap`].
+    pub fn enable_taps(&mut self, config: TapConfig) {
+        info!("Tap detection enabled: {:?}", config);
+        self.taps = Some(TapDetector::new(config));
+    }
+    /// Stop detecting taps.
+    pub fn disable_taps(&mut self) {
+        self.taps = None;
+    }
+    /// Start detecting free falls and falls, which are published as [`Event::Fall`].
+    pub fn enable_falls(&mut self, config: FallConfig) {
+        info!("Fall detection enabled: {:?}", config);
+        self.falls = Some(FallDetector::new(config));
+    }
+    /// Stop detecting falls.
+    pub fn disable_falls(&mut self) {
+        self.falls = None;
+    }
+    /// Start counting steps from zero, publishing every step as [`Event::Steps`].
+    pub fn enable_pedometer(&mut self, config: PedometerConfig) {
+        info!("Pedometer enabled: {:?}", config);
+        self.pedometer = Some(Pedometer::new(config));
+    }
+    /// Stop counting steps.
+    pub fn disable_pedometer(&mut self) {
+        self.pedometer = None;
+    }
+    /// Start analysing the vibration of every window of samples, published as
+    /// [`Event::Vibration`].
+    ///
+    /// The frequencies assume a sample every `1 / sample_rate` seconds, so the sensor
+    /// should be in FIFO mode at that output data rate, and it should not change while enabled.
+    pub fn enable_spectrum(&mut self, sample_rate: f32, config: SpectrumConfig) {
+        info!("Vibration analysis enabled: {:?}", config);
+        self.spectrum = Some(SpectrumAnalyzer::new(sample_rate, config));
+    }
+    /// Stop analysing vibration.
+    pub fn disable_spectrum(&mut self) {
+        self.spectrum = None;
+    }
+    /// Start classifying activity, publishing every change as [`Event::Activity`].
+    pub fn enable_activity(&mut self, config: ActivityConfig) {
+        info!("Activity classification enabled: {:?}", config);
+        self.activity = Some(ActivityClassifier::new(config));
+    }
+    /// Stop classifying activity.
+    pub fn disable_activity(&mut self) {
+        self.activity = None;
+    }
+    /// Start recognising gestures with `model`, publishing each as [`Event::Gesture`].
+    ///
+    /// Enabling again swaps in a new model, such as one just loaded from flash.
+    pub fn enable_gestures(&mut self, model: GestureModel<'static>, config: GestureConfig) {
+        info!(
+            "Gesture recognition enabled over {} samples: {:?}",
+            model.window(),
+            config
+        );
+        self.gestures = Some(GestureRecognizer::new(model, config));
+    }
+    /// Stop recognising gestures.
+    pub fn disable_gestures(&mut self) {
+        self.gestures = None;
+    }
+    /// Start logging shocks, each also published as [`Event::Shock`].
+    ///
+    /// The peak is only as good as the sample rate, so the sensor should be in FIFO mode
+    /// at a high output data rate, with a range above the threshold.
+    pub fn enable_shocks(&mut self, config: ShockConfig) {
+        info!("Shock logging enabled: {:?}", config);
+        self.shocks = Some(ShockDetector::new(config));
+    }
+    /// Stop logging shocks, keeping those already logged.
+    pub fn disable_shocks(&mut self) {
+        self.shocks = None;
+    }
+    /// Start measuring the tilt from a reference attitude, publishing it as [`Event::Level`]
+    /// whenever it moves. The reference is captured with [`tare_level`](super::tare_level).
+    ///
+    /// The tilt comes from the gimbal, so is only measured while the sensor task is running.
+    pub fn enable_level(&mut self, config: LevelConfig) {
+        info!("Bubble level enabled: {:?}", config);
+        self.level = Some(BubbleLevel::new(config));
+    }
+    /// Stop measuring the tilt, forgetting the reference.
+    pub fn disable_level(&mut self) {
+        self.level = None;
+    }
+    /// Start watching for movement while the alarm is armed with
+    /// [`arm_alarm`](super::arm_alarm), which triggers it and publishes [`Event::Alarm`].
+    pub fn enable_tamper(&mut self, config: TamperConfig) {
+        info!("Tamper detection enabled: {:?}", config);
+        self.tamper = Some(TamperDetector::new(config));
+    }
+    /// Stop watching for movement, leaving the alarm in its current state.
+    pub fn disable_tamper(&mut self) {
+        self.tamper = None;
+    }
+    /// Start moving a cursor with the board's rotation, sending the movement to
+    /// [`pointer_reports`](super::pointer_reports). The buttons are pressed with
+    /// [`click`](super::click).
+    pub fn enable_pointer(&mut self, config: PointerConfig) {
+        info!("Air mouse enabled: {:?}", config);
+        self.pointer = Some(AirMouse::new(config));
+    }
+    /// Stop moving the cursor.
+    pub fn disable_pointer(&mut self) {
+        self.pointer = None;
+    }
+    /// Start tracking which side the board rests on, publishing each new pose as
+    /// [`Event::Pose`].
+    pub fn enable_pose(&mut self, config: PoseConfig) {
+        info!("Pose detection enabled: {:?}", config);
+        self.pose = Some(PoseDetector::new(config));
+    }
+    /// Stop tracking the pose, keeping the last one reported.
+    pub fn disable_pose(&mut self) {
+        self.pose = None;
+    }
+    /// The current activity, once classification has been enabled for a window.
+    pub fn activity(&self) -> Option<ActivityUpdate> {
+        self.activity
+            .as_ref()
+            .and_then(ActivityClassifier::activity)
+    }
+    /// The steps counted so far, if the pedometer is enabled.
+    pub fn steps(&self) -> Option<Steps> {
+        self.pedometer.as_ref().map(Pedometer::steps)
+    }
+
+    /// Run a measurement through the enabled detectors, publishing what they find.
+    pub fn update(&mut self, meas: &Measurement) {
+        if let Some(tap) = self.taps.as_mut().and_then(|taps| taps.update(meas)) {
+            info!("Tap: {:?}", tap);
+            events::publish(Event::Tap(tap));
+        }
+        if let Some(shock) = self.shocks.as_mut().and_then(|s| s.update(meas)) {
+            warn!("Shock: {:?}", shock);
+            shock::record(shock);
+            events::publish(Event::Shock(shock));
+        }
+        if let Some(fall) = self.falls.as_mut().and_then(|falls| falls.update(meas)) {
+            warn!("Fall: {:?}", fall);
+            events::publish(Event::Fall(fall));
+        }
+        if let Some(steps) = self.pedometer.as_mut().and_then(|p| p.update(meas)) {
+            events::publish(Event::Steps(steps));
+        }
+        let accel = [meas.accel.x, meas.accel.y, meas.accel.z];
+        if let Some(vibration) = self.spectrum.as_mut().and_then(|s| s.update(accel)) {
+            events::publish(Event::Vibration(vibration));
+        }
+        if let Some(update) = self.activity.as_mut().and_then(|a| a.update(meas)) {
+            info!("Activity: {:?}", update);
+            events::publish(Event::Activity(update));
+        }
+        if let Some(gesture) = self.gestures.as_mut().and_then(|g| g.update(meas)) {
+            info!("Gesture: {:?}", gesture);
+            events::publish(Event::Gesture(gesture));
+        }
+        if let Some(level) = self.level.as_mut().and_then(|l| l.update(meas)) {
+            events::publish(Event::Level(level));
+        }
+        if let Some(report) = self.pointer.as_mut().and_then(|p| p.update(meas)) {
+            pointer::send(report);
+        }
+        if let Some(pose) = self.pose.as_mut().and_then(|p| p.update(meas)) {
+            pose::report(pose);
+        }
+        // the detector triggers the alarm itself, which publishes the change
+        if let Some(tamper) = self.tamper.as_mut().and_then(|t| t.update(meas)) {
+            warn!("Tamper: {:?}", tamper);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Events picked out of the IMU's measurements.
+//!
+//! The detectors publish events as they find them, and any number of tasks up to
+//! [`SUBSCRIBERS`] can subscribe to them. A subscriber that falls behind loses the oldest events.
+
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    pubsub::{PubSubChannel, Subscriber},
+};
+
+use super::{
+    ActivityUpdate, AlarmState, FallEvent, GestureEvent, Level, Pose, Shock, Steps, Tap, Vibration,
+};
+
+/// Number of events buffered for each subscriber.
+const CAPACITY: usize = 8;
+/// Maximum number of subscribers at any one time.
+pub const SUBSCRIBERS: usize = 4;
+
+/// Something detected in the IMU's measurements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The board was tapped
+    Tap(Tap),
+    /// The board fell
+    Fall(FallEvent),
+    /// The board was hit, and the shock logged
+    Shock(Shock),
+    /// A step was counted, or walking stopped
+    Steps(Steps),
+    /// A window of vibration was analysed
+    Vibration(Vibration),
+    /// The wearer's activity changed
+    Activity(ActivityUpdate),
+    /// A gesture was recognised
+    Gesture(GestureEvent),
+    /// The tilt of the bubble level moved
+    Level(Level),
+    /// The anti-tamper alarm was armed, disarmed or triggered
+    Alarm(AlarmState),
+    /// The board came to rest on a different side
+    Pose(Pose),
+}
+
+/// A subscription to the IMU's events.
+pub type EventSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, Event, CAPACITY, SUBSCRIBERS, 1>;
+
+static EVENTS: PubSubChannel<CriticalSectionRawMutex, Event, CAPACITY, SUBSCRIBERS, 1> =
+    PubSubChannel::new();
+
+/// Subscribe to the IMU's events, if there is a subscriber slot free.
+pub fn subscribe() -> Option<EventSubscriber> {
+    EVENTS.subscriber().ok()
+}
+
+/// Publish an event to every subscriber.
+pub(crate) fn publish(event: Event) {
+    EVENTS.immediate_publisher().publish_immediate(event);
+}
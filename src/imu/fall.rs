@@ -0,0 +1,240 @@
+//! Detection of free fall, and of falls ending in an impact and stillness.
+//!
+//! While falling freely the accelerometer reads close to 0g on every axis. A drop is
+//! reported as [`FallEvent::FreeFall`] once the board is caught or lands, with how long it fell.
+//! An impact is a spike well above 1g, and when the board then lies still for a while,
+//! as a dropped board or a person lying on the ground would, it is reported as a
+//! [`FallEvent::Impact`]. An impact followed by more movement is ignored.
+
+use embassy_time::{Duration, Instant};
+
+use super::{Measurement, math};
+
+/// Something found by the fall detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FallEvent {
+    /// The board fell freely for `duration` before it was caught or landed
+    FreeFall { duration: Duration },
+    /// The board took an impact of `peak` g and then lay still
+    Impact { peak: f32 },
+}
+
+/// Tuning of the fall detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FallConfig {
+    /// Total acceleration below which the board is falling, in g
+    pub free_fall_threshold: f32,
+    /// Shortest free fall that is reported, 100ms is a drop of about 5cm
+    pub free_fall_duration: Duration,
+    /// Total acceleration that counts as an impact, in g
+    pub impact_threshold: f32,
+    /// How far the total acceleration may stray from 1g while lying still, in g
+    pub still_tolerance: f32,
+    /// How long the board must lie still after an impact
+    pub still_duration: Duration,
+    /// Time after an impact within which the board must come to rest
+    pub settle_window: Duration,
+}
+
+impl Default for FallConfig {
+    fn default() -> Self {
+        Self {
+            free_fall_threshold: 0.3,
+            free_fall_duration: Duration::from_millis(100),
+            impact_threshold: 2.5,
+            still_tolerance: 0.1,
+            still_duration: Duration::from_secs(1),
+            settle_window: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Waiting for a free fall or an impact
+    Idle,
+    /// Falling since `start`
+    Falling { start: Instant },
+    /// Hit at `at`, with the largest squared acceleration of the impact so far,
+    /// waiting for the board to come to rest
+    Impact {
+        at: Instant,
+        peak: f32,
+        still_since: Option<Instant>,
+    },
+}
+
+/// Picks free falls and falls out of a stream of measurements.
+pub struct FallDetector {
+    config: FallConfig,
+    state: State,
+}
+
+impl FallDetector {
+    /// Create a detector with the given tuning.
+    pub fn new(config: FallConfig) -> Self {
+        Self {
+            config,
+            state: State::Idle,
+        }
+    }
+
+    /// The detector's tuning.
+    pub fn config(&self) -> FallConfig {
+        self.config
+    }
+
+    /// Feed a measurement, returning anything it completes.
+    pub fn update(&mut self, meas: &Measurement) -> Option<FallEvent> {
+        let at = meas.at;
+        // compare squared magnitudes to save a square root per sample
+        let accel = meas.accel;
+        let magnitude = accel.x * accel.x + accel.y * accel.y + accel.z * accel.z;
+        let config = &self.config;
+        let falling = magnitude < config.free_fall_threshold * config.free_fall_threshold;
+        let impact = magnitude > config.impact_threshold * config.impact_threshold;
+        let still = ((1.0 - config.still_tolerance) * (1.0 - config.still_tolerance)
+            ..(1.0 + config.still_tolerance) * (1.0 + config.still_tolerance))
+            .contains(&magnitude);
+        // whatever comes after a free fall or an abandoned impact starts from here
+        let idle = if falling {
+            State::Falling { start: at }
+        } else if impact {
+            State::Impact {
+                at,
+                peak: magnitude,
+                still_since: None,
+            }
+        } else {
+            State::Idle
+        };
+
+        let (state, event) = match self.state {
+            State::Idle => (idle, None),
+            State::Falling { start } if falling => (State::Falling { start }, None),
+            State::Falling { start } => {
+                let duration = at.saturating_duration_since(start);
+                let event = (duration >= config.free_fall_duration)
+                    .then_some(FallEvent::FreeFall { duration });
+                (idle, event)
+            }
+            State::Impact { at: hit, peak, .. } if impact => (
+                State::Impact {
+                    at: hit,
+                    peak: peak.max(magnitude),
+                    still_since: None,
+                },
+                None,
+            ),
+            State::Impact {
+                at: hit,
+                peak,
+                still_since,
+            } if still => {
+                let since = still_since.unwrap_or(at);
+                if at.saturating_duration_since(since) >= config.still_duration {
+                    let peak = math::sqrt(peak);
+                    (State::Idle, Some(FallEvent::Impact { peak }))
+                } else {
+                    let still_since = Some(since);
+                    (
+                        State::Impact {
+                            at: hit,
+                            peak,
+                            still_since,
+                        },
+                        None,
+                    )
+                }
+            }
+            State::Impact { at: hit, .. }
+                if at.saturating_duration_since(hit) > config.settle_window =>
+            {
+                // kept moving, so it was not a fall
+                (idle, None)
+            }
+            State::Impact { at: hit, peak, .. } => (
+                State::Impact {
+                    at: hit,
+                    peak,
+                    still_since: None,
+                },
+                None,
+            ),
+        };
+        self.state = state;
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample period at 100 Hz.
+    const PERIOD: Duration = Duration::from_millis(10);
+    const LEVEL: [f32; 3] = [0.0, 0.0, 1.0];
+
+    /// Feed samples at 100 Hz, returning the events with the sample index they came out at.
+    fn run(samples: &[[f32; 3]]) -> std::vec::Vec<(usize, FallEvent)> {
+        let mut detector = FallDetector::new(FallConfig::default());
+        let start = Instant::from_secs(1);
+        samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &accel)| {
+                let meas = Measurement::still()
+                    .with_accel(accel)
+                    .at(start + PERIOD * i as u32);
+                detector.update(&meas).map(|event| (i, event))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drop_reports_free_fall_then_impact() {
+        let mut samples = std::vec![LEVEL; 50];
+        samples.extend([[0.0, 0.0, 0.05]; 30]);
+        samples.extend([[0.0, 0.0, 4.0], [0.0, 0.0, 3.0]]);
+        samples.extend([LEVEL; 200]);
+        let events = run(&samples);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0],
+            (
+                80,
+                FallEvent::FreeFall {
+                    duration: Duration::from_millis(300)
+                }
+            )
+        );
+        // a second after it came to rest
+        let (182, FallEvent::Impact { peak }) = events[1] else {
+            panic!("unexpected {:?}", events[1]);
+        };
+        assert!((peak - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn ignores_short_dips() {
+        let mut samples = std::vec![LEVEL; 50];
+        samples.extend([[0.0, 0.0, 0.05]; 5]);
+        samples.extend([LEVEL; 50]);
+        assert!(run(&samples).is_empty());
+    }
+
+    #[test]
+    fn ignores_impacts_followed_by_movement() {
+        let mut samples = std::vec![LEVEL; 50];
+        samples.push([3.0, 0.0, 1.0]);
+        samples.extend((0..300).map(|i| {
+            if i % 2 == 0 {
+                [1.5, 0.0, 1.0]
+            } else {
+                [-0.5, 0.0, 1.0]
+            }
+        }));
+        samples.extend([LEVEL; 200]);
+        assert!(run(&samples).is_empty());
+    }
+}
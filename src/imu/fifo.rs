@@ -0,0 +1,178 @@
+//! Decoding of the ICM42670 FIFO stream.
+//!
+//! The FIFO is configured for accelerometer, gyroscope and timestamp data, so every
+//! sample arrives as a 16 byte "packet 3":
+//!
+//! | Byte  | Content                                   |
+//! | ----- | ----------------------------------------- |
+//! | 0     | Header                                    |
+//! | 1-6   | Accelerometer X, Y, Z (big-endian i16)    |
+//! | 7-12  | Gyroscope X, Y, Z (big-endian i16)        |
+//! | 13    | Temperature (°C = raw / 2 + 25)           |
+//! | 14-15 | Timestamp (big-endian u16, 16µs per tick) |
+//!
+//! Packets with only one of the sensors enabled are shorter, and are decoded too.
+
+use embassy_time::Duration;
+
+/// Size of a packet holding both accelerometer and gyroscope data.
+pub const PACKET_LEN: usize = 16;
+/// Size of the FIFO in bytes.
+pub const FIFO_SIZE: usize = 2048;
+
+/// Resolution of the FIFO timestamp with `TMST_RES` set.
+const TICK: Duration = Duration::from_micros(16);
+/// Value of an axis the sensor could not sample in time.
+const INVALID: i16 = i16::MIN;
+
+const HEADER_MSG: u8 = 1 << 7;
+const HEADER_ACCEL: u8 = 1 << 6;
+const HEADER_GYRO: u8 = 1 << 5;
+const HEADER_20: u8 = 1 << 4;
+const HEADER_TIMESTAMP: u8 = 0b10 << 2;
+const HEADER_TIMESTAMP_MASK: u8 = 0b11 << 2;
+
+/// A single decoded FIFO packet, in raw sensor units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Packet {
+    /// Raw accelerometer sample, if present and valid
+    pub accel: Option<[i16; 3]>,
+    /// Raw gyroscope sample, if present and valid
+    pub gyro: Option<[i16; 3]>,
+    /// Die temperature in °C
+    pub temperature: f32,
+    /// Sensor timestamp counter, if present
+    pub timestamp: Option<u16>,
+}
+
+/// Iterator over the packets in a burst read from `FIFO_DATA`.
+pub struct Packets<'a>(&'a [u8]);
+
+/// Decode the packets in a burst read from `FIFO_DATA`.
+///
+/// Decoding stops at the first empty or unsupported packet, or at a truncated tail.
+pub fn decode(bytes: &[u8]) -> Packets<'_> {
+    Packets(bytes)
+}
+
+impl Iterator for Packets<'_> {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Packet> {
+        let &header = self.0.first()?;
+        if header & (HEADER_MSG | HEADER_20) != 0 {
+            return None;
+        }
+        let has_accel = header & HEADER_ACCEL != 0;
+        let has_gyro = header & HEADER_GYRO != 0;
+        let len = match (has_accel, has_gyro) {
+            (true, true) => PACKET_LEN,
+            (true, false) | (false, true) => 8,
+            (false, false) => return None,
+        };
+        let packet = self.0.get(..len)?;
+        self.0 = &self.0[len..];
+
+        let vector = |offset: usize| {
+            let raw: [i16; 3] = core::array::from_fn(|i| {
+                i16::from_be_bytes([packet[offset + 2 * i], packet[offset + 2 * i + 1]])
+            });
+            (!raw.contains(&INVALID)).then_some(raw)
+        };
+        let (accel, gyro) = match (has_accel, has_gyro) {
+            (true, true) => (vector(1), vector(7)),
+            (true, false) => (vector(1), None),
+            _ => (None, vector(1)),
+        };
+        let temperature = packet[if len == PACKET_LEN { 13 } else { 7 }] as i8;
+        let timestamp = (len == PACKET_LEN && header & HEADER_TIMESTAMP_MASK == HEADER_TIMESTAMP)
+            .then(|| u16::from_be_bytes([packet[14], packet[15]]));
+        Some(Packet {
+            accel,
+            gyro,
+            temperature: temperature as f32 / 2.0 + 25.0,
+            timestamp,
+        })
+    }
+}
+
+/// Unwraps the 16 bit FIFO timestamp counter into a monotonic elapsed time.
+///
+/// The counter wraps roughly every second, so it must be fed at least that often,
+/// which holds for every supported output data rate.
+#[derive(Default)]
+pub struct Clock {
+    /// Last raw counter value seen
+    last: Option<u16>,
+    /// Time elapsed since the first sample
+    elapsed: Duration,
+}
+
+impl Clock {
+    /// Time elapsed between the first sample and the one with this counter value.
+    pub fn elapsed(&mut self, timestamp: u16) -> Duration {
+        if let Some(last) = self.last {
+            self.elapsed += TICK * timestamp.wrapping_sub(last) as u32;
+        }
+        self.last = Some(timestamp);
+        self.elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(accel: [i16; 3], gyro: [i16; 3], temperature: i8, timestamp: u16) -> [u8; 16] {
+        let mut bytes = [0; PACKET_LEN];
+        bytes[0] = HEADER_ACCEL | HEADER_GYRO | HEADER_TIMESTAMP | 0b11;
+        for i in 0..3 {
+            bytes[1 + 2 * i..3 + 2 * i].copy_from_slice(&accel[i].to_be_bytes());
+            bytes[7 + 2 * i..9 + 2 * i].copy_from_slice(&gyro[i].to_be_bytes());
+        }
+        bytes[13] = temperature as u8;
+        bytes[14..].copy_from_slice(&timestamp.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn decodes_packets_in_order() {
+        let mut burst = [0; 2 * PACKET_LEN];
+        burst[..PACKET_LEN].copy_from_slice(&packet([1, 2, 3], [4, 5, 6], 10, 100));
+        burst[PACKET_LEN..].copy_from_slice(&packet([-1, -2, -3], [-4, -5, -6], -10, 200));
+        let packets: std::vec::Vec<_> = decode(&burst).collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].accel, Some([1, 2, 3]));
+        assert_eq!(packets[0].gyro, Some([4, 5, 6]));
+        assert_eq!(packets[0].temperature, 30.0);
+        assert_eq!(packets[0].timestamp, Some(100));
+        assert_eq!(packets[1].accel, Some([-1, -2, -3]));
+        assert_eq!(packets[1].temperature, 20.0);
+        assert_eq!(packets[1].timestamp, Some(200));
+    }
+
+    #[test]
+    fn stops_at_empty_or_truncated_packets() {
+        let mut burst = [0; PACKET_LEN + 4];
+        burst[..PACKET_LEN].copy_from_slice(&packet([1, 2, 3], [4, 5, 6], 0, 0));
+        burst[PACKET_LEN] = HEADER_ACCEL | HEADER_GYRO;
+        assert_eq!(decode(&burst).count(), 1);
+        assert_eq!(decode(&[HEADER_MSG; PACKET_LEN]).count(), 0);
+    }
+
+    #[test]
+    fn flags_invalid_samples() {
+        let bytes = packet([INVALID, 0, 0], [1, 1, 1], 0, 0);
+        let packet = decode(&bytes).next().unwrap();
+        assert_eq!(packet.accel, None);
+        assert_eq!(packet.gyro, Some([1, 1, 1]));
+    }
+
+    #[test]
+    fn clock_unwraps_counter() {
+        let mut clock = Clock::default();
+        assert_eq!(clock.elapsed(65_000), Duration::from_ticks(0));
+        assert_eq!(clock.elapsed(65_500), Duration::from_micros(500 * 16));
+        assert_eq!(clock.elapsed(464), Duration::from_micros(1000 * 16));
+    }
+}
@@ -0,0 +1,406 @@
+//! Gesture recognition with a small fixed-point neural network.
+//!
+//! The last [`GestureModel::window`] samples of acceleration and angular rate, taken at
+//! the model's own sample rate, are quantised to i8 and run through a multilayer
+//! perceptron with one hidden ReLU layer. All arithmetic is integer apart from the final
+//! softmax that turns the logits into a confidence.
+//!
+//! The weights are not compiled in but parsed from a binary blob, so that a retrained
+//! model can be flashed on its own. The blob is little-endian:
+//!
+//! | Offset | Content                                                        |
+//! | ------ | -------------------------------------------------------------- |
+//! | 0      | Magic `GST1` (u32)                                             |
+//! | 4      | Window length in samples (u16)                                 |
+//! | 6      | Sample rate in Hz (u16)                                        |
+//! | 8      | Hidden units (u16)                                             |
+//! | 10     | Outputs (u8), one per [`CLASSES`]                              |
+//! | 11     | Right shift from hidden accumulators to activations (u8)       |
+//! | 12     | Scale from output accumulators to logits (f32)                 |
+//! | 16     | Hidden weights (i8), a row of window × 6 inputs per unit       |
+//! |        | Hidden biases (i32)                                            |
+//! |        | Output weights (i8), a row of hidden units per output          |
+//! |        | Output biases (i32)                                            |
+//! |        | FNV-1a checksum of everything before it (u32)                  |
+//!
+//! Inputs are ordered by sample, oldest first, then by channel: acceleration X, Y, Z
+//! scaled so that ±[`ACCEL_SCALE`] g is ±127, then angular rate X, Y, Z scaled so that
+//! ±[`GYRO_SCALE`] °/s is ±127. `tools/gesture_model.py` packs trained weights.
+
+use embassy_time::{Duration, Instant};
+use embedded_storage::nor_flash::ReadNorFlash;
+
+use super::Measurement;
+use super::calibration::fnv1a;
+use crate::AppError;
+
+/// Marks a gesture model blob, and its layout version.
+const MAGIC: u32 = 0x3154_5347; // "GST1"
+/// Size of the blob header in bytes.
+const HEADER_LEN: usize = 16;
+/// Inputs per sample: acceleration and angular rate.
+const CHANNELS: usize = 6;
+/// Longest window a model can use, in samples.
+pub const MAX_WINDOW: usize = 64;
+/// Most hidden units a model can have.
+pub const MAX_HIDDEN: usize = 64;
+/// Largest model blob, in bytes.
+pub const MAX_MODEL_LEN: usize = 8192;
+/// Acceleration quantised to ±127, in g.
+pub const ACCEL_SCALE: f32 = 4.0;
+/// Angular rate quantised to ±127, in °/s.
+pub const GYRO_SCALE: f32 = 1000.0;
+
+/// A recognised gesture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Shake,
+    Circle,
+    FlickLeft,
+    FlickRight,
+}
+
+/// What each output of a model stands for, the first being no gesture.
+pub const CLASSES: [Option<Gesture>; 5] = [
+    None,
+    Some(Gesture::Shake),
+    Some(Gesture::Circle),
+    Some(Gesture::FlickLeft),
+    Some(Gesture::FlickRight),
+];
+
+/// A gesture recognised with some confidence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureEvent {
+    pub gesture: Gesture,
+    /// Softmax probability of the gesture, from 0 to 1
+    pub confidence: f32,
+}
+
+/// The weights of a gesture network, borrowed from a model blob.
+#[derive(Clone, Copy)]
+pub struct GestureModel<'a> {
+    window: usize,
+    sample_rate: u16,
+    hidden: usize,
+    shift: u8,
+    output_scale: f32,
+    hidden_weights: &'a [u8],
+    hidden_biases: &'a [u8],
+    output_weights: &'a [u8],
+    output_biases: &'a [u8],
+}
+
+impl<'a> GestureModel<'a> {
+    /// Parse and check a model blob.
+    pub fn parse(blob: &'a [u8]) -> Result<Self, AppError> {
+        let header = blob
+            .get(..HEADER_LEN)
+            .ok_or(AppError::InvalidGestureModel)?;
+        let half = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]) as usize;
+        let word =
+            |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+        let (window, sample_rate, hidden) = (half(4), half(6) as u16, half(8));
+        let (outputs, shift, output_scale) =
+            (header[10] as usize, header[11], f32::from_bits(word(12)));
+        let valid = word(0) == MAGIC
+            && sample_rate > 0
+            && outputs == CLASSES.len()
+            && shift < 32
+            && output_scale.is_finite()
+            && output_scale > 0.0;
+        if !valid || Self::blob_len(window, hidden) != Some(blob.len()) {
+            return Err(AppError::InvalidGestureModel);
+        }
+        let (body, checksum) = blob.split_at(blob.len() - 4);
+        if u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != fnv1a(body) {
+            return Err(AppError::InvalidGestureModel);
+        }
+        let (hidden_weights, rest) = body[HEADER_LEN..].split_at(hidden * window * CHANNELS);
+        let (hidden_biases, rest) = rest.split_at(hidden * 4);
+        let (output_weights, output_biases) = rest.split_at(outputs * hidden);
+        Ok(Self {
+            window,
+            sample_rate,
+            hidden,
+            shift,
+            output_scale,
+            hidden_weights,
+            hidden_biases,
+            output_weights,
+            output_biases,
+        })
+    }
+
+    /// Load the model blob stored at `offset` into `buffer`, which must be large enough
+    /// to hold it, and parse it.
+    pub fn load<F: ReadNorFlash>(
+        flash: &mut F,
+        offset: u32,
+        buffer: &'a mut [u8],
+    ) -> Result<Self, AppError> {
+        let mut header = [0; HEADER_LEN];
+        flash
+            .read(offset, &mut header)
+            .map_err(|_| AppError::GestureModelLoad)?;
+        let half = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]) as usize;
+        let blob = Self::blob_len(half(4), half(8))
+            .and_then(|len| buffer.get_mut(..len))
+            .ok_or(AppError::InvalidGestureModel)?;
+        flash
+            .read(offset, blob)
+            .map_err(|_| AppError::GestureModelLoad)?;
+        Self::parse(blob)
+    }
+
+    /// Size of a blob with the given window and hidden units, if they are supported.
+    fn blob_len(window: usize, hidden: usize) -> Option<usize> {
+        if !(1..=MAX_WINDOW).contains(&window) || !(1..=MAX_HIDDEN).contains(&hidden) {
+            return None;
+        }
+        let outputs = CLASSES.len();
+        Some(HEADER_LEN + hidden * (window * CHANNELS + 4) + outputs * (hidden + 4) + 4)
+    }
+
+    /// Number of samples the model looks at.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Time between the samples the model looks at.
+    pub fn sample_period(&self) -> Duration {
+        Duration::from_hz(self.sample_rate as u64)
+    }
+
+    /// Run the network over a window of quantised inputs, returning the logit of each class.
+    fn infer(&self, inputs: &[i8]) -> [f32; CLASSES.len()] {
+        let mut activations = [0i8; MAX_HIDDEN];
+        let activations = &mut activations[..self.hidden];
+        let inputs_per_unit = self.window * CHANNELS;
+        for (unit, activation) in activations.iter_mut().enumerate() {
+            let row = &self.hidden_weights[unit * inputs_per_unit..][..inputs_per_unit];
+            let sum = dot(row, inputs) + bias(self.hidden_biases, unit);
+            *activation = (sum >> self.shift).clamp(0, i8::MAX as i32) as i8;
+        }
+        core::array::from_fn(|output| {
+            let row = &self.output_weights[output * self.hidden..][..self.hidden];
+            let sum = dot(row, activations) + bias(self.output_biases, output);
+            sum as f32 * self.output_scale
+        })
+    }
+}
+
+/// Dot product of a row of i8 weights with i8 inputs.
+fn dot(weights: &[u8], inputs: &[i8]) -> i32 {
+    weights
+        .iter()
+        .zip(inputs)
+        .map(|(&weight, &input)| weight as i8 as i32 * input as i32)
+        .sum()
+}
+
+/// The i32 bias at `index`.
+fn bias(biases: &[u8], index: usize) -> i32 {
+    let bytes = &biases[index * 4..][..4];
+    i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Quantise a value to i8, with `scale` mapping to 127.
+fn quantise(value: f32, scale: f32) -> i8 {
+    (value / scale * i8::MAX as f32).clamp(-127.0, 127.0) as i8
+}
+
+/// Tuning of the gesture recognition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureConfig {
+    /// Confidence needed to report a gesture
+    pub min_confidence: f32,
+    /// Samples between each run of the network
+    pub stride: usize,
+    /// Time after a gesture before the next can be recognised
+    pub cooldown: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            min_confidence: 0.8,
+            stride: 4,
+            cooldown: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Runs a gesture model over the measurement stream.
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    model: GestureModel<'static>,
+    /// Quantised samples of the window, oldest first
+    samples: [[i8; CHANNELS]; MAX_WINDOW],
+    len: usize,
+    /// Samples taken since the network last ran
+    since_run: usize,
+    last_sample: Option<Instant>,
+    last_gesture: Option<Instant>,
+}
+
+impl GestureRecognizer {
+    /// Create a recogniser for the given model.
+    pub fn new(model: GestureModel<'static>, config: GestureConfig) -> Self {
+        Self {
+            config,
+            model,
+            samples: [[0; CHANNELS]; MAX_WINDOW],
+            len: 0,
+            since_run: 0,
+            last_sample: None,
+            last_gesture: None,
+        }
+    }
+
+    /// The recogniser's tuning.
+    pub fn config(&self) -> GestureConfig {
+        self.config
+    }
+
+    /// Feed a measurement, returning any gesture it completes.
+    ///
+    /// Measurements arriving faster than the model's sample rate are skipped.
+    pub fn update(&mut self, meas: &Measurement) -> Option<GestureEvent> {
+        let at = meas.at;
+        let period = self.model.sample_period();
+        // allow for jitter in the incoming samples
+        let due = self
+            .last_sample
+            .is_none_or(|last| at.saturating_duration_since(last) >= period - period / 4);
+        if !due {
+            return None;
+        }
+        self.last_sample = Some(at);
+        let (a, g) = (meas.accel, meas.gyro);
+        let sample = [
+            quantise(a.x, ACCEL_SCALE),
+            quantise(a.y, ACCEL_SCALE),
+            quantise(a.z, ACCEL_SCALE),
+            quantise(g.x, GYRO_SCALE),
+            quantise(g.y, GYRO_SCALE),
+            quantise(g.z, GYRO_SCALE),
+        ];
+        let window = self.model.window;
+        if self.len == window {
+            self.samples.copy_within(1..window, 0);
+            self.len -= 1;
+        }
+        self.samples[self.len] = sample;
+        self.len += 1;
+        self.since_run += 1;
+
+        let cooling = self
+            .last_gesture
+            .is_some_and(|last| at.saturating_duration_since(last) < self.config.cooldown);
+        if self.len < window || self.since_run < self.config.stride || cooling {
+            return None;
+        }
+        self.since_run = 0;
+        let logits = self.model.infer(self.samples[..window].as_flattened());
+        let (class, best) = logits
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let total: f32 = logits
+            .iter()
+            .map(|logit| micromath::F32Ext::exp(logit - best))
+            .sum();
+        let event = GestureEvent {
+            gesture: CLASSES[class]?,
+            confidence: 1.0 / total,
+        };
+        if event.confidence < self.config.min_confidence {
+            return None;
+        }
+        // start afresh so the same movement is not recognised twice
+        self.last_gesture = Some(at);
+        self.len = 0;
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample period at 100 Hz, twice the test model's rate.
+    const PERIOD: Duration = Duration::from_millis(10);
+    const WINDOW: usize = 8;
+
+    /// A model with two hidden units summing the yaw rate either way, over 8 samples at
+    /// 50Hz. Turning left is a left flick and turning right a right flick.
+    fn blob() -> std::vec::Vec<u8> {
+        let mut blob = std::vec::Vec::new();
+        blob.extend(MAGIC.to_le_bytes());
+        blob.extend((WINDOW as u16).to_le_bytes());
+        blob.extend(50u16.to_le_bytes());
+        blob.extend(2u16.to_le_bytes());
+        blob.extend([CLASSES.len() as u8, 3]);
+        blob.extend(0.1f32.to_le_bytes());
+        for sign in [1i8, -1] {
+            for _ in 0..WINDOW {
+                blob.extend([0, 0, 0, 0, 0, sign as u8]);
+            }
+        }
+        blob.extend([0i32, 0].iter().flat_map(|b| b.to_le_bytes()));
+        for weights in [[0i8, 0], [0, 0], [0, 0], [1, 0], [0, 1]] {
+            blob.extend(weights.map(|w| w as u8));
+        }
+        blob.extend([20i32, 0, 0, 0, 0].iter().flat_map(|b| b.to_le_bytes()));
+        blob.extend(fnv1a(&blob).to_le_bytes());
+        blob
+    }
+
+    /// Feed a second of turning at `yaw` °/s, returning every gesture.
+    fn turn(recognizer: &mut GestureRecognizer, yaw: f32) -> std::vec::Vec<GestureEvent> {
+        let start = Instant::from_secs(1);
+        (0..100)
+            .filter_map(|i| {
+                let meas = Measurement::still()
+                    .with_gyro([0.0, 0.0, yaw])
+                    .at(start + PERIOD * i as u32);
+                recognizer.update(&meas)
+            })
+            .collect()
+    }
+
+    fn recognizer() -> GestureRecognizer {
+        let blob = std::boxed::Box::leak(blob().into_boxed_slice());
+        let model = GestureModel::parse(blob).unwrap();
+        GestureRecognizer::new(model, GestureConfig::default())
+    }
+
+    #[test]
+    fn recognises_gestures() {
+        let left = turn(&mut recognizer(), 500.0);
+        assert_eq!(left[0].gesture, Gesture::FlickLeft);
+        assert!(left[0].confidence > 0.95, "{:?}", left[0]);
+        let right = turn(&mut recognizer(), -500.0);
+        assert_eq!(right[0].gesture, Gesture::FlickRight);
+    }
+
+    #[test]
+    fn ignores_stillness_and_cools_down() {
+        assert!(turn(&mut recognizer(), 0.0).is_empty());
+        // a second of turning is at most two gestures, half a second apart
+        let gestures = turn(&mut recognizer(), 500.0);
+        assert!((1..=2).contains(&gestures.len()), "{:?}", gestures);
+    }
+
+    #[test]
+    fn rejects_invalid_blobs() {
+        let mut blob = blob();
+        assert!(GestureModel::parse(&blob).is_ok());
+        assert!(GestureModel::parse(&blob[..blob.len() - 1]).is_err());
+        assert!(GestureModel::parse(&[0xFF; 64]).is_err());
+        blob[HEADER_LEN] ^= 1;
+        assert!(GestureModel::parse(&blob).is_err());
+    }
+}
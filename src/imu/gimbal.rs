@@ -0,0 +1,292 @@
+//! Sensor fusion of the accelerometer and gyroscope into an orientation.
+//!
+//! The filter is fed the time elapsed between consecutive samples, taken from the sensor's
+//! own timestamps in FIFO mode, so that precision does not degrade with uptime.
+
+use embassy_time::{Duration, Instant};
+use icm42670::accelerometer::vector::F32x3;
+use imu_fusion::{Fusion, FusionAhrsSettings, FusionQuaternion, FusionVector};
+// std provides these methods itself in host-side tests, and core on newer toolchains
+#[cfg(not(test))]
+#[allow(unused_imports)]
+use micromath::F32Ext;
+
+pub struct Gimbal {
+    /// The fusion filter
+    fusion: Fusion,
+    /// Time between samples at the configured rate
+    period: Duration,
+    /// Time of the previous sample
+    last: Option<Instant>,
+}
+
+/// Tuning of the attitude and heading reference system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AhrsConfig {
+    /// How strongly the accelerometer corrects the gyroscope, 0 for gyroscope only
+    pub gain: f32,
+    /// Gyroscope range in °/s, above which the filter re-initialises; should match
+    /// the [`ImuConfig`](super::ImuConfig) range, 0 to disable
+    pub gyro_range: f32,
+    /// Angle in degrees beyond which an accelerometer reading is treated as
+    /// linear acceleration and rejected, 0 to disable
+    pub acceleration_rejection: f32,
+    /// How long readings may be rejected before the filter forces a recovery
+    pub recovery_trigger_period: Duration,
+}
+
+impl Default for AhrsConfig {
+    fn default() -> Self {
+        Self {
+            gain: 0.5,
+            gyro_range: 2000.0,
+            acceleration_rejection: 10.0,
+            recovery_trigger_period: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Unit quaternion rotating the sensor frame into the earth frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+/// Tait-Bryan angles in degrees, applied in yaw, pitch, roll order.
+///
+/// Without a magnetometer the yaw is relative to the heading at start-up, and drifts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Euler {
+    /// Rotation about the X axis, ±180°
+    pub roll: f32,
+    /// Rotation about the Y axis, ±90°
+    pub pitch: f32,
+    /// Rotation about the Z axis, ±180°
+    pub yaw: f32,
+}
+
+/// Full orientation of the board from the fusion filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orientation {
+    pub quaternion: Quaternion,
+    pub euler: Euler,
+}
+
+impl Quaternion {
+    /// Rotate a vector from the sensor frame into the earth frame.
+    pub fn to_earth(&self, v: F32x3) -> F32x3 {
+        let Quaternion { w, x, y, z } = *self;
+        // rows of the rotation matrix of the quaternion
+        F32x3 {
+            x: (1.0 - 2.0 * (y * y + z * z)) * v.x
+                + 2.0 * (x * y - w * z) * v.y
+                + 2.0 * (x * z + w * y) * v.z,
+            y: 2.0 * (x * y + w * z) * v.x
+                + (1.0 - 2.0 * (x * x + z * z)) * v.y
+                + 2.0 * (y * z - w * x) * v.z,
+            z: 2.0 * (x * z - w * y) * v.x
+                + 2.0 * (y * z + w * x) * v.y
+                + (1.0 - 2.0 * (x * x + y * y)) * v.z,
+        }
+    }
+
+    /// Acceleration in the earth frame with gravity removed, in g, from an
+    /// accelerometer reading in the sensor frame.
+    pub fn linear_acceleration(&self, accel: F32x3) -> F32x3 {
+        let earth = self.to_earth(accel);
+        F32x3 {
+            z: earth.z - 1.0,
+            ..earth
+        }
+    }
+}
+
+impl From<Quaternion> for Euler {
+    fn from(q: Quaternion) -> Self {
+        let Quaternion { w, x, y, z } = q;
+        let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+        let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+        let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+        Self {
+            roll: roll.to_degrees(),
+            pitch: pitch.to_degrees(),
+            yaw: yaw.to_degrees(),
+        }
+    }
+}
+
+impl Gimbal {
+    /// Create a new gimbal with a period between measurements
+    pub fn new(period: Duration, config: &AhrsConfig) -> Self {
+        Self {
+            fusion: Self::fusion(period, config),
+            period,
+            last: None,
+        }
+    }
+
+    /// Apply new AHRS settings, which restarts the filter.
+    pub fn set_config(&mut self, config: &AhrsConfig) {
+        self.fusion = Self::fusion(self.period, config);
+        self.last = None;
+    }
+
+    fn fusion(period: Duration, config: &AhrsConfig) -> Fusion {
+        // the fusion filter expects the sample rate in Hz, and the recovery period in samples
+        let sample_rate = 1_000_000 / period.as_micros().max(1);
+        let recovery = config.recovery_trigger_period.as_micros() / period.as_micros().max(1);
+        let mut settings = FusionAhrsSettings::new();
+        settings.gain = config.gain;
+        settings.gyr_range = config.gyro_range;
+        settings.acc_rejection = config.acceleration_rejection;
+        settings.recovery_trigger_period = recovery as i32;
+        Fusion::new(sample_rate as u32, settings)
+    }
+
+    /// Seconds since the previous sample, or the nominal period for the first one
+    /// and for any gap the filter should not integrate over.
+    fn delta_time(&mut self, at: Instant) -> f32 {
+        let nominal = self.period;
+        let delta = self
+            .last
+            .and_then(|last| at.checked_duration_since(last))
+            .filter(|delta| *delta > Duration::from_ticks(0) && *delta < nominal * 10)
+            .unwrap_or(nominal);
+        self.last = Some(at);
+        delta.as_micros() as f32 / 1_000_000.0
+    }
+
+    /// Read the sensor data sampled at a given time and calculate the inclination
+    /// and full orientation.
+    pub fn read_at(&mut self, gyro: F32x3, accel: F32x3, at: Instant) -> (F32x3, Orientation) {
+        let delta_time = self.delta_time(at);
+        // our sensor does not have a magnetometer
+        self.fusion.update_no_mag_by_duration_seconds(
+            FusionVector::new(gyro.x, gyro.y, gyro.z),
+            FusionVector::new(accel.x, accel.y, accel.z),
+            delta_time,
+        );
+        let quaternion = self.fusion.quaternion();
+        let fusion_accel = Self::quaternion_to_acceleration(&quaternion);
+        let FusionQuaternion { w, x, y, z } = quaternion;
+        let quaternion = Quaternion { w, x, y, z };
+        let orientation = Orientation {
+            quaternion,
+            euler: quaternion.into(),
+        };
+        (Self::inclination(fusion_accel), orientation)
+    }
+
+    /// Calculate the inclination of the sensor in degrees
+    fn inclination(accel: F32x3) -> F32x3 {
+        let F32x3 { x, y, z } = accel;
+        let (x_sq, y_sq, z_sq) = (x.powi(2), y.powi(2), z.powi(2));
+        F32x3 {
+            x: (x.atan2((y_sq + z_sq).sqrt())).to_degrees(),
+            y: (y.atan2((x_sq + z_sq).sqrt())).to_degrees(),
+            z: (z.atan2((x_sq + y_sq).sqrt())).to_degrees(),
+        }
+    }
+
+    /// Convert a quaternion to a 3D acceleration vector.
+    fn quaternion_to_acceleration(quaternion: &FusionQuaternion) -> F32x3 {
+        let FusionQuaternion { x, y, z, w } = quaternion;
+        F32x3 {
+            x: 2.0 * (x * z - w * y),
+            y: 2.0 * (y * z + w * x),
+            z: w.powi(2) - x.powi(2) - y.powi(2) + z.powi(2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_euler(actual: Euler, roll: f32, pitch: f32, yaw: f32) {
+        assert!((actual.roll - roll).abs() < 0.1, "roll {}", actual.roll);
+        assert!((actual.pitch - pitch).abs() < 0.1, "pitch {}", actual.pitch);
+        assert!((actual.yaw - yaw).abs() < 0.1, "yaw {}", actual.yaw);
+    }
+
+    #[test]
+    fn delta_time_follows_timestamps() {
+        let period = Duration::from_millis(10);
+        let mut gimbal = Gimbal::new(period, &AhrsConfig::default());
+        // a day of uptime would have lost sub-millisecond precision as f32 seconds
+        let start = Instant::from_secs(86_400);
+        assert_eq!(gimbal.delta_time(start), 0.01);
+        assert_eq!(
+            gimbal.delta_time(start + Duration::from_micros(9_875)),
+            0.009875
+        );
+        // gaps and out of order samples fall back to the nominal period
+        assert_eq!(gimbal.delta_time(start + Duration::from_secs(5)), 0.01);
+        assert_eq!(gimbal.delta_time(start), 0.01);
+    }
+
+    #[test]
+    fn identity_is_level() {
+        let q = Quaternion {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert_euler(q.into(), 0.0, 0.0, 0.0);
+    }
+
+    #[test]
+    fn single_axis_rotations() {
+        let half = core::f32::consts::FRAC_1_SQRT_2;
+        let about_x = Quaternion {
+            w: half,
+            x: half,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert_euler(about_x.into(), 90.0, 0.0, 0.0);
+        let about_y = Quaternion {
+            w: half,
+            x: 0.0,
+            y: half,
+            z: 0.0,
+        };
+        assert_euler(about_y.into(), 0.0, 90.0, 0.0);
+        let about_z = Quaternion {
+            w: half,
+            x: 0.0,
+            y: 0.0,
+            z: -half,
+        };
+        assert_euler(about_z.into(), 0.0, 0.0, -90.0);
+    }
+
+    #[test]
+    fn linear_acceleration_removes_gravity() {
+        let half = core::f32::consts::FRAC_1_SQRT_2;
+        // rolled onto its side, so gravity reads along the sensor's Y axis
+        let rolled = Quaternion {
+            w: half,
+            x: half,
+            y: 0.0,
+            z: 0.0,
+        };
+        let assert_near = |actual: F32x3, [x, y, z]: [f32; 3]| {
+            let error = (actual.x - x).abs() + (actual.y - y).abs() + (actual.z - z).abs();
+            assert!(error < 1e-5, "{:?}", actual);
+        };
+        let still = F32x3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        assert_near(rolled.linear_acceleration(still), [0.0, 0.0, 0.0]);
+        // pushed along the sensor's Z axis, which now points along -Y of the earth
+        let pushed = F32x3 { z: 0.5, ..still };
+        assert_near(rolled.linear_acceleration(pushed), [0.0, -0.5, 0.0]);
+    }
+}
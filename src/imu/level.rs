@@ -0,0 +1,202 @@
+//! A bubble level, measuring how far the board is tilted from a reference attitude.
+//!
+//! The tilt is the angle between gravity now and gravity in the reference, taken from
+//! the gimbal's inclination, so it is the same whichever way the board is tilted.
+//! The reference starts as the board lying flat, face up, and [`tare_level`] captures
+//! the current attitude in its place, for levelling something that is not flat itself.
+//!
+//! A new [`Level`] is only reported once the tilt has moved by [`LevelConfig::step`],
+//! so a board sitting still does not flood the application with readings.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use icm42670::accelerometer::vector::F32x3;
+use micromath::F32Ext;
+
+use super::{Measurement, math};
+
+/// Direction of gravity when the board lies flat, face up.
+const FLAT: [f32; 3] = [0.0, 0.0, 1.0];
+
+/// How level the board is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    /// Angle from the reference attitude, in degrees
+    pub tilt: f32,
+    /// How far off level, from 0 within the tolerance up to 1 at the full tilt
+    pub severity: f32,
+}
+
+impl Level {
+    /// Whether the board is level within the tolerance.
+    pub fn is_level(&self) -> bool {
+        self.severity == 0.0
+    }
+}
+
+/// Tuning of the bubble level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelConfig {
+    /// Tilt still counted as level, in degrees
+    pub tolerance: f32,
+    /// Tilt at which the severity reaches 1, in degrees
+    pub full_tilt: f32,
+    /// Change of tilt needed to report a new reading, in degrees
+    pub step: f32,
+}
+
+impl Default for LevelConfig {
+    fn default() -> Self {
+        Self {
+            tolerance: 1.0,
+            full_tilt: 20.0,
+            step: 0.25,
+        }
+    }
+}
+
+/// Measures the tilt from a reference attitude.
+pub struct BubbleLevel {
+    config: LevelConfig,
+    /// Direction of gravity in the reference attitude, as a unit vector
+    reference: [f32; 3],
+    /// Tilt last reported
+    reported: Option<f32>,
+}
+
+impl BubbleLevel {
+    /// Create a level with the given tuning, referenced to the board lying flat.
+    pub fn new(config: LevelConfig) -> Self {
+        Self {
+            config,
+            reference: FLAT,
+            reported: None,
+        }
+    }
+
+    /// The level's tuning.
+    pub fn config(&self) -> LevelConfig {
+        self.config
+    }
+
+    /// Take `inclination` as the reference attitude, so it reads as level from now on.
+    pub fn tare(&mut self, inclination: F32x3) {
+        self.reference = gravity(inclination);
+        self.reported = None;
+    }
+
+    /// Go back to referencing the board lying flat.
+    pub fn reset(&mut self) {
+        self.reference = FLAT;
+        self.reported = None;
+    }
+
+    /// The tilt and severity of an inclination.
+    pub fn level(&self, inclination: F32x3) -> Level {
+        let [x, y, z] = gravity(inclination);
+        let [rx, ry, rz] = self.reference;
+        let cross = [y * rz - z * ry, z * rx - x * rz, x * ry - y * rx];
+        let sine = math::sqrt(cross.iter().map(|c| c * c).sum::<f32>());
+        let cosine = x * rx + y * ry + z * rz;
+        // atan2 keeps its precision near level, where acos of the dot product would not
+        let tilt = F32Ext::atan2(sine, cosine).to_degrees();
+        let LevelConfig {
+            tolerance,
+            full_tilt,
+            ..
+        } = self.config;
+        let severity = (tilt - tolerance) / (full_tilt - tolerance).max(f32::EPSILON);
+        Level {
+            tilt,
+            severity: severity.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Feed a measurement, returning the level once the tilt has moved by a step.
+    ///
+    /// A tare requested with [`tare_level`] is applied first, and always reported.
+    pub fn update(&mut self, meas: &Measurement) -> Option<Level> {
+        let inclination = meas.inclination?;
+        if TARE.lock(|tare| tare.replace(false)) {
+            self.tare(inclination);
+        }
+        let level = self.level(inclination);
+        let moved = self
+            .reported
+            .is_none_or(|tilt| F32Ext::abs(level.tilt - tilt) >= self.config.step);
+        if moved {
+            self.reported = Some(level.tilt);
+            return Some(level);
+        }
+        None
+    }
+}
+
+/// Direction of gravity from an inclination, as a unit vector.
+pub(super) fn gravity(inclination: F32x3) -> [f32; 3] {
+    // each angle is between its axis and the horizontal, so its sine is the axis's share
+    [inclination.x, inclination.y, inclination.z].map(|angle| F32Ext::sin(angle.to_radians()))
+}
+
+static TARE: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Ask the running bubble level to take the next attitude as its reference.
+pub fn tare_level() {
+    TARE.lock(|tare| tare.set(true));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inclination of the board tilted by `degrees` about its X axis.
+    fn tilted(degrees: f32) -> F32x3 {
+        F32x3 {
+            x: 0.0,
+            y: degrees,
+            z: 90.0 - degrees.abs(),
+        }
+    }
+
+    fn measurement(inclination: F32x3) -> Measurement {
+        Measurement::still().with_inclination(inclination)
+    }
+
+    #[test]
+    fn shades_with_tilt() {
+        let level = BubbleLevel::new(LevelConfig::default());
+        let flat = level.level(tilted(0.0));
+        assert!(flat.tilt.abs() < 0.3, "{:?}", flat);
+        assert!(flat.is_level());
+        assert!(level.level(tilted(0.8)).is_level());
+        let halfway = level.level(tilted(10.5));
+        // micromath's trigonometry is good to a few tenths of a degree
+        assert!((halfway.tilt - 10.5).abs() < 0.3, "{:?}", halfway);
+        assert!((halfway.severity - 0.5).abs() < 0.02, "{:?}", halfway);
+        assert_eq!(level.level(tilted(-45.0)).severity, 1.0);
+    }
+
+    #[test]
+    fn tare_sets_the_reference() {
+        let mut level = BubbleLevel::new(LevelConfig::default());
+        level.tare(tilted(30.0));
+        assert!(level.level(tilted(30.0)).is_level());
+        let off = level.level(tilted(35.0));
+        assert!((off.tilt - 5.0).abs() < 0.3, "{:?}", off);
+        level.reset();
+        assert!((level.level(tilted(30.0)).tilt - 30.0).abs() < 0.3);
+    }
+
+    #[test]
+    fn reports_steps_and_requested_tares() {
+        let mut level = BubbleLevel::new(LevelConfig::default());
+        assert!(level.update(&measurement(tilted(5.0))).is_some());
+        assert_eq!(level.update(&measurement(tilted(5.1))), None);
+        assert!(level.update(&measurement(tilted(5.5))).is_some());
+        tare_level();
+        let tared = level.update(&measurement(tilted(5.5))).unwrap();
+        assert!(tared.is_level(), "{:?}", tared);
+        assert_eq!(level.update(&measurement(tilted(5.5))), None);
+    }
+}
@@ -0,0 +1,15 @@
+//! Float maths shared by the detectors.
+
+/// Square root of `x`.
+///
+/// On the chip this is micromath's fast approximation, good to a few percent. Host-side
+/// tests use the exact square root instead, so that they check the detectors' own logic
+/// to tight tolerances.
+#[inline]
+pub(crate) fn sqrt(x: f32) -> f32 {
+    #[cfg(not(test))]
+    let root = micromath::F32Ext::sqrt(x);
+    #[cfg(test)]
+    let root = x.sqrt();
+    root
+}
@@ -0,0 +1,247 @@
+//! Step counting with a software peak detector.
+//!
+//! The APEX pedometer of the ICM-42670-P needs its DMP set up and runs on its own
+//! sample stream, so steps are counted from the measurements instead, which also lets
+//! the detector be tested on the host.
+//!
+//! Every step is a bounce in the total acceleration. The magnitude is smoothed, its
+//! slowly varying mean removed, and a step counted each time what is left rises above
+//! [`PedometerConfig::threshold`]. Steps only start counting after
+//! [`PedometerConfig::min_steps`] in a row, so that bumps and fidgeting are ignored.
+
+use embassy_time::{Duration, Instant};
+
+use super::{Measurement, math};
+
+/// Time constant of the smoothing of the magnitude, in seconds.
+const SMOOTHING_TIME_CONSTANT: f32 = 0.05;
+/// Time constant of the mean of the magnitude, in seconds.
+const MEAN_TIME_CONSTANT: f32 = 1.0;
+/// Weight of the latest step interval in the cadence.
+const CADENCE_WEIGHT: f32 = 0.3;
+
+/// Steps counted so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Steps {
+    /// Number of steps since the pedometer was started
+    pub count: u32,
+    /// Steps per minute, zero once walking stops
+    pub cadence: f32,
+    /// Estimated speed in m/s, from the cadence and stride length
+    pub speed: f32,
+    /// Estimated distance in m, from the count and stride length
+    pub distance: f32,
+}
+
+/// Tuning of the pedometer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PedometerConfig {
+    /// Bounce above the mean acceleration that counts as a step, in g
+    pub threshold: f32,
+    /// Shortest time between steps
+    pub min_interval: Duration,
+    /// Longest time between steps before walking has stopped
+    pub max_interval: Duration,
+    /// Steps in a row before any are counted
+    pub min_steps: u32,
+    /// Length of a step in m, for the speed and distance estimates
+    pub stride_length: f32,
+}
+
+impl Default for PedometerConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.12,
+            min_interval: Duration::from_millis(250),
+            max_interval: Duration::from_secs(2),
+            min_steps: 4,
+            stride_length: 0.7,
+        }
+    }
+}
+
+/// Counts steps in a stream of measurements.
+pub struct Pedometer {
+    config: PedometerConfig,
+    /// Smoothed and mean magnitude of the acceleration
+    filtered: Option<(f32, f32)>,
+    /// Whether the bounce has dropped below the mean since the last step
+    armed: bool,
+    /// Time of the previous sample
+    last_sample: Option<Instant>,
+    /// Time of the previous step
+    last_step: Option<Instant>,
+    /// Steps in a row so far
+    streak: u32,
+    /// Mean time between steps in the current streak, in seconds
+    interval: Option<f32>,
+    count: u32,
+}
+
+impl Pedometer {
+    /// Create a pedometer with the given tuning.
+    pub fn new(config: PedometerConfig) -> Self {
+        Self {
+            config,
+            filtered: None,
+            armed: false,
+            last_sample: None,
+            last_step: None,
+            streak: 0,
+            interval: None,
+            count: 0,
+        }
+    }
+
+    /// The pedometer's tuning.
+    pub fn config(&self) -> PedometerConfig {
+        self.config
+    }
+
+    /// The steps counted so far.
+    pub fn steps(&self) -> Steps {
+        let cadence = match (self.streak >= self.config.min_steps, self.interval) {
+            (true, Some(interval)) if interval > 0.0 => 60.0 / interval,
+            _ => 0.0,
+        };
+        Steps {
+            count: self.count,
+            cadence,
+            speed: cadence * self.config.stride_length / 60.0,
+            distance: self.count as f32 * self.config.stride_length,
+        }
+    }
+
+    /// Feed a measurement, returning the steps whenever they change.
+    pub fn update(&mut self, meas: &Measurement) -> Option<Steps> {
+        let at = meas.at;
+        let accel = meas.accel;
+        let magnitude = math::sqrt(accel.x * accel.x + accel.y * accel.y + accel.z * accel.z);
+        let dt = self
+            .last_sample
+            .and_then(|last| at.checked_duration_since(last))
+            .map_or(0.0, |dt| dt.as_micros() as f32 / 1_000_000.0);
+        self.last_sample = Some(at);
+        let (smoothed, mean) = self.filtered.unwrap_or((magnitude, magnitude));
+        let smoothed = smoothed + dt / (SMOOTHING_TIME_CONSTANT + dt) * (magnitude - smoothed);
+        let mean = mean + dt / (MEAN_TIME_CONSTANT + dt) * (magnitude - mean);
+        self.filtered = Some((smoothed, mean));
+
+        let since_step = self
+            .last_step
+            .map(|last| at.saturating_duration_since(last));
+        let bounce = smoothed - mean;
+        if bounce < 0.0 {
+            self.armed = true;
+        } else if self.armed
+            && bounce > self.config.threshold
+            && since_step.is_none_or(|since| since >= self.config.min_interval)
+        {
+            self.armed = false;
+            return self.step(at, since_step);
+        }
+        // walking has stopped, report the cadence dropping to zero once
+        if since_step.is_some_and(|since| since > self.config.max_interval) {
+            let walking = self.streak >= self.config.min_steps;
+            self.last_step = None;
+            self.streak = 0;
+            self.interval = None;
+            return walking.then(|| self.steps());
+        }
+        None
+    }
+
+    /// Count a step, once there have been enough in a row.
+    fn step(&mut self, at: Instant, since_step: Option<Duration>) -> Option<Steps> {
+        self.last_step = Some(at);
+        let since_step = since_step.filter(|since| *since <= self.config.max_interval);
+        match since_step {
+            Some(since) => {
+                let since = since.as_micros() as f32 / 1_000_000.0;
+                self.interval = Some(match self.interval {
+                    Some(interval) => interval + CADENCE_WEIGHT * (since - interval),
+                    None => since,
+                });
+                self.streak += 1;
+            }
+            None => {
+                self.interval = None;
+                self.streak = 1;
+            }
+        }
+        if self.streak < self.config.min_steps {
+            return None;
+        }
+        // the steps leading up to the streak count too
+        self.count += if self.streak == self.config.min_steps {
+            self.streak
+        } else {
+            1
+        };
+        Some(self.steps())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample period at 100 Hz.
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    /// Feed vertical accelerations at 100 Hz, returning every update.
+    fn run(pedometer: &mut Pedometer, samples: impl Iterator<Item = f32>) -> std::vec::Vec<Steps> {
+        let start = Instant::from_secs(1);
+        samples
+            .enumerate()
+            .filter_map(|(i, z)| {
+                let meas = Measurement::still()
+                    .with_accel([0.0, 0.0, z])
+                    .at(start + PERIOD * i as u32);
+                pedometer.update(&meas)
+            })
+            .collect()
+    }
+
+    /// Walking at `hz` steps per second for `seconds`, then standing still for 3 seconds.
+    fn walk(hz: f32, seconds: usize) -> impl Iterator<Item = f32> {
+        let walking = (0..seconds * 100).map(move |i| {
+            let t = i as f32 / 100.0;
+            1.0 + 0.3 * (2.0 * core::f32::consts::PI * hz * t).sin()
+        });
+        walking.chain(core::iter::repeat_n(1.0, 300))
+    }
+
+    #[test]
+    fn counts_steps_and_cadence() {
+        let mut pedometer = Pedometer::new(PedometerConfig::default());
+        let updates = run(&mut pedometer, walk(2.0, 10));
+        let walking = updates[updates.len() - 2];
+        assert!((19..=21).contains(&walking.count), "{:?}", walking);
+        assert!((walking.cadence - 120.0).abs() < 5.0, "{:?}", walking);
+        assert!((walking.speed - 1.4).abs() < 0.1, "{:?}", walking);
+        // the RSC measurement reports the stride length taken back out of the distance
+        let stride = walking.distance / walking.count as f32;
+        assert!((stride - 0.7).abs() < 1e-6, "{:?}", walking);
+        // standing still drops the cadence to zero and keeps the count
+        let stopped = updates[updates.len() - 1];
+        assert_eq!(stopped.count, walking.count);
+        assert_eq!(stopped.cadence, 0.0);
+    }
+
+    #[test]
+    fn first_steps_are_counted_once_walking() {
+        let mut pedometer = Pedometer::new(PedometerConfig::default());
+        let updates = run(&mut pedometer, walk(2.0, 10));
+        assert_eq!(updates[0].count, 4);
+    }
+
+    #[test]
+    fn ignores_isolated_bumps() {
+        let mut pedometer = Pedometer::new(PedometerConfig::default());
+        // a bump every three seconds
+        let samples = (0..1000).map(|i| if i % 300 < 10 { 1.5 } else { 1.0 });
+        assert!(run(&mut pedometer, samples).is_empty());
+        assert_eq!(pedometer.steps().count, 0);
+    }
+}
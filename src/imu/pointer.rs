@@ -0,0 +1,240 @@
+//! An air mouse, turning the board's rotation into cursor movement.
+//!
+//! The board is held like a presentation pointer, face up and pointing along Y.
+//! Turning it left or right moves the cursor sideways and tilting the nose up or down
+//! moves it vertically, by [`PointerConfig::sensitivity`] counts per degree. Rates under
+//! [`PointerConfig::dead_zone`] are ignored, so the cursor rests while the board is held
+//! still, and the fractions of a count left over are carried into the next report.
+//!
+//! Buttons are pressed with [`click`], from whatever input the application uses.
+//!
+//! Reports are queued on a channel of their own, read with [`pointer_reports`], rather than
+//! published with the other events, so that a burst of movement cannot push a fall or the
+//! alarm out of a subscriber's buffer, nor they a button release.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::{Channel, Receiver},
+};
+use embassy_time::{Duration, Instant};
+
+use super::Measurement;
+
+/// The left mouse button, as a bit of [`PointerReport::buttons`].
+pub const LEFT_BUTTON: u8 = 0b001;
+/// Number of reports buffered for the host.
+pub const REPORT_QUEUE_LEN: usize = 8;
+
+/// The receiving end of the pointer report channel.
+pub type PointerReceiver =
+    Receiver<'static, CriticalSectionRawMutex, PointerReport, REPORT_QUEUE_LEN>;
+
+/// Movement of the cursor and state of the buttons, as sent in a mouse report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PointerReport {
+    /// Buttons held down, one bit each
+    pub buttons: u8,
+    /// Movement right, in counts
+    pub dx: i8,
+    /// Movement down, in counts
+    pub dy: i8,
+}
+
+/// Tuning of the air mouse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointerConfig {
+    /// Cursor counts per degree turned
+    pub sensitivity: f32,
+    /// Angular rate ignored as the hand shaking, in °/s
+    pub dead_zone: f32,
+    /// Shortest time between reports
+    pub interval: Duration,
+}
+
+impl Default for PointerConfig {
+    fn default() -> Self {
+        Self {
+            sensitivity: 20.0,
+            dead_zone: 3.0,
+            interval: Duration::from_millis(20),
+        }
+    }
+}
+
+/// Turns angular rates into cursor movement.
+pub struct AirMouse {
+    config: PointerConfig,
+    /// Movement not yet reported, in counts
+    pending: [f32; 2],
+    last_sample: Option<Instant>,
+    last_report: Option<Instant>,
+}
+
+impl AirMouse {
+    /// Create an air mouse with the given tuning.
+    pub fn new(config: PointerConfig) -> Self {
+        Self {
+            config,
+            pending: [0.0; 2],
+            last_sample: None,
+            last_report: None,
+        }
+    }
+
+    /// The air mouse's tuning.
+    pub fn config(&self) -> PointerConfig {
+        self.config
+    }
+
+    /// Feed a measurement, returning a report with the cursor movement
+    /// once per interval while the board turns.
+    pub fn update(&mut self, meas: &Measurement) -> Option<PointerReport> {
+        let at = meas.at;
+        let dt = self
+            .last_sample
+            .and_then(|last| at.checked_duration_since(last))
+            .map_or(0.0, |dt| dt.as_micros() as f32 / 1_000_000.0);
+        self.last_sample = Some(at);
+        // turning right is a negative rate about Z, and tilting the nose down about X
+        let rates = [-meas.gyro.z, -meas.gyro.x];
+        for (pending, rate) in self.pending.iter_mut().zip(rates) {
+            if rate.abs() > self.config.dead_zone {
+                *pending += rate * dt * self.config.sensitivity;
+            }
+        }
+        let due = self
+            .last_report
+            .is_none_or(|last| at.saturating_duration_since(last) >= self.config.interval);
+        if !due {
+            return None;
+        }
+        let [dx, dy] = self.pending.map(|counts| counts.clamp(-127.0, 127.0) as i8);
+        if dx == 0 && dy == 0 {
+            return None;
+        }
+        self.pending[0] -= dx as f32;
+        self.pending[1] -= dy as f32;
+        self.last_report = Some(at);
+        Some(PointerReport {
+            buttons: buttons(),
+            dx,
+            dy,
+        })
+    }
+}
+
+static BUTTONS: Mutex<CriticalSectionRawMutex, Cell<u8>> = Mutex::new(Cell::new(0));
+
+static REPORTS: Channel<CriticalSectionRawMutex, PointerReport, REPORT_QUEUE_LEN> = Channel::new();
+
+/// The air mouse's reports, oldest first, to send to the host.
+pub fn pointer_reports() -> PointerReceiver {
+    REPORTS.receiver()
+}
+
+/// Queue a report for the host. While the queue is full the oldest report is dropped,
+/// as the newest carries the buttons' current state.
+pub(crate) fn send(report: PointerReport) {
+    while REPORTS.try_send(report).is_err() {
+        let _ = REPORTS.try_receive();
+    }
+}
+
+/// The buttons held down.
+fn buttons() -> u8 {
+    BUTTONS.lock(Cell::get)
+}
+
+/// Press or release `buttons`, sending a report of the new state.
+pub fn set_buttons(buttons: u8, pressed: bool) {
+    let state = BUTTONS.lock(|state| {
+        let new = if pressed {
+            state.get() | buttons
+        } else {
+            state.get() & !buttons
+        };
+        state.set(new);
+        new
+    });
+    send(PointerReport {
+        buttons: state,
+        ..Default::default()
+    });
+}
+
+/// Press and release `buttons`.
+pub fn click(buttons: u8) {
+    set_buttons(buttons, true);
+    set_buttons(buttons, false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample period at 100 Hz.
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn turning(yaw: f32, pitch: f32) -> Measurement {
+        Measurement::still().with_gyro([pitch, 0.0, yaw])
+    }
+
+    fn run(mouse: &mut AirMouse, meas: &Measurement, samples: u32) -> std::vec::Vec<PointerReport> {
+        let start = Instant::from_secs(1);
+        (0..samples)
+            .filter_map(|i| mouse.update(&meas.at(start + PERIOD * i)))
+            .collect()
+    }
+
+    #[test]
+    fn moves_with_rotation() {
+        let mut mouse = AirMouse::new(PointerConfig::default());
+        // 1s turning right at 10°/s while tilting the nose up at 5°/s
+        let reports = run(&mut mouse, &turning(-10.0, 5.0), 101);
+        assert_eq!(reports.len(), 50);
+        let dx: i32 = reports.iter().map(|r| r.dx as i32).sum();
+        let dy: i32 = reports.iter().map(|r| r.dy as i32).sum();
+        // the last sample is still pending, and each count is truncated, so the
+        // reports cover a little under 99 samples' worth
+        assert!((196..=198).contains(&dx), "{}", dx);
+        assert!((-99..=-97).contains(&dy), "{}", dy);
+    }
+
+    #[test]
+    fn rests_while_held_still() {
+        let mut mouse = AirMouse::new(PointerConfig::default());
+        assert!(run(&mut mouse, &turning(2.0, -2.5), 100).is_empty());
+    }
+
+    #[test]
+    fn clamps_fast_turns() {
+        let mut mouse = AirMouse::new(PointerConfig::default());
+        let reports = run(&mut mouse, &turning(-1000.0, 0.0), 5);
+        assert!(reports.iter().all(|r| r.dx == 127), "{:?}", reports);
+    }
+
+    #[test]
+    fn keeps_the_newest_reports_when_full() {
+        let reports = pointer_reports();
+        for dx in 0..REPORT_QUEUE_LEN as i8 {
+            send(PointerReport {
+                dx,
+                ..Default::default()
+            });
+        }
+        click(LEFT_BUTTON);
+        let mut queued = std::vec::Vec::new();
+        while let Ok(report) = reports.try_receive() {
+            queued.push(report);
+        }
+        assert_eq!(queued.len(), REPORT_QUEUE_LEN);
+        assert_eq!(queued[0].dx, 2);
+        let clicks: std::vec::Vec<_> = queued[REPORT_QUEUE_LEN - 2..]
+            .iter()
+            .map(|r| r.buttons)
+            .collect();
+        assert_eq!(clicks, [LEFT_BUTTON, 0]);
+    }
+}
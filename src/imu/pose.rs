@@ -0,0 +1,209 @@
+//! Which of its six sides the board is resting on, for using it as a "mode cube".
+//!
+//! The direction of gravity is taken from the gimbal's inclination when it is running,
+//! or from the acceleration otherwise, skipping samples whose magnitude shows the board
+//! is being moved. The side facing down is the axis closest to gravity, once gravity is
+//! within [`PoseConfig::max_tilt`] of it, so a board lying between two sides keeps its
+//! last pose rather than flickering between them.
+//!
+//! A new pose must hold for [`PoseConfig::hold`] before it is reported, and the latest is
+//! kept for the rest of the application in [`current_pose`].
+//!
+//! The poses take the sensor's X axis as pointing to the right of the board, Y to its top
+//! and Z out of its face.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant};
+use micromath::F32Ext;
+
+use super::{Event, Measurement, events, level, math};
+
+/// Side of the board facing up or down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pose {
+    /// Lying flat, face up
+    FaceUp,
+    /// Lying flat, face down
+    FaceDown,
+    /// Standing on its left edge
+    Left,
+    /// Standing on its right edge
+    Right,
+    /// Standing on its top edge, upside down
+    Top,
+    /// Standing on its bottom edge, upright
+    Bottom,
+}
+
+impl Pose {
+    /// The pose for a direction of gravity as a unit vector, if it is within the tilt
+    /// whose cosine is `min_cosine` of an axis.
+    pub fn from_gravity(gravity: [f32; 3], min_cosine: f32) -> Option<Self> {
+        let axis = (0..3).max_by(|&a, &b| gravity[a].abs().total_cmp(&gravity[b].abs()))?;
+        if gravity[axis].abs() < min_cosine {
+            return None;
+        }
+        // the accelerometer reads +1g along the axis pointing up
+        let up = gravity[axis] > 0.0;
+        Some(match (axis, up) {
+            (0, true) => Pose::Left,
+            (0, false) => Pose::Right,
+            (1, true) => Pose::Bottom,
+            (1, false) => Pose::Top,
+            (_, true) => Pose::FaceUp,
+            (_, false) => Pose::FaceDown,
+        })
+    }
+}
+
+/// Tuning of the pose detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoseConfig {
+    /// Furthest gravity may be from an axis to count as resting on that side, in degrees
+    pub max_tilt: f32,
+    /// How far the acceleration's magnitude may be from 1g while the board counts as still,
+    /// when the gimbal is not running, in g
+    pub motion_tolerance: f32,
+    /// How long a new pose must hold before it is reported
+    pub hold: Duration,
+}
+
+impl Default for PoseConfig {
+    fn default() -> Self {
+        Self {
+            max_tilt: 30.0,
+            motion_tolerance: 0.15,
+            hold: Duration::from_millis(300),
+        }
+    }
+}
+
+/// Tracks which side the board is resting on.
+pub struct PoseDetector {
+    config: PoseConfig,
+    /// Cosine of the maximum tilt
+    min_cosine: f32,
+    /// Pose last reported
+    pose: Option<Pose>,
+    /// A different pose, and since when it has held
+    candidate: Option<(Pose, Instant)>,
+}
+
+impl PoseDetector {
+    /// Create a detector with the given tuning.
+    pub fn new(config: PoseConfig) -> Self {
+        Self {
+            config,
+            min_cosine: F32Ext::cos(config.max_tilt.to_radians()),
+            pose: None,
+            candidate: None,
+        }
+    }
+
+    /// The detector's tuning.
+    pub fn config(&self) -> PoseConfig {
+        self.config
+    }
+
+    /// Feed a measurement, returning the pose once a new one has held.
+    pub fn update(&mut self, meas: &Measurement) -> Option<Pose> {
+        let at = meas.at;
+        let Some(pose) = self
+            .gravity(meas)
+            .and_then(|gravity| Pose::from_gravity(gravity, self.min_cosine))
+            .filter(|&pose| Some(pose) != self.pose)
+        else {
+            self.candidate = None;
+            return None;
+        };
+        let since = match self.candidate.filter(|&(candidate, _)| candidate == pose) {
+            Some((_, since)) => since,
+            None => {
+                self.candidate = Some((pose, at));
+                at
+            }
+        };
+        if at.saturating_duration_since(since) < self.config.hold {
+            return None;
+        }
+        self.pose = Some(pose);
+        self.candidate = None;
+        Some(pose)
+    }
+
+    /// Direction of gravity as a unit vector, if the board is not being moved.
+    fn gravity(&self, meas: &Measurement) -> Option<[f32; 3]> {
+        if let Some(inclination) = meas.inclination {
+            return Some(level::gravity(inclination));
+        }
+        let accel = [meas.accel.x, meas.accel.y, meas.accel.z];
+        let magnitude = math::sqrt(accel.iter().map(|a| a * a).sum::<f32>());
+        if (magnitude - 1.0).abs() > self.config.motion_tolerance {
+            return None;
+        }
+        Some(accel.map(|a| a / magnitude))
+    }
+}
+
+static POSE: Mutex<CriticalSectionRawMutex, Cell<Option<Pose>>> = Mutex::new(Cell::new(None));
+
+/// The side the board was last reported resting on, once the detector has settled.
+pub fn current_pose() -> Option<Pose> {
+    POSE.lock(Cell::get)
+}
+
+/// Keep a new pose and publish it as [`Event::Pose`].
+pub(super) fn report(pose: Pose) {
+    POSE.lock(|current| current.set(Some(pose)));
+    events::publish(Event::Pose(pose));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(x: f32, y: f32, z: f32) -> Measurement {
+        Measurement::still().with_accel([x, y, z])
+    }
+
+    #[test]
+    fn names_each_side() {
+        let cosine = F32Ext::cos(30f32.to_radians());
+        for (gravity, pose) in [
+            ([0.0, 0.0, 1.0], Pose::FaceUp),
+            ([0.0, 0.0, -1.0], Pose::FaceDown),
+            ([1.0, 0.0, 0.0], Pose::Left),
+            ([-1.0, 0.0, 0.0], Pose::Right),
+            ([0.0, -1.0, 0.0], Pose::Top),
+            ([0.0, 1.0, 0.0], Pose::Bottom),
+        ] {
+            assert_eq!(Pose::from_gravity(gravity, cosine), Some(pose));
+        }
+        // halfway between face up and standing on its left edge
+        let between = math::sqrt(0.5);
+        assert_eq!(Pose::from_gravity([between, 0.0, between], cosine), None);
+    }
+
+    #[test]
+    fn debounces_changes() {
+        let mut detector = PoseDetector::new(PoseConfig::default());
+        let ms = Instant::from_millis;
+        let (flat, down) = (measurement(0.0, 0.1, 1.0), measurement(0.0, 0.0, -1.0));
+        assert_eq!(detector.update(&flat.at(ms(0))), None);
+        assert_eq!(detector.update(&flat.at(ms(300))), Some(Pose::FaceUp));
+        assert_eq!(detector.update(&flat.at(ms(600))), None);
+        // a brief flip is not reported
+        assert_eq!(detector.update(&down.at(ms(700))), None);
+        assert_eq!(detector.update(&flat.at(ms(800))), None);
+        assert_eq!(detector.update(&down.at(ms(900))), None);
+        // nor is shaking it, which restarts the hold
+        assert_eq!(
+            detector.update(&measurement(0.0, 0.0, -2.0).at(ms(1000))),
+            None
+        );
+        assert_eq!(detector.update(&down.at(ms(1100))), None);
+        assert_eq!(detector.update(&down.at(ms(1400))), Some(Pose::FaceDown));
+    }
+}
@@ -0,0 +1,204 @@
+//! Register level access to the ICM42670.
+//!
+//! The `icm42670` driver only covers the data and configuration registers, but the FIFO,
+//! APEX and self-test blocks need raw register access, so the sensor talks to the device
+//! through this thin layer instead.
+//!
+//! <https://invensense.tdk.com/download-pdf/icm-42670-p-datasheet/>
+
+use embassy_time::{Duration, block_for};
+use embedded_hal::i2c::I2c;
+
+/// Expected value of the `WHO_AM_I` register.
+pub const DEVICE_ID: u8 = 0x67;
+
+// User bank 0
+pub const SIGNAL_PATH_RESET: u8 = 0x02;
+pub const TEMP_DATA1: u8 = 0x09;
+pub const ACCEL_DATA_X1: u8 = 0x0B;
+pub const GYRO_DATA_X1: u8 = 0x11;
+pub const PWR_MGMT0: u8 = 0x1F;
+pub const GYRO_CONFIG0: u8 = 0x20;
+pub const ACCEL_CONFIG0: u8 = 0x21;
+pub const GYRO_CONFIG1: u8 = 0x23;
+pub const ACCEL_CONFIG1: u8 = 0x24;
+pub const WOM_CONFIG: u8 = 0x27;
+pub const FIFO_CONFIG1: u8 = 0x28;
+pub const FIFO_CONFIG2: u8 = 0x29;
+pub const FIFO_CONFIG3: u8 = 0x2A;
+pub const INT_SOURCE1: u8 = 0x2C;
+pub const INT_STATUS2: u8 = 0x3B;
+pub const FIFO_COUNTH: u8 = 0x3D;
+pub const FIFO_COUNTL: u8 = 0x3E;
+pub const FIFO_DATA: u8 = 0x3F;
+pub const WHO_AM_I: u8 = 0x75;
+pub const BLK_SEL_W: u8 = 0x79;
+pub const MADDR_W: u8 = 0x7A;
+pub const M_W: u8 = 0x7B;
+pub const BLK_SEL_R: u8 = 0x7C;
+pub const MADDR_R: u8 = 0x7D;
+pub const M_R: u8 = 0x7E;
+
+// MREG1, accessed through `BLK_SEL`/`MADDR`/`M`
+pub const TMST_CONFIG1: u8 = 0x00;
+pub const FIFO_CONFIG5: u8 = 0x01;
+pub const ACCEL_WOM_X_THR: u8 = 0x4B;
+pub const ACCEL_WOM_Y_THR: u8 = 0x4C;
+pub const ACCEL_WOM_Z_THR: u8 = 0x4D;
+pub const ST_STATUS1: u8 = 0x63;
+pub const ST_STATUS2: u8 = 0x64;
+pub const SELFTEST: u8 = 0x6B;
+
+// SIGNAL_PATH_RESET bits
+pub const SOFT_RESET_DEVICE_CONFIG: u8 = 1 << 4;
+pub const FIFO_FLUSH: u8 = 1 << 2;
+
+// FIFO_CONFIG1 bits
+pub const FIFO_BYPASS: u8 = 1 << 0;
+
+// WOM_CONFIG bits
+pub const WOM_MODE: u8 = 1 << 1;
+pub const WOM_EN: u8 = 1 << 0;
+
+// INT_SOURCE1 and INT_STATUS2 bits
+pub const WOM_X_INT: u8 = 1 << 2;
+pub const WOM_Y_INT: u8 = 1 << 1;
+pub const WOM_Z_INT: u8 = 1 << 0;
+
+// TMST_CONFIG1 bits
+pub const TMST_RES: u8 = 1 << 3;
+pub const TMST_EN: u8 = 1 << 0;
+
+// FIFO_CONFIG5 bits
+pub const FIFO_TMST_FSYNC_EN: u8 = 1 << 2;
+pub const FIFO_GYRO_EN: u8 = 1 << 1;
+pub const FIFO_ACCEL_EN: u8 = 1 << 0;
+
+// ST_STATUS1 bits
+pub const ACCEL_ST_PASS: u8 = 1 << 5;
+pub const ACCEL_ST_DONE: u8 = 1 << 4;
+
+// ST_STATUS2 bits
+pub const ST_INCOMPLETE: u8 = 1 << 6;
+pub const GYRO_ST_PASS: u8 = 1 << 5;
+pub const GYRO_ST_DONE: u8 = 1 << 4;
+
+// SELFTEST bits, the per-axis enables are in bits 0 to 5 from accelerometer X to gyroscope Z
+pub const GYRO_ST_EN: u8 = 1 << 7;
+pub const ACCEL_ST_EN: u8 = 1 << 6;
+pub const ST_AXES_EN: u8 = 0b11_1111;
+
+/// Errors from the ICM42670.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The I2C bus reported an error.
+    Bus(E),
+    /// The device answered with an unexpected `WHO_AM_I`.
+    BadChip(u8),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Error::Bus(error)
+    }
+}
+
+/// Operating mode of the accelerometer and gyroscope, written to `PWR_MGMT0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerMode {
+    /// Both sensors off
+    Sleep,
+    /// Gyroscope drive running, sensors off
+    Standby,
+    /// Accelerometer only, low power
+    AccelLowPower,
+    /// Accelerometer only, low noise
+    AccelLowNoise,
+    /// Gyroscope only, low noise
+    GyroLowNoise,
+    /// Accelerometer low power and gyroscope low noise
+    SixAxisLowPower,
+    /// Accelerometer and gyroscope low noise
+    SixAxisLowNoise,
+}
+
+impl PowerMode {
+    /// The `GYRO_MODE` and `ACCEL_MODE` bits of `PWR_MGMT0`.
+    pub fn bits(self) -> u8 {
+        match self {
+            PowerMode::Sleep => 0b0000,
+            PowerMode::Standby => 0b0100,
+            PowerMode::AccelLowPower => 0b0010,
+            PowerMode::AccelLowNoise => 0b0011,
+            PowerMode::GyroLowNoise => 0b1100,
+            PowerMode::SixAxisLowPower => 0b1110,
+            PowerMode::SixAxisLowNoise => 0b1111,
+        }
+    }
+}
+
+/// The ICM42670 at its primary address on an I2C bus.
+pub struct Registers<I> {
+    i2c: I,
+}
+
+impl<I: I2c> Registers<I> {
+    /// I2C address with `AD0` pulled low.
+    const ADDRESS: u8 = 0x68;
+
+    /// Wrap the I2C bus the device is on.
+    pub fn new(i2c: I) -> Self {
+        Self { i2c }
+    }
+    /// Read a single register.
+    pub fn read(&mut self, reg: u8) -> Result<u8, Error<I::Error>> {
+        let mut buf = [0];
+        self.read_into(reg, &mut buf)?;
+        Ok(buf[0])
+    }
+    /// Read consecutive registers into a buffer.
+    ///
+    /// `FIFO_DATA` does not auto-increment, so this also drains the FIFO.
+    pub fn read_into(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Error<I::Error>> {
+        Ok(self.i2c.write_read(Self::ADDRESS, &[reg], buf)?)
+    }
+    /// Write a single register.
+    pub fn write(&mut self, reg: u8, value: u8) -> Result<(), Error<I::Error>> {
+        Ok(self.i2c.write(Self::ADDRESS, &[reg, value])?)
+    }
+    /// Replace the bits selected by `mask` in a register.
+    pub fn update(&mut self, reg: u8, mask: u8, value: u8) -> Result<(), Error<I::Error>> {
+        let current = self.read(reg)?;
+        self.write(reg, (current & !mask) | (value & mask))
+    }
+    /// Read three big-endian 16 bit values, such as an accelerometer sample.
+    pub fn read_vector(&mut self, reg: u8) -> Result<[i16; 3], Error<I::Error>> {
+        let mut buf = [0; 6];
+        self.read_into(reg, &mut buf)?;
+        Ok(core::array::from_fn(|i| {
+            i16::from_be_bytes([buf[2 * i], buf[2 * i + 1]])
+        }))
+    }
+    /// Read a register from the MREG1 bank.
+    pub fn read_mreg1(&mut self, reg: u8) -> Result<u8, Error<I::Error>> {
+        self.write(BLK_SEL_R, 0x00)?;
+        self.write(MADDR_R, reg)?;
+        block_for(Duration::from_micros(10));
+        let value = self.read(M_R)?;
+        block_for(Duration::from_micros(10));
+        Ok(value)
+    }
+    /// Write a register in the MREG1 bank.
+    pub fn write_mreg1(&mut self, reg: u8, value: u8) -> Result<(), Error<I::Error>> {
+        self.write(BLK_SEL_W, 0x00)?;
+        self.write(MADDR_W, reg)?;
+        self.write(M_W, value)?;
+        block_for(Duration::from_micros(10));
+        Ok(())
+    }
+    /// Replace the bits selected by `mask` in an MREG1 register.
+    pub fn update_mreg1(&mut self, reg: u8, mask: u8, value: u8) -> Result<(), Error<I::Error>> {
+        let current = self.read_mreg1(reg)?;
+        self.write_mreg1(reg, (current & !mask) | (value & mask))
+    }
+}
@@ -0,0 +1,120 @@
+//! Sampling the IMU in a task of its own, handing every sample to consumers over a channel.
+//!
+//! Sharing an executor with the BLE host and the LED delays the sampling task whenever
+//! they are busy, so [`ImuSensor::start_sampling`](super::ImuSensor::start_sampling) can be
+//! spawned on an `esp_hal_embassy::InterruptExecutor` at a raised priority instead, with
+//! the consumers left at thread priority reading [`samples`].
+//!
+//! How late each wake-up is against its schedule is measured, and summarised as a
+//! [`Jitter`] every [`REPORT_INTERVAL`].
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::{Channel, Receiver},
+};
+use embassy_time::Duration;
+
+use super::Measurement;
+
+/// Number of samples buffered for the consumers.
+pub const QUEUE_LEN: usize = 32;
+/// Time between summaries of the jitter.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The receiving end of the sample channel.
+pub type SampleReceiver = Receiver<'static, CriticalSectionRawMutex, Measurement, QUEUE_LEN>;
+
+pub(super) static SAMPLES: Channel<CriticalSectionRawMutex, Measurement, QUEUE_LEN> =
+    Channel::new();
+
+static JITTER: Mutex<CriticalSectionRawMutex, Cell<Option<Jitter>>> = Mutex::new(Cell::new(None));
+
+/// The samples taken by the sampling task, oldest first. Samples are dropped while
+/// the channel is full, so it should be read at least as fast as they are taken.
+pub fn samples() -> SampleReceiver {
+    SAMPLES.receiver()
+}
+
+/// The latest summary of the sampling task's jitter, once one has been made.
+pub fn sampling_jitter() -> Option<Jitter> {
+    JITTER.lock(Cell::get)
+}
+
+/// Publish a summary of the jitter.
+pub(super) fn report(jitter: Jitter) {
+    JITTER.lock(|latest| latest.set(Some(jitter)));
+}
+
+/// How late the sampling task woke up over a period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Jitter {
+    /// Number of wake-ups
+    pub wakeups: u32,
+    /// Mean lateness
+    pub mean: Duration,
+    /// Greatest lateness
+    pub max: Duration,
+    /// Wake-ups so late that one or more periods were skipped
+    pub overruns: u32,
+    /// Samples dropped because the channel was full
+    pub dropped: u32,
+}
+
+/// Measures how late each wake-up is.
+#[derive(Default)]
+pub struct JitterMeter {
+    total_micros: u64,
+    jitter: Jitter,
+}
+
+impl JitterMeter {
+    /// Record a wake-up `late` after it was due, with the sampling `period`.
+    pub fn wakeup(&mut self, late: Duration, period: Duration) {
+        self.jitter.wakeups += 1;
+        self.total_micros += late.as_micros();
+        self.jitter.max = self.jitter.max.max(late);
+        if late >= period {
+            self.jitter.overruns += 1;
+        }
+    }
+
+    /// Record samples dropped because the channel was full.
+    pub fn dropped(&mut self, samples: u32) {
+        self.jitter.dropped += samples;
+    }
+
+    /// Summarise the jitter since the last summary, and start again.
+    pub fn summary(&mut self) -> Jitter {
+        let wakeups = self.jitter.wakeups.max(1) as u64;
+        let jitter = Jitter {
+            mean: Duration::from_micros(self.total_micros / wakeups),
+            ..self.jitter
+        };
+        *self = Self::default();
+        jitter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarises_lateness() {
+        let mut meter = JitterMeter::default();
+        let period = Duration::from_millis(10);
+        for late in [100, 300, 200, 12_000] {
+            meter.wakeup(Duration::from_micros(late), period);
+        }
+        meter.dropped(3);
+        let jitter = meter.summary();
+        assert_eq!(jitter.wakeups, 4);
+        assert_eq!(jitter.mean, Duration::from_micros(3150));
+        assert_eq!(jitter.max, Duration::from_millis(12));
+        assert_eq!(jitter.overruns, 1);
+        assert_eq!(jitter.dropped, 3);
+        assert_eq!(meter.summary(), Jitter::default());
+    }
+}
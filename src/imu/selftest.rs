@@ -0,0 +1,154 @@
+//! Power-on self-test of the ICM42670.
+//!
+//! The device is identified by its `WHO_AM_I`, then its built-in self-test deflects each
+//! axis of the accelerometer and gyroscope with an internal actuator and checks that the
+//! response is within the limits trimmed at the factory. This runs before an
+//! [`ImuSensor`](super::ImuSensor) is created, so that a missing or faulty sensor is
+//! reported rather than panicking.
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::i2c::I2c;
+use log::warn;
+
+use super::registers::{self as reg, Error, PowerMode, Registers};
+
+/// Status bits of every axis.
+pub const ALL_AXES: u8 = 0b111;
+/// Longest the self-test may take before it is given up on.
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the self-test is checked for completion.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Outcome of the ICM42670 self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ImuSelfTest {
+    /// Contents of `WHO_AM_I`, `None` if the device did not answer
+    pub device_id: Option<u8>,
+    /// Whether the self-test finished in time, and on every axis
+    pub completed: bool,
+    /// Accelerometer axes that passed, X in bit 0 to Z in bit 2
+    pub accel_axes: u8,
+    /// Gyroscope axes that passed, X in bit 0 to Z in bit 2
+    pub gyro_axes: u8,
+}
+
+impl ImuSelfTest {
+    /// Whether the device answered as an ICM42670.
+    pub fn identified(&self) -> bool {
+        self.device_id == Some(reg::DEVICE_ID)
+    }
+    /// Whether every axis passed the built-in self-test.
+    pub fn axes_passed(&self) -> bool {
+        self.completed && self.accel_axes == ALL_AXES && self.gyro_axes == ALL_AXES
+    }
+    /// Whether the device was identified and passed its self-test.
+    pub fn passed(&self) -> bool {
+        self.identified() && self.axes_passed()
+    }
+}
+
+/// Identify the ICM42670 and run its built-in self-test.
+///
+/// The sensors are left off, to be reset when an [`ImuSensor`](super::ImuSensor) is created.
+pub async fn self_test<I: I2c>(i2c: I) -> ImuSelfTest {
+    let mut device = Registers::new(i2c);
+    let mut result = ImuSelfTest::default();
+    if let Err(error) = run(&mut device, &mut result).await {
+        warn!("IMU self-test stopped: {:?}", error);
+    }
+    result
+}
+
+/// Fill in the result as each step of the self-test completes.
+async fn run<I: I2c>(
+    device: &mut Registers<I>,
+    result: &mut ImuSelfTest,
+) -> Result<(), Error<I::Error>> {
+    let id = device.read(reg::WHO_AM_I)?;
+    result.device_id = Some(id);
+    if id != reg::DEVICE_ID {
+        return Ok(());
+    }
+    // the self-test drives the sensors itself, starting from sleep
+    device.update(reg::PWR_MGMT0, 0x0F, PowerMode::Sleep.bits())?;
+    device.write_mreg1(
+        reg::SELFTEST,
+        reg::ACCEL_ST_EN | reg::GYRO_ST_EN | reg::ST_AXES_EN,
+    )?;
+    let end = Instant::now() + TIMEOUT;
+    let status = loop {
+        Timer::after(POLL).await;
+        let accel = device.read_mreg1(reg::ST_STATUS1)?;
+        let gyro = device.read_mreg1(reg::ST_STATUS2)?;
+        if accel & reg::ACCEL_ST_DONE != 0 && gyro & reg::GYRO_ST_DONE != 0 {
+            break Some((accel, gyro));
+        }
+        if Instant::now() >= end {
+            break None;
+        }
+    };
+    device.write_mreg1(reg::SELFTEST, 0)?;
+    if let Some((accel, gyro)) = status {
+        result.completed = gyro & reg::ST_INCOMPLETE == 0;
+        // the pass bits of X, Y and Z are in bits 1 to 3, below the done and overall pass bits
+        result.accel_axes = (accel >> 1) & ALL_AXES;
+        result.gyro_axes = (gyro >> 1) & ALL_AXES;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockBus;
+    use embassy_futures::block_on;
+
+    fn finish(bus: &MockBus, accel: u8, gyro: u8) {
+        let mut state = bus.state();
+        state.mreg1[reg::ST_STATUS1 as usize] = reg::ACCEL_ST_DONE | accel;
+        state.mreg1[reg::ST_STATUS2 as usize] = reg::GYRO_ST_DONE | gyro;
+    }
+
+    #[test]
+    fn passes_every_axis() {
+        let bus = MockBus::default();
+        finish(
+            &bus,
+            reg::ACCEL_ST_PASS | 0b1110,
+            reg::GYRO_ST_PASS | 0b1110,
+        );
+        let result = block_on(self_test(bus.clone()));
+        assert!(result.passed(), "{:?}", result);
+        assert_eq!(bus.mreg1(reg::SELFTEST), 0);
+        assert_eq!(bus.register(reg::PWR_MGMT0) & 0x0F, 0);
+    }
+
+    #[test]
+    fn reports_failing_axes() {
+        let bus = MockBus::default();
+        // gyroscope Y failed
+        finish(&bus, reg::ACCEL_ST_PASS | 0b1110, 0b1010);
+        let result = block_on(self_test(bus));
+        assert!(result.identified() && result.completed);
+        assert_eq!((result.accel_axes, result.gyro_axes), (ALL_AXES, 0b101));
+        assert!(!result.passed());
+    }
+
+    #[test]
+    fn identifies_the_device_first() {
+        let bus = MockBus::default();
+        bus.set_register(reg::WHO_AM_I, 0x00);
+        finish(
+            &bus,
+            reg::ACCEL_ST_PASS | 0b1110,
+            reg::GYRO_ST_PASS | 0b1110,
+        );
+        let result = block_on(self_test(bus));
+        assert_eq!(result.device_id, Some(0x00));
+        assert!(!result.completed);
+
+        let bus = MockBus::default();
+        bus.disconnect();
+        assert_eq!(block_on(self_test(bus)), ImuSelfTest::default());
+    }
+}
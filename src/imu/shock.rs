@@ -0,0 +1,287 @@
+//! Logging of shocks and impacts, for knowing when and how hard the board was hit.
+//!
+//! A shock starts when the total acceleration rises above [`ShockConfig::threshold`]
+//! and ends once it has stayed below for [`ShockConfig::quiet`], so the ringing after a
+//! hit is counted as part of it. Each is recorded with its peak, duration, the axis
+//! that took most of it and when it started.
+//!
+//! Hits are short and hard, so for a true peak the sensor should buffer samples in its
+//! FIFO at a high output data rate, with the accelerometer at its ±16g range.
+//!
+//! Shocks are kept in a bounded log shared with the rest of the application,
+//! see [`recorded_shock`], with the oldest dropped once it is full.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+use super::{Axis, Direction, Measurement, math};
+
+/// Number of shocks kept in the log.
+pub const LOG_LEN: usize = 32;
+
+/// A shock recorded by the detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shock {
+    /// Time the acceleration first rose above the threshold
+    pub at: Instant,
+    /// Largest total acceleration, in g
+    pub peak: f32,
+    /// Time from the start of the shock until it last rose above the threshold
+    pub duration: Duration,
+    /// Axis with the largest acceleration at the peak
+    pub axis: Axis,
+    /// Direction of the acceleration along `axis` at the peak
+    pub direction: Direction,
+}
+
+/// Tuning of the shock detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShockConfig {
+    /// Total acceleration that counts as a shock, in g
+    pub threshold: f32,
+    /// Time below the threshold that ends a shock
+    pub quiet: Duration,
+}
+
+impl Default for ShockConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 3.0,
+            quiet: Duration::from_millis(20),
+        }
+    }
+}
+
+/// A shock in progress.
+#[derive(Clone, Copy)]
+struct Active {
+    start: Instant,
+    /// Time the acceleration was last above the threshold
+    last_above: Instant,
+    /// Largest squared total acceleration so far, and the acceleration at it
+    peak: (f32, [f32; 3]),
+}
+
+/// Finds shocks in a stream of measurements.
+pub struct ShockDetector {
+    config: ShockConfig,
+    active: Option<Active>,
+}
+
+impl ShockDetector {
+    /// Create a detector with the given tuning.
+    pub fn new(config: ShockConfig) -> Self {
+        Self {
+            config,
+            active: None,
+        }
+    }
+
+    /// The detector's tuning.
+    pub fn config(&self) -> ShockConfig {
+        self.config
+    }
+
+    /// Feed a measurement, returning a shock once it has ended.
+    pub fn update(&mut self, meas: &Measurement) -> Option<Shock> {
+        let at = meas.at;
+        let accel = [meas.accel.x, meas.accel.y, meas.accel.z];
+        let squared: f32 = accel.iter().map(|a| a * a).sum();
+        let threshold = self.config.threshold * self.config.threshold;
+        if squared > threshold {
+            let active = self.active.get_or_insert(Active {
+                start: at,
+                last_above: at,
+                peak: (squared, accel),
+            });
+            active.last_above = at;
+            if squared > active.peak.0 {
+                active.peak = (squared, accel);
+            }
+            return None;
+        }
+        let active = self.active?;
+        if at.saturating_duration_since(active.last_above) < self.config.quiet {
+            return None;
+        }
+        self.active = None;
+        let (squared, accel) = active.peak;
+        let (axis, value) = [Axis::X, Axis::Y, Axis::Z]
+            .into_iter()
+            .zip(accel)
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))?;
+        Some(Shock {
+            at: active.start,
+            peak: math::sqrt(squared),
+            duration: active.last_above - active.start,
+            axis,
+            direction: if value < 0.0 {
+                Direction::Negative
+            } else {
+                Direction::Positive
+            },
+        })
+    }
+}
+
+/// The most recent shocks, oldest first, with a count of every shock recorded.
+pub struct ShockLog {
+    shocks: Deque<Shock, LOG_LEN>,
+    total: u32,
+}
+
+impl ShockLog {
+    /// Create an empty log.
+    pub const fn new() -> Self {
+        Self {
+            shocks: Deque::new(),
+            total: 0,
+        }
+    }
+
+    /// Record a shock, dropping the oldest if the log is full.
+    pub fn push(&mut self, shock: Shock) {
+        if self.shocks.is_full() {
+            self.shocks.pop_front();
+        }
+        // the log has room after dropping the oldest
+        let _ = self.shocks.push_back(shock);
+        self.total = self.total.wrapping_add(1);
+    }
+
+    /// The shock `index` places before the most recent, zero being the most recent.
+    pub fn get(&self, index: usize) -> Option<Shock> {
+        let len = self.shocks.len();
+        let position = len.checked_sub(index + 1)?;
+        self.shocks.iter().nth(position).copied()
+    }
+
+    /// Number of shocks held in the log.
+    pub fn len(&self) -> usize {
+        self.shocks.len()
+    }
+
+    /// Whether no shocks have been recorded since the log was cleared.
+    pub fn is_empty(&self) -> bool {
+        self.shocks.is_empty()
+    }
+
+    /// Number of shocks recorded since boot, including those dropped from the log.
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// Empty the log, keeping the total.
+    pub fn clear(&mut self) {
+        self.shocks.clear();
+    }
+}
+
+impl Default for ShockLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static LOG: Mutex<CriticalSectionRawMutex, RefCell<ShockLog>> =
+    Mutex::new(RefCell::new(ShockLog::new()));
+
+/// Add a shock to the shared log.
+pub(crate) fn record(shock: Shock) {
+    LOG.lock(|log| log.borrow_mut().push(shock));
+}
+
+/// The recorded shock `index` places before the most recent, zero being the most recent.
+pub fn recorded_shock(index: usize) -> Option<Shock> {
+    LOG.lock(|log| log.borrow().get(index))
+}
+
+/// The number of shocks held in the log, and the number recorded since boot.
+pub fn shock_count() -> (usize, u32) {
+    LOG.lock(|log| {
+        let log = log.borrow();
+        (log.len(), log.total())
+    })
+}
+
+/// Empty the shared log.
+pub fn clear_shocks() {
+    LOG.lock(|log| log.borrow_mut().clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample period at 1600 Hz.
+    const PERIOD: Duration = Duration::from_micros(625);
+
+    /// Feed accelerations at 1600 Hz, returning every shock.
+    fn run(detector: &mut ShockDetector, samples: &[[f32; 3]]) -> std::vec::Vec<Shock> {
+        let start = Instant::from_secs(1);
+        samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &accel)| {
+                let meas = Measurement::still()
+                    .with_accel(accel)
+                    .at(start + PERIOD * i as u32);
+                detector.update(&meas)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_peak_axis_and_duration() {
+        let mut detector = ShockDetector::new(ShockConfig::default());
+        let mut samples = std::vec![[0.0, 0.0, 1.0]; 100];
+        // a 5ms hit along -Y peaking at 8g, ringing back above the threshold once
+        for (i, g) in [4.0, 6.0, 8.0, 7.0, 5.0, 3.5, 2.0, 2.0, 3.5, 2.0]
+            .into_iter()
+            .enumerate()
+        {
+            samples[10 + i] = [0.5, -g, 1.0];
+        }
+        let shocks = run(&mut detector, &samples);
+        assert_eq!(shocks.len(), 1, "{:?}", shocks);
+        let shock = shocks[0];
+        assert_eq!(shock.at, Instant::from_secs(1) + PERIOD * 10);
+        assert!((shock.peak - 8.08).abs() < 0.05, "{:?}", shock);
+        assert_eq!(shock.duration, PERIOD * 8);
+        assert_eq!(shock.axis, Axis::Y);
+        assert_eq!(shock.direction, Direction::Negative);
+    }
+
+    #[test]
+    fn ignores_handling() {
+        let mut detector = ShockDetector::new(ShockConfig::default());
+        let samples: std::vec::Vec<_> = (0..1600)
+            .map(|i| [0.0, 0.0, 1.0 + 0.8 * (i as f32 / 100.0).sin()])
+            .collect();
+        assert!(run(&mut detector, &samples).is_empty());
+    }
+
+    #[test]
+    fn log_is_bounded() {
+        let mut log = ShockLog::new();
+        for i in 0..LOG_LEN + 3 {
+            log.push(Shock {
+                at: Instant::from_secs(i as u64),
+                peak: 4.0,
+                duration: Duration::from_millis(2),
+                axis: Axis::Z,
+                direction: Direction::Positive,
+            });
+        }
+        assert_eq!(log.len(), LOG_LEN);
+        assert_eq!(log.total(), LOG_LEN as u32 + 3);
+        let latest = log.get(0).unwrap();
+        assert_eq!(latest.at, Instant::from_secs(LOG_LEN as u64 + 2));
+        let oldest = log.get(LOG_LEN - 1).unwrap();
+        assert_eq!(oldest.at, Instant::from_secs(3));
+        assert_eq!(log.get(LOG_LEN), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! Vibration analysis over windows of the accelerometer stream.
+//!
+//! Samples are collected into fixed windows of [`WINDOW`] samples per axis. Once a window
+//! is full its mean (gravity) is removed, a Hann window applied and a real FFT taken, all in
+//! place in fixed-size buffers so that nothing is allocated. Each axis is summarised by its
+//! RMS, peak-to-peak, dominant frequency and the energy in each of [`BANDS`] frequency bands.
+//!
+//! The frequencies assume one sample per output data rate period, so the analyser
+//! should be fed every sample, as in FIFO mode.
+
+use microfft::real::rfft_256;
+
+use super::math;
+
+/// Number of samples per window.
+pub const WINDOW: usize = 256;
+/// Number of frequency bands reported.
+pub const BANDS: usize = 4;
+
+/// Vibration of one axis over a window.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AxisVibration {
+    /// Root mean square about the mean, in g
+    pub rms: f32,
+    /// Difference between the largest and smallest sample, in g
+    pub peak_to_peak: f32,
+    /// Frequency of the strongest component, in Hz
+    pub dominant_frequency: f32,
+    /// Mean square in each band, in g², which together make up the square of the RMS
+    pub band_energy: [f32; BANDS],
+}
+
+/// Vibration of each axis over a window.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vibration {
+    pub x: AxisVibration,
+    pub y: AxisVibration,
+    pub z: AxisVibration,
+}
+
+/// Tuning of the vibration analysis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectrumConfig {
+    /// Edges of the frequency bands in Hz, band `i` covering `edges[i]..edges[i + 1]`
+    pub band_edges: [f32; BANDS + 1],
+}
+
+impl Default for SpectrumConfig {
+    fn default() -> Self {
+        Self {
+            band_edges: [0.0, 25.0, 50.0, 100.0, 200.0],
+        }
+    }
+}
+
+/// Collects accelerometer samples and analyses every full window.
+pub struct SpectrumAnalyzer {
+    config: SpectrumConfig,
+    /// Rate of the incoming samples, in Hz
+    sample_rate: f32,
+    /// Samples of the current window, per axis
+    window: [[f32; WINDOW]; 3],
+    /// Number of samples in the current window
+    len: usize,
+}
+
+impl SpectrumAnalyzer {
+    /// Create an analyser for samples arriving at `sample_rate` Hz.
+    pub fn new(sample_rate: f32, config: SpectrumConfig) -> Self {
+        Self {
+            config,
+            sample_rate,
+            window: [[0.0; WINDOW]; 3],
+            len: 0,
+        }
+    }
+
+    /// The analyser's tuning.
+    pub fn config(&self) -> SpectrumConfig {
+        self.config
+    }
+
+    /// Add an accelerometer sample in g, returning the analysis once a window is full.
+    pub fn update(&mut self, accel: [f32; 3]) -> Option<Vibration> {
+        for (axis, value) in self.window.iter_mut().zip(accel) {
+            axis[self.len] = value;
+        }
+        self.len += 1;
+        if self.len < WINDOW {
+            return None;
+        }
+        self.len = 0;
+        let [x, y, z] = core::array::from_fn(|axis| self.analyse(axis));
+        Some(Vibration { x, y, z })
+    }
+
+    /// Analyse one axis of the full window, reusing its buffer for the FFT.
+    fn analyse(&mut self, axis: usize) -> AxisVibration {
+        let samples = &mut self.window[axis];
+        let (min, max, sum) = samples
+            .iter()
+            .fold((f32::MAX, f32::MIN, 0.0), |(min, max, sum), &value| {
+                (min.min(value), max.max(value), sum + value)
+            });
+        let mean = sum / WINDOW as f32;
+        let mut mean_square = 0.0;
+        let mut window_power = 0.0;
+        for (i, value) in samples.iter_mut().enumerate() {
+            let centred = *value - mean;
+            mean_square += centred * centred;
+            let hann = hann(i);
+            window_power += hann * hann;
+            *value = centred * hann;
+        }
+        mean_square /= WINDOW as f32;
+
+        let spectrum = rfft_256(samples);
+        // one-sided mean square per bin, scaled back up for the power taken by the window
+        let scale = 2.0 / (WINDOW as f32 * window_power);
+        let resolution = self.sample_rate / WINDOW as f32;
+        let mut band_energy = [0.0; BANDS];
+        let mut dominant = (0, 0.0);
+        // bin 0 holds the mean and the Nyquist bin, both left out
+        for (bin, value) in spectrum.iter().enumerate().skip(1) {
+            let power = value.norm_sqr() * scale;
+            if power > dominant.1 {
+                dominant = (bin, power);
+            }
+            let frequency = bin as f32 * resolution;
+            let edges = &self.config.band_edges;
+            if let Some(band) = (0..BANDS).find(|&b| (edges[b]..edges[b + 1]).contains(&frequency))
+            {
+                band_energy[band] += power;
+            }
+        }
+        AxisVibration {
+            rms: math::sqrt(mean_square),
+            peak_to_peak: max - min,
+            dominant_frequency: dominant.0 as f32 * resolution,
+            band_energy,
+        }
+    }
+}
+
+/// The Hann window at sample `i`.
+fn hann(i: usize) -> f32 {
+    let phase = 2.0 * core::f32::consts::PI * i as f32 / WINDOW as f32;
+    0.5 - 0.5 * micromath::F32Ext::cos(phase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 50 Hz vibration of 0.5g along X at 400 Hz, with gravity along Z.
+    fn vibrate(analyzer: &mut SpectrumAnalyzer) -> std::vec::Vec<Vibration> {
+        (0..2 * WINDOW)
+            .filter_map(|i| {
+                let t = i as f32 / 400.0;
+                let x = 0.5 * (2.0 * core::f32::consts::PI * 50.0 * t).sin();
+                analyzer.update([x, 0.0, 1.0])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_once_per_window() {
+        let mut analyzer = SpectrumAnalyzer::new(400.0, SpectrumConfig::default());
+        assert_eq!(vibrate(&mut analyzer).len(), 2);
+    }
+
+    #[test]
+    fn finds_rms_peak_and_frequency() {
+        let mut analyzer = SpectrumAnalyzer::new(400.0, SpectrumConfig::default());
+        let vibration = vibrate(&mut analyzer)[0];
+        let x = vibration.x;
+        assert!((x.rms - 0.354).abs() < 0.01, "{:?}", x);
+        assert!((x.peak_to_peak - 1.0).abs() < 0.01, "{:?}", x);
+        assert!((x.dominant_frequency - 50.0).abs() < 1.6, "{:?}", x);
+        // gravity is removed, and a still axis has no vibration
+        assert!(vibration.z.rms < 1e-3, "{:?}", vibration.z);
+        assert!(vibration.z.band_energy.iter().all(|energy| *energy < 1e-6));
+    }
+
+    #[test]
+    fn band_energies_add_up_to_mean_square() {
+        let mut analyzer = SpectrumAnalyzer::new(400.0, SpectrumConfig::default());
+        let x = vibrate(&mut analyzer)[0].x;
+        let total: f32 = x.band_energy.iter().sum();
+        assert!(
+            (total - x.rms * x.rms).abs() < 0.1 * x.rms * x.rms,
+            "{:?}",
+            x
+        );
+        // most of it in the 50-100 Hz band, with some leaking into the one below
+        assert!(x.band_energy[2] > 0.6 * total, "{:?}", x);
+        assert!(x.band_energy[0] < 0.01 * total, "{:?}", x);
+    }
+}
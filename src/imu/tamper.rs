@@ -0,0 +1,190 @@
+//! An anti-tamper alarm, for equipment left unattended.
+//!
+//! Once armed with [`arm_alarm`], the first measurement is taken as the resting
+//! attitude, and the alarm is triggered when the acceleration moves away from it by
+//! more than [`TamperConfig::accel_threshold`] or the board turns faster than
+//! [`TamperConfig::gyro_threshold`]. It stays triggered until [`disarm_alarm`].
+//!
+//! The state is shared with the rest of the application, so the alarm can be armed and
+//! disarmed from any task, and every change is published as an [`Event::Alarm`].
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+use icm42670::accelerometer::vector::F32x3;
+
+use super::{Event, Measurement, events, math};
+
+/// Movement that triggered the alarm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tamper {
+    /// Time the alarm was triggered
+    pub at: Instant,
+    /// Change of acceleration from the resting attitude, in g
+    pub accel_change: f32,
+    /// Angular rate, in °/s
+    pub rotation: f32,
+}
+
+/// State of the alarm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmState {
+    Disarmed,
+    /// Watching for movement
+    Armed,
+    /// The board was moved while armed
+    Triggered(Tamper),
+}
+
+/// Tuning of the tamper detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TamperConfig {
+    /// Change of acceleration from the resting attitude that triggers the alarm, in g
+    pub accel_threshold: f32,
+    /// Angular rate that triggers the alarm, in °/s
+    pub gyro_threshold: f32,
+}
+
+impl Default for TamperConfig {
+    fn default() -> Self {
+        Self {
+            accel_threshold: 0.1,
+            gyro_threshold: 20.0,
+        }
+    }
+}
+
+/// Watches for movement while the alarm is armed.
+pub struct TamperDetector {
+    config: TamperConfig,
+    /// Acceleration at rest, captured after arming
+    rest: Option<F32x3>,
+}
+
+impl TamperDetector {
+    /// Create a detector with the given tuning.
+    pub fn new(config: TamperConfig) -> Self {
+        Self { config, rest: None }
+    }
+
+    /// The detector's tuning.
+    pub fn config(&self) -> TamperConfig {
+        self.config
+    }
+
+    /// Feed a measurement, triggering the alarm if it is armed and
+    /// the board has moved. Returns the movement when it does.
+    pub fn update(&mut self, meas: &Measurement) -> Option<Tamper> {
+        let at = meas.at;
+        if alarm_state() != AlarmState::Armed {
+            self.rest = None;
+            return None;
+        }
+        let rest = *self.rest.get_or_insert(meas.accel);
+        let (accel, gyro) = (meas.accel, meas.gyro);
+        let change = [accel.x - rest.x, accel.y - rest.y, accel.z - rest.z];
+        let tamper = Tamper {
+            at,
+            accel_change: math::sqrt(change.iter().map(|c| c * c).sum::<f32>()),
+            rotation: math::sqrt(gyro.x * gyro.x + gyro.y * gyro.y + gyro.z * gyro.z),
+        };
+        let moved = tamper.accel_change > self.config.accel_threshold
+            || tamper.rotation > self.config.gyro_threshold;
+        (moved && trip_alarm(tamper)).then_some(tamper)
+    }
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, Cell<AlarmState>> =
+    Mutex::new(Cell::new(AlarmState::Disarmed));
+
+/// The alarm's current state.
+pub fn alarm_state() -> AlarmState {
+    STATE.lock(Cell::get)
+}
+
+/// Move the alarm to `state`, publishing the change.
+fn set_state(state: AlarmState) {
+    STATE.lock(|current| current.set(state));
+    events::publish(Event::Alarm(state));
+}
+
+/// Arm the alarm, if it is not already armed or triggered.
+pub fn arm_alarm() {
+    if alarm_state() == AlarmState::Disarmed {
+        set_state(AlarmState::Armed);
+    }
+}
+
+/// Disarm the alarm, silencing it if it has been triggered.
+pub fn disarm_alarm() {
+    if alarm_state() != AlarmState::Disarmed {
+        set_state(AlarmState::Disarmed);
+    }
+}
+
+/// Trigger the alarm with a movement picked up elsewhere, such as waking on motion.
+///
+/// Returns whether it was triggered, which it only is while armed.
+pub fn trip_alarm(tamper: Tamper) -> bool {
+    let armed = STATE.lock(|state| {
+        let armed = state.get() == AlarmState::Armed;
+        if armed {
+            state.set(AlarmState::Triggered(tamper));
+        }
+        armed
+    });
+    if armed {
+        events::publish(Event::Alarm(AlarmState::Triggered(tamper)));
+    }
+    armed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_time::Duration;
+
+    fn measurement(accel: [f32; 3], gyro: [f32; 3]) -> Measurement {
+        Measurement::still().with_accel(accel).with_gyro(gyro)
+    }
+
+    // the alarm state is shared, so the whole cycle is checked in one test
+    #[test]
+    fn triggers_only_while_armed() {
+        let mut detector = TamperDetector::new(TamperConfig::default());
+        let start = Instant::from_secs(1);
+        let tilted = measurement([0.0, 0.2, 0.98], [0.0; 3]);
+        assert_eq!(detector.update(&tilted.at(start)), None);
+
+        arm_alarm();
+        assert_eq!(alarm_state(), AlarmState::Armed);
+        // resting on its side is fine, as long as it stays there
+        let resting = measurement([0.0, 0.71, 0.71], [0.3, -0.2, 0.1]);
+        for i in 0..10 {
+            let at = start + Duration::from_millis(10 * i);
+            assert_eq!(detector.update(&resting.at(at)), None);
+        }
+        let turned = measurement([0.0, 0.71, 0.71], [0.0, 0.0, 45.0]);
+        let at = start + Duration::from_millis(100);
+        let tamper = detector.update(&turned.at(at)).unwrap();
+        assert_eq!(tamper.at, at);
+        assert!((tamper.rotation - 45.0).abs() < 0.1, "{:?}", tamper);
+        assert_eq!(alarm_state(), AlarmState::Triggered(tamper));
+        // reported once, staying triggered
+        assert_eq!(detector.update(&turned.at(at)), None);
+        arm_alarm();
+        assert_eq!(alarm_state(), AlarmState::Triggered(tamper));
+
+        disarm_alarm();
+        assert_eq!(alarm_state(), AlarmState::Disarmed);
+        assert_eq!(detector.update(&tilted.at(at)), None);
+        arm_alarm();
+        assert_eq!(detector.update(&resting.at(at)), None);
+        let lifted = measurement([0.0, 0.71, 0.9], [0.0; 3]);
+        let tamper = detector.update(&lifted.at(at)).unwrap();
+        assert!((tamper.accel_change - 0.19).abs() < 0.01, "{:?}", tamper);
+        assert!(!trip_alarm(tamper));
+        disarm_alarm();
+    }
+}
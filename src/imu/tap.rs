@@ -0,0 +1,314 @@
+//! Software detection of single and double taps on the board.
+//!
+//! The ICM-42670-P's APEX engine offers a pedometer, tilt, free-fall and wake-on-motion,
+//! but no tap detection, so taps are picked out of the accelerometer stream instead.
+//! A tap is a short spike of acceleration on top of gravity that dies down again within
+//! [`TapConfig::max_duration`]. A second tap within [`TapConfig::double_window`] of the
+//! first makes it a double tap, so single taps are only reported once that window has passed.
+//!
+//! A tap only lasts a few milliseconds, so the detector needs every sample at a high
+//! output data rate, as delivered in FIFO mode.
+
+use embassy_time::{Duration, Instant};
+
+use super::Measurement;
+
+/// Time constant of the gravity estimate, in seconds.
+const GRAVITY_TIME_CONSTANT: f32 = 0.1;
+
+/// An axis of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// Direction along an axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Positive,
+    Negative,
+}
+
+/// Number of taps in quick succession.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapCount {
+    Single,
+    Double,
+}
+
+/// A detected tap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tap {
+    /// Axis with the strongest spike
+    pub axis: Axis,
+    /// Direction of the spike along the axis, which is the direction the board was pushed
+    pub direction: Direction,
+    /// Single or double tap
+    pub count: TapCount,
+}
+
+/// Tuning of the tap detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TapConfig {
+    /// Acceleration on top of gravity that starts a tap, in g
+    pub threshold: f32,
+    /// Longest spike still counted as a tap, anything longer is movement
+    pub max_duration: Duration,
+    /// Time after a tap during which the board is left to stop ringing
+    pub quiet: Duration,
+    /// Time from the start of one tap to the next for a double tap, zero to
+    /// report every tap as a single tap straight away
+    pub double_window: Duration,
+}
+
+impl Default for TapConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            max_duration: Duration::from_millis(50),
+            quiet: Duration::from_millis(80),
+            double_window: Duration::from_millis(350),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Waiting for a spike
+    Idle,
+    /// A spike is in progress, with its strongest axis and value so far
+    Spike {
+        start: Instant,
+        axis: usize,
+        peak: f32,
+    },
+    /// A spike was too long to be a tap
+    Moving,
+    /// Letting the board ring down after a tap
+    Quiet { until: Instant },
+}
+
+/// Picks taps out of a stream of accelerometer samples.
+pub struct TapDetector {
+    config: TapConfig,
+    state: State,
+    /// Low-passed acceleration, which is gravity while the board is still
+    gravity: Option<[f32; 3]>,
+    /// Time of the previous sample
+    last: Option<Instant>,
+    /// A single tap waiting to see if it becomes a double tap, with its start time
+    pending: Option<(Instant, Tap)>,
+}
+
+impl TapDetector {
+    /// Create a detector with the given tuning.
+    pub fn new(config: TapConfig) -> Self {
+        Self {
+            config,
+            state: State::Idle,
+            gravity: None,
+            last: None,
+            pending: None,
+        }
+    }
+
+    /// The detector's tuning.
+    pub fn config(&self) -> TapConfig {
+        self.config
+    }
+
+    /// Feed a measurement, returning a tap once it is complete.
+    pub fn update(&mut self, meas: &Measurement) -> Option<Tap> {
+        let (accel, at) = (meas.accel, meas.at);
+        let sample = [accel.x, accel.y, accel.z];
+        let dt = self
+            .last
+            .and_then(|last| at.checked_duration_since(last))
+            .map_or(0.0, |dt| dt.as_micros() as f32 / 1_000_000.0);
+        self.last = Some(at);
+        let Some(gravity) = self.gravity else {
+            self.gravity = Some(sample);
+            return None;
+        };
+
+        let expired = self
+            .pending
+            .take_if(|(start, _)| at.saturating_duration_since(*start) > self.config.double_window)
+            .map(|(_, tap)| tap);
+
+        let delta: [f32; 3] = core::array::from_fn(|i| sample[i] - gravity[i]);
+        let axis = (0..3)
+            .max_by(|&a, &b| delta[a].abs().total_cmp(&delta[b].abs()))
+            .unwrap_or(2);
+        let above = delta[axis].abs() > self.config.threshold;
+
+        let mut tap = None;
+        self.state = match self.state {
+            State::Idle if above => State::Spike {
+                start: at,
+                axis,
+                peak: delta[axis],
+            },
+            State::Spike { start, .. }
+                if at.saturating_duration_since(start) > self.config.max_duration =>
+            {
+                State::Moving
+            }
+            State::Spike { start, peak, .. } if above && delta[axis].abs() > peak.abs() => {
+                State::Spike {
+                    start,
+                    axis,
+                    peak: delta[axis],
+                }
+            }
+            State::Spike { start, axis, peak } if !above => {
+                tap = self.tapped(start, axis, peak);
+                State::Quiet {
+                    until: at + self.config.quiet,
+                }
+            }
+            State::Moving if !above => State::Idle,
+            State::Quiet { until } if at >= until && !above => State::Idle,
+            state => state,
+        };
+
+        // keep the spike itself out of the gravity estimate
+        if !matches!(self.state, State::Spike { .. }) {
+            let alpha = dt / (GRAVITY_TIME_CONSTANT + dt);
+            self.gravity = Some(core::array::from_fn(|i| {
+                gravity[i] + alpha * (sample[i] - gravity[i])
+            }));
+        }
+        expired.or(tap)
+    }
+
+    /// Turn a completed spike into a tap, pairing it with a pending one into a double tap.
+    fn tapped(&mut self, start: Instant, axis: usize, peak: f32) -> Option<Tap> {
+        let tap = Tap {
+            axis: match axis {
+                0 => Axis::X,
+                1 => Axis::Y,
+                _ => Axis::Z,
+            },
+            direction: if peak > 0.0 {
+                Direction::Positive
+            } else {
+                Direction::Negative
+            },
+            count: TapCount::Single,
+        };
+        if self.config.double_window == Duration::from_ticks(0) {
+            return Some(tap);
+        }
+        match self.pending.take() {
+            Some((_, first)) => Some(Tap {
+                count: TapCount::Double,
+                ..first
+            }),
+            None => {
+                self.pending = Some((start, tap));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample period at 400 Hz.
+    const PERIOD: Duration = Duration::from_micros(2_500);
+    const LEVEL: [f32; 3] = [0.0, 0.0, 1.0];
+
+    /// Feed samples at 400 Hz, returning the taps with the sample index they came out at.
+    fn run(detector: &mut TapDetector, samples: &[[f32; 3]]) -> std::vec::Vec<(usize, Tap)> {
+        let start = Instant::from_secs(1);
+        samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &accel)| {
+                let meas = Measurement::still()
+                    .with_accel(accel)
+                    .at(start + PERIOD * i as u32);
+                detector.update(&meas).map(|tap| (i, tap))
+            })
+            .collect()
+    }
+
+    /// Samples resting level with a 3 sample spike at each of `taps`.
+    fn trace(len: usize, taps: &[usize], spike: [f32; 3]) -> std::vec::Vec<[f32; 3]> {
+        (0..len)
+            .map(|i| {
+                if taps.iter().any(|&tap| (tap..tap + 3).contains(&i)) {
+                    spike
+                } else {
+                    LEVEL
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_tap_is_reported_after_double_window() {
+        let mut detector = TapDetector::new(TapConfig::default());
+        let taps = run(&mut detector, &trace(400, &[40], [0.0, 0.0, -1.5]));
+        assert_eq!(
+            taps,
+            [(
+                // just over 350ms after the tap started
+                181,
+                Tap {
+                    axis: Axis::Z,
+                    direction: Direction::Negative,
+                    count: TapCount::Single,
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn two_taps_make_a_double_tap() {
+        let mut detector = TapDetector::new(TapConfig::default());
+        let taps = run(&mut detector, &trace(400, &[40, 100], [1.8, 0.2, 1.0]));
+        assert_eq!(
+            taps,
+            [(
+                // as soon as the second spike ends
+                103,
+                Tap {
+                    axis: Axis::X,
+                    direction: Direction::Positive,
+                    count: TapCount::Double,
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn taps_are_immediate_without_double_window() {
+        let mut detector = TapDetector::new(TapConfig {
+            double_window: Duration::from_ticks(0),
+            ..Default::default()
+        });
+        let taps = run(&mut detector, &trace(400, &[40, 100], [0.0, -1.5, 1.0]));
+        assert_eq!(
+            taps.iter().map(|(i, _)| *i).collect::<std::vec::Vec<_>>(),
+            [43, 103]
+        );
+        assert!(taps.iter().all(|(_, tap)| tap.count == TapCount::Single));
+    }
+
+    #[test]
+    fn ignores_pushes_and_turning_over() {
+        let mut detector = TapDetector::new(TapConfig::default());
+        // a 200ms push along X, then laying the board face down
+        let mut samples = std::vec![LEVEL; 40];
+        samples.extend([[2.0, 0.0, 1.0]; 80]);
+        samples.extend([LEVEL; 200]);
+        samples.extend([[0.0, 0.0, -1.0]; 400]);
+        assert!(run(&mut detector, &samples).is_empty());
+    }
+}
@@ -4,44 +4,44 @@
 //! The actor can be controlled by sending messages to it.
 //! The actor can be created with a spawner and a configuration.
 
-use actor_private::*;
-use ector::{ActorContext, mutex::NoopRawMutex};
-use embassy_executor::Spawner;
-use esp_hal::rmt;
-use esp_hal_smartled::SmartLedsAdapterAsync;
-use log::info;
-use smart_leds::{RGB8, SmartLedsWriteAsync, brightness, colors::BLACK, gamma};
+#[cfg(feature = "hardware")]
 use {
+    crate::{ActorInbox, AppError},
+    actor_private::*,
     core::future::pending,
-    embassy_executor::SpawnError,
+    ector::{ActorContext, mutex::NoopRawMutex},
+    embassy_executor::{SpawnError, Spawner},
     embassy_futures::select::{Either, select},
     embassy_time::{Duration, Timer},
+    esp_hal::rmt,
+    esp_hal_smartled::SmartLedsAdapterAsync,
+    log::info,
+    sequence::{Cursor, Step},
+    smart_leds::{RGB8, SmartLedsWriteAsync, brightness, colors::BLACK, gamma},
 };
 
-use crate::{ActorInbox, AppError};
+// only the actor steps through sequences, so without it only the tests do
+#[cfg_attr(not(feature = "hardware"), allow(dead_code))]
+mod sequence;
 
+pub use sequence::Repeat;
+
+#[cfg(feature = "hardware")]
 pub type Led = SmartLedsAdapterAsync<rmt::Channel<esp_hal::Async, 0>, 25>;
 
 /// Set the colour and brightness of the specified LED.
+#[cfg(feature = "hardware")]
 pub async fn write(led: &mut Led, colour: RGB8, level: u8) -> Result<(), AppError> {
     led.write(brightness(gamma([colour].into_iter()), level))
         .await
         .map_err(AppError::LedWrite)
 }
 
-/// The actor's repeat mode.
+#[cfg(feature = "hardware")]
 #[derive(Clone, Copy)]
-pub enum Repeat {
-    /// Run the sequence once
-    Once,
-    /// Run the sequence a fixed number of times
-    N(u8),
-    /// Run the sequence forever
-    Forever,
-}
-
 pub struct LedActor(ActorInbox<Message>);
 
+#[cfg(feature = "hardware")]
 impl LedActor {
     /// Turn on the LED
     pub fn on(&self) -> Result<(), AppError> {
@@ -82,6 +82,7 @@ impl LedActor {
 
 /// Create a new actor with a spawner and a configuration.
 /// This pattern could be made into a macro to simplify the actor creation.
+#[cfg(feature = "hardware")]
 pub fn spawn_actor(spawner: Spawner, led: Led) -> Result<LedActor, SpawnError> {
     static CONTEXT: ActorContext<Actor, NoopRawMutex, 10> = ActorContext::new();
     let inbox = CONTEXT.address();
@@ -89,6 +90,7 @@ pub fn spawn_actor(spawner: Spawner, led: Led) -> Result<LedActor, SpawnError> {
     Ok(LedActor(inbox))
 }
 
+#[cfg(feature = "hardware")]
 mod actor_private {
 
     use ector::{DynamicAddress, Inbox};
@@ -116,12 +118,8 @@ mod actor_private {
         timer: Timer,
         /// The period between actions
         period: Duration,
-        /// The current sequence of colours
-        sequence: &'static [RGB8],
-        /// The current index in the sequence
-        index: usize,
-        /// The current repeat mode
-        repeat: Repeat,
+        /// The position in the current sequence of colours
+        cursor: Cursor,
     }
 
     /// The actor's private data, not to be shared with other actors.
@@ -195,9 +193,7 @@ mod actor_private {
                     self.scheduler = Some(Scheduler {
                         timer: Timer::after(period),
                         period,
-                        sequence,
-                        index: 0,
-                        repeat,
+                        cursor: Cursor::new(sequence, repeat),
                     });
                     Ok(())
                 }
@@ -210,20 +206,10 @@ mod actor_private {
             };
             scheduler.timer = Timer::after(scheduler.period);
             // run the next action in the sequence.
-            match scheduler.sequence.get(scheduler.index) {
-                Some(&colour) => {
-                    write(&mut self.led, colour, self.brightness).await?;
-                    scheduler.index += 1;
-                }
-                None => {
-                    // if we've reached the end of the sequence, handle the repeat mode.
-                    match scheduler.repeat {
-                        Repeat::Once => self.scheduler = None,
-                        Repeat::N(0) => self.scheduler = None,
-                        Repeat::N(n) => scheduler.repeat = Repeat::N(n - 1),
-                        Repeat::Forever => scheduler.index = 0,
-                    }
-                }
+            match scheduler.cursor.next() {
+                Step::Colour(colour) => write(&mut self.led, colour, self.brightness).await?,
+                Step::Pause => {}
+                Step::Done => self.scheduler = None,
             };
             Ok(())
         }
@@ -0,0 +1,102 @@
+//! Stepping through a sequence of colours, run as many times as its [`Repeat`] mode asks.
+
+use smart_leds::RGB8;
+
+/// The actor's repeat mode.
+#[derive(Clone, Copy)]
+pub enum Repeat {
+    /// Run the sequence once
+    Once,
+    /// Run the sequence a fixed number of times
+    N(u8),
+    /// Run the sequence forever
+    Forever,
+}
+
+/// What to do on the next tick of a sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Step {
+    /// Show a colour
+    Colour(RGB8),
+    /// Hold the last colour, between runs of the sequence
+    Pause,
+    /// The sequence has finished
+    Done,
+}
+
+/// Position in a sequence of colours.
+pub(super) struct Cursor {
+    /// The sequence of colours
+    sequence: &'static [RGB8],
+    /// The index of the next colour
+    index: usize,
+    /// The runs still to go
+    repeat: Repeat,
+}
+
+impl Cursor {
+    /// Start at the first colour of `sequence`.
+    pub(super) fn new(sequence: &'static [RGB8], repeat: Repeat) -> Self {
+        Self {
+            sequence,
+            index: 0,
+            repeat,
+        }
+    }
+    /// Advance by a tick.
+    pub(super) fn next(&mut self) -> Step {
+        if let Some(&colour) = self.sequence.get(self.index) {
+            self.index += 1;
+            return Step::Colour(colour);
+        }
+        // at the end of the sequence, each repeat starts again from its first colour
+        match self.repeat {
+            Repeat::Once | Repeat::N(0) => Step::Done,
+            Repeat::N(n) => {
+                self.repeat = Repeat::N(n - 1);
+                self.index = 0;
+                Step::Pause
+            }
+            Repeat::Forever => {
+                self.index = 0;
+                Step::Pause
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_leds::colors::{BLACK, RED};
+
+    fn run(cursor: &mut Cursor, ticks: usize) -> Vec<Step> {
+        (0..ticks).map(|_| cursor.next()).collect()
+    }
+
+    #[test]
+    fn repeats_from_the_first_colour() {
+        use Step::*;
+        let mut once = Cursor::new(&[RED, BLACK], Repeat::Once);
+        assert_eq!(run(&mut once, 3), [Colour(RED), Colour(BLACK), Done]);
+
+        let mut twice = Cursor::new(&[RED, BLACK], Repeat::N(1));
+        assert_eq!(
+            run(&mut twice, 6),
+            [
+                Colour(RED),
+                Colour(BLACK),
+                Pause,
+                Colour(RED),
+                Colour(BLACK),
+                Done
+            ]
+        );
+
+        let mut forever = Cursor::new(&[RED], Repeat::Forever);
+        assert_eq!(
+            run(&mut forever, 5),
+            [Colour(RED), Pause, Colour(RED), Pause, Colour(RED)]
+        );
+    }
+}
@@ -1,22 +1,38 @@
-#![no_std]
+//! Everything that needs the chip is behind the default `hardware` feature, so that the
+//! sensor drivers and detectors can be tested on the host against a fake I2C bus with
+//! `cargo test-host`.
 
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(feature = "hardware")]
 use ector::mutex::NoopRawMutex;
+#[cfg(feature = "hardware")]
 use embassy_sync::channel::Sender;
+#[cfg(feature = "hardware")]
 use esp_hal_smartled::LedAdapterError;
 use thiserror::Error;
 
 pub mod ambient;
+#[cfg(feature = "hardware")]
 pub mod ble;
+#[cfg(feature = "hardware")]
 pub mod bsp;
+#[cfg(feature = "hardware")]
 pub mod buttons;
 pub mod imu;
 pub mod led;
+pub mod selftest;
+
+#[cfg(test)]
+mod mock;
 
 /// Alias for the actor's inbox
+#[cfg(feature = "hardware")]
 pub type ActorInbox<M> = Sender<'static, NoopRawMutex, M, 10>;
 
 #[derive(Debug, Error)]
 pub enum AppError {
+    #[cfg(feature = "hardware")]
     #[error("Failed to write to LED: {0:?}")]
     LedWrite(LedAdapterError),
     #[error("Failed to send message to LED actor")]
@@ -29,4 +45,20 @@ pub enum AppError {
     InvalidReadPeriod(u64, u64),
     #[error("Failed to read from Ambient Sensor")]
     AmbientI2cRead,
+    #[error("Failed to communicate with the IMU")]
+    ImuI2c,
+    #[error("Expected an ICM42670 but WHO_AM_I read {0:#04x}")]
+    ImuBadChip(u8),
+    #[error("IMU output data rate {0}Hz is not supported in the current power mode")]
+    InvalidImuOdr(f32),
+    #[error("IMU filter bandwidth {0}Hz must be less than half the output data rate {1}Hz")]
+    InvalidImuBandwidth(f32, f32),
+    #[error("The IMU moved while capturing a still period")]
+    ImuNotStill,
+    #[error("Failed to store the IMU calibration")]
+    CalibrationStore,
+    #[error("Failed to read the gesture model")]
+    GestureModelLoad,
+    #[error("The gesture model is missing or invalid")]
+    InvalidGestureModel,
 }
@@ -1,58 +1,296 @@
 #![no_std]
 #![no_main]
 
-use embassy_futures::select::select3;
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{Either, select, select4};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::gpio::Input;
 use esp32c3_devkit_demo::{
     ambient::AmbientSensor,
-    ble::{GattServer, advertise},
-    bsp::Board,
-    imu::ImuSensor,
-    led::{self, Repeat},
+    ble::{BleController, GattServer, advertise},
+    bsp::{Board, CALIBRATION_OFFSET, GESTURE_MODEL_OFFSET},
+    buttons::wait_for_release,
+    imu::{
+        self, Activity, ActivityConfig, AlarmState, Calibration, Detectors, Event, EventSubscriber,
+        FallConfig, Gesture, GestureConfig, GestureModel, ImuConfig, ImuSensor, LEFT_BUTTON,
+        MAX_MODEL_LEN, Odr, PedometerConfig, PointerConfig, Pose, PoseConfig, PowerMode as ImuMode,
+        ShockConfig, SpectrumConfig, Tamper, TamperConfig, TapConfig, WakeOnMotion,
+    },
+    led::{self, LedActor, Repeat},
+    selftest,
 };
-use icm42670::PowerMode as ImuMode;
-use log::info;
+use log::{error, info};
 use shtcx::PowerMode as AmbMode;
-use smart_leds::colors::{BLUE, GREEN, RED};
-use trouble_host::prelude::appearance;
+use smart_leds::colors::{BLACK, BLUE, GREEN, ORANGE, RED, WHITE};
+use static_cell::StaticCell;
+use trouble_host::prelude::{Peripheral, appearance};
 
 use esp_backtrace as _;
 
+/// How long to advertise for before idling until the board is moved.
+const ADVERTISE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Whether to show the wearer's activity as the LED colour while connected.
+const SHOW_ACTIVITY: bool = true;
+/// How long to hold the boot button down to arm or disarm the anti-tamper alarm.
+const ALARM_HOLD: Duration = Duration::from_secs(2);
+/// How long after the button is released before the alarm is armed, to put the board down.
+const ARM_DELAY: Duration = Duration::from_secs(10);
+/// How often the IMU's FIFO is drained, notifying the latest sample while connected.
+const IMU_PERIOD: Duration = Duration::from_hz(20);
+/// How long each check of the self-test report is shown on the LED.
+const SELF_TEST_STEP: Duration = Duration::from_millis(300);
+/// Whether to move the connected host's cursor with the board, clicking with the boot button.
+/// The board then presents itself as a mouse, which the host pairs with.
+const AIR_MOUSE: bool = false;
+
 #[esp_hal_embassy::main]
 async fn main(spawner: embassy_executor::Spawner) -> ! {
     let name = "Esp devkit demo";
-    let appearance = &appearance::sensor::MULTISENSOR;
-    let board = Board::init();
+    let appearance = if AIR_MOUSE {
+        &appearance::human_interface_device::MOUSE
+    } else {
+        &appearance::sensor::MULTISENSOR
+    };
+    let mut board = Board::init();
 
-    let (server, mut peripheral) =
-        GattServer::start(name, appearance, spawner, board.ble_controller);
+    let (server, mut peripheral) = GattServer::start(
+        name,
+        appearance,
+        spawner,
+        board.ble_controller,
+        &mut board.trng,
+    );
 
     let led = led::spawn_actor(spawner, board.led).expect("failed to spawn led actor");
     led.set_brightness(50).unwrap();
     let sequence = &[RED, GREEN, BLUE];
 
-    let mut imu = ImuSensor::new(board.i2c_bus);
+    let report = selftest::run(board.i2c_bus).await;
+    led.set_sequence(report.pattern(), SELF_TEST_STEP, Repeat::Once)
+        .unwrap();
+    Timer::after(SELF_TEST_STEP * report.pattern().len() as u32).await;
+
+    let mut imu = match ImuSensor::new(board.i2c_bus) {
+        Ok(imu) => imu,
+        Err(error) => {
+            error!("{}, serving the self-test report only", error);
+            led.set_sequence(report.pattern(), SELF_TEST_STEP, Repeat::Forever)
+                .unwrap();
+            serve_self_test(server, &mut peripheral).await
+        }
+    };
+    match Calibration::load(&mut board.flash, CALIBRATION_OFFSET) {
+        Some(calibration) => imu.set_calibration(calibration),
+        None => info!("No IMU calibration stored, run the calibrate example"),
+    }
+    // taps only last a few milliseconds, so buffer every sample in the FIFO at 400Hz.
+    imu.configure(ImuConfig {
+        odr: Odr::Hz400,
+        ..Default::default()
+    })
+    .expect("valid IMU config");
+    imu.enable_fifo(20).expect("sensor available");
+    let mut detectors = Detectors::default();
+    detectors.enable_taps(TapConfig::default());
+    detectors.enable_falls(FallConfig::default());
+    detectors.enable_shocks(ShockConfig::default());
+    detectors.enable_pedometer(PedometerConfig::default());
+    detectors.enable_spectrum(imu.config().odr.hz(), SpectrumConfig::default());
+    detectors.enable_activity(ActivityConfig::default());
+    detectors.enable_tamper(TamperConfig::default());
+    detectors.enable_pose(PoseConfig::default());
+    if AIR_MOUSE {
+        detectors.enable_pointer(PointerConfig::default());
+    }
+    static GESTURE_MODEL: StaticCell<[u8; MAX_MODEL_LEN]> = StaticCell::new();
+    let buffer = GESTURE_MODEL.init([0; MAX_MODEL_LEN]);
+    match GestureModel::load(&mut board.flash, GESTURE_MODEL_OFFSET, buffer) {
+        Ok(model) => detectors.enable_gestures(model, GestureConfig::default()),
+        Err(error) => info!("{}, flash one to recognise gestures", error),
+    }
+    let events = imu::subscribe().expect("IMU event subscriber available");
+    let pointer_reports = imu::pointer_reports();
+    spawner.must_spawn(imu_led_task(led, events, SHOW_ACTIVITY));
+    spawner.must_spawn(button_task(board.button, AIR_MOUSE));
     let mut ambient = AmbientSensor::new(board.i2c_bus);
     Timer::after(Duration::from_secs(1)).await;
 
     loop {
         info!("Advertising for BLE Connection...");
-        led.set_sequence(sequence, Duration::from_secs(1), Repeat::Forever)
-            .unwrap();
-        let adv = advertise("Esp32c3-devkit-rust", &mut peripheral, server);
-        if let Ok(conn) = adv.await {
-            let ble = (server, &conn);
-            led.off().unwrap();
-            imu.set_power_mode(ImuMode::SixAxisLowNoise)
-                .expect("sensor available");
+        if !alarm_triggered() && !face_down() {
+            led.set_sequence(sequence, Duration::from_secs(1), Repeat::Forever)
+                .unwrap();
+        }
+        // the detectors keep running while advertising, so the alarm is always watched
+        imu.set_power_mode(ImuMode::SixAxisLowNoise)
+            .expect("sensor available");
+        let adv = advertise("Esp32c3-devkit-rust", AIR_MOUSE, &mut peripheral, server);
+        let watch = select(
+            imu.start_task(IMU_PERIOD, |meas| detectors.update(meas), async |_| {}),
+            Timer::after(ADVERTISE_TIMEOUT),
+        );
+        let conn = match select(adv, watch).await {
+            Either::First(conn) => conn,
+            Either::Second(watched) => {
+                if let Either::First(Err(error)) = watched {
+                    error!("IMU task stopped: {}", error);
+                }
+                info!("Nobody connected, idling until the board is moved");
+                if !alarm_triggered() {
+                    led.off().unwrap();
+                }
+                let wake = WakeOnMotion::default();
+                imu.wait_for_motion(wake).await.expect("sensor available");
+                // moving the board at all while armed is tampering with it
+                imu::trip_alarm(Tamper {
+                    at: Instant::now(),
+                    accel_change: wake.threshold,
+                    rotation: 0.0,
+                });
+                continue;
+            }
+        };
+        if let Ok(conn) = conn {
+            if !alarm_triggered() {
+                led.off().unwrap();
+            }
             ambient
                 .set_power_mode(AmbMode::LowPower, Duration::from_millis(100))
                 .unwrap();
 
-            let imu_task = imu.start_task(Duration::from_hz(20), Some(ble));
-            let amb_task = ambient.start_task(Duration::from_hz(1), Some(ble));
+            let imu_task = imu.start_task(
+                IMU_PERIOD,
+                |meas| detectors.update(meas),
+                async |meas| {
+                    if let Err(error) = server.notify_imu(&conn, meas).await {
+                        error!("Error notifying BLE: {:?}", error);
+                    }
+                },
+            );
+            let amb_task = ambient.start_task(Duration::from_hz(1), async |sample| {
+                if let Err(error) = server.notify_ambient(&conn, sample).await {
+                    error!("Error notifying BLE: {:?}", error);
+                }
+            });
             let gatt_task = server.start_task(&conn);
-            select3(imu_task, amb_task, gatt_task).await;
+            let mut events = imu::subscribe().expect("IMU event subscriber available");
+            let events_task = server.notify_imu_events(&conn, &mut events);
+            let pointer_task = server.notify_pointer(&conn, &pointer_reports);
+            select4(
+                imu_task,
+                amb_task,
+                gatt_task,
+                select(events_task, pointer_task),
+            )
+            .await;
+        }
+    }
+}
+
+/// Advertise and serve the GATT server on its own, so that the self-test report can
+/// be read while the IMU is unavailable.
+async fn serve_self_test(
+    server: &'static GattServer<'static>,
+    peripheral: &mut Peripheral<'static, BleController>,
+) -> ! {
+    loop {
+        info!("Advertising for BLE Connection...");
+        if let Ok(conn) = advertise("Esp32c3-devkit-rust", false, peripheral, server).await {
+            if let Err(error) = server.start_task(&conn).await {
+                error!("BLE connection failed: {:?}", error);
+            }
+        }
+    }
+}
+
+/// Whether the anti-tamper alarm is going off, which nothing else should hide.
+fn alarm_triggered() -> bool {
+    matches!(imu::alarm_state(), AlarmState::Triggered(_))
+}
+
+/// Whether the board is lying face down, when the LED is kept off.
+fn face_down() -> bool {
+    imu::current_pose() == Some(Pose::FaceDown)
+}
+
+/// Flash the LED red whenever the IMU detects a fall, play a sequence for each gesture,
+/// and optionally show the wearer's activity: off when still, green walking, orange
+/// running and blue in a vehicle. The LED is turned off while the board lies face down.
+///
+/// The LED blinks red when the anti-tamper alarm is armed and green when it is disarmed,
+/// and flashes red until disarmed once it is triggered.
+#[embassy_executor::task]
+async fn imu_led_task(led: LedActor, mut events: EventSubscriber, show_activity: bool) {
+    let mut is_face_down = false;
+    loop {
+        let event = events.next_message_pure().await;
+        let was_face_down = is_face_down;
+        if let Event::Pose(pose) = event {
+            is_face_down = pose == Pose::FaceDown;
+        }
+        match event {
+            Event::Alarm(state) => {
+                let (sequence, period, repeat): (&'static [_], _, _) = match state {
+                    AlarmState::Disarmed => (&[GREEN, BLACK], 200, Repeat::N(2)),
+                    AlarmState::Armed => (&[RED, BLACK], 200, Repeat::N(2)),
+                    AlarmState::Triggered(_) => (&[RED, BLACK], 100, Repeat::Forever),
+                };
+                led.set_sequence(sequence, Duration::from_millis(period), repeat)
+                    .unwrap()
+            }
+            _ if alarm_triggered() => {}
+            Event::Pose(_) if is_face_down => led.off().unwrap(),
+            Event::Pose(_) if was_face_down => led.on().unwrap(),
+            _ if is_face_down => {}
+            Event::Fall(_) => led
+                .set_sequence(&[RED, BLACK], Duration::from_millis(100), Repeat::N(10))
+                .unwrap(),
+            Event::Gesture(event) => {
+                let (sequence, period): (&'static [_], _) = match event.gesture {
+                    Gesture::Shake => (&[WHITE, BLACK], Duration::from_millis(50)),
+                    Gesture::Circle => (&[RED, GREEN, BLUE], Duration::from_millis(150)),
+                    Gesture::FlickLeft => (&[BLUE, BLACK], Duration::from_millis(200)),
+                    Gesture::FlickRight => (&[GREEN, BLACK], Duration::from_millis(200)),
+                };
+                led.set_sequence(sequence, period, Repeat::N(3)).unwrap()
+            }
+            Event::Activity(update) if show_activity => match update.activity {
+                Activity::Still => led.off().unwrap(),
+                Activity::Walking => led.set_colour(GREEN).unwrap(),
+                Activity::Running => led.set_colour(ORANGE).unwrap(),
+                Activity::Vehicle => led.set_colour(BLUE).unwrap(),
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Arm the anti-tamper alarm [`ARM_DELAY`] after the boot button is held down, unless it is
+/// pressed again in the meantime, and disarm it when held down again. Shorter presses click
+/// the air mouse's left button, if enabled.
+#[embassy_executor::task]
+async fn button_task(mut button: Input<'static>, air_mouse: bool) {
+    loop {
+        if wait_for_release(&mut button).await < ALARM_HOLD {
+            if air_mouse {
+                imu::click(LEFT_BUTTON);
+            }
+            continue;
+        }
+        match imu::alarm_state() {
+            AlarmState::Disarmed => {
+                info!("Alarm arming in {} s", ARM_DELAY.as_secs());
+                match select(Timer::after(ARM_DELAY), wait_for_release(&mut button)).await {
+                    Either::First(()) => {
+                        info!("Alarm armed");
+                        imu::arm_alarm();
+                    }
+                    Either::Second(_) => info!("Arming cancelled"),
+                }
+            }
+            AlarmState::Armed | AlarmState::Triggered(_) => {
+                info!("Alarm disarmed");
+                imu::disarm_alarm();
+            }
         }
     }
 }
@@ -0,0 +1,296 @@
+//! A fake I2C bus for host-side tests, and a builder of measurements to feed the detectors.
+//!
+//! Emulates the register map of the ICM42670 and the command interface of the SHTC3
+//! closely enough to drive the sensor wrappers without a board attached.
+//! The bus is cheaply cloneable, so a test can keep a handle to inspect and script
+//! the devices after the sensor under test has taken ownership of its copy.
+
+use core::cell::RefCell;
+use embassy_time::Instant;
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use icm42670::accelerometer::vector::F32x3;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::imu::Measurement;
+
+/// I2C address of the ICM42670.
+pub const IMU_ADDRESS: u8 = 0x68;
+/// I2C address of the SHTC3.
+pub const AMBIENT_ADDRESS: u8 = 0x70;
+
+/// ICM42670 register addresses.
+pub use crate::imu::registers as reg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockError(pub ErrorKind);
+
+impl embedded_hal::i2c::Error for MockError {
+    fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
+/// The shared state of the fake bus.
+pub struct State {
+    /// ICM42670 register file.
+    pub imu: [u8; 256],
+    /// ICM42670 MREG1 bank.
+    pub mreg1: [u8; 256],
+    /// Bytes waiting in the ICM42670 FIFO.
+    pub fifo: VecDeque<u8>,
+    /// Register pointer for the next ICM42670 read.
+    pointer: u8,
+    /// Raw bytes returned for the next SHTC3 read.
+    pub ambient: [u8; 6],
+    /// Last command written to the SHTC3.
+    pub ambient_command: Option<u16>,
+    /// Fail every transaction once this many have succeeded.
+    pub fail_after: Option<usize>,
+    /// Number of successful transactions.
+    pub transactions: usize,
+}
+
+#[derive(Clone)]
+pub struct MockBus(Rc<RefCell<State>>);
+
+impl Default for MockBus {
+    fn default() -> Self {
+        let mut imu = [0; 256];
+        imu[reg::WHO_AM_I as usize] = 0x67;
+        imu[reg::GYRO_CONFIG0 as usize] = 0x06;
+        imu[reg::ACCEL_CONFIG0 as usize] = 0x06;
+        Self(Rc::new(RefCell::new(State {
+            imu,
+            mreg1: [0; 256],
+            fifo: VecDeque::new(),
+            pointer: 0,
+            ambient: [0; 6],
+            ambient_command: None,
+            fail_after: None,
+            transactions: 0,
+        })))
+    }
+}
+
+impl MockBus {
+    /// Access the shared state of the bus.
+    pub fn state(&self) -> core::cell::RefMut<'_, State> {
+        self.0.borrow_mut()
+    }
+    /// Make every transaction from now on fail with a NACK.
+    pub fn disconnect(&self) {
+        let mut state = self.state();
+        state.fail_after = Some(state.transactions);
+    }
+    /// Read an ICM42670 register.
+    pub fn register(&self, reg: u8) -> u8 {
+        self.state().imu[reg as usize]
+    }
+    /// Write an ICM42670 register.
+    pub fn set_register(&self, reg: u8, value: u8) {
+        self.state().imu[reg as usize] = value;
+    }
+    /// Read an ICM42670 MREG1 register.
+    pub fn mreg1(&self, reg: u8) -> u8 {
+        self.state().mreg1[reg as usize]
+    }
+    /// Queue an accelerometer, gyroscope and timestamp packet in the ICM42670 FIFO.
+    pub fn push_fifo_packet(&self, accel: [i16; 3], gyro: [i16; 3], temp: i8, timestamp: u16) {
+        let mut state = self.state();
+        state.fifo.push_back(0b0110_1011);
+        for value in accel.iter().chain(gyro.iter()) {
+            state.fifo.extend(value.to_be_bytes());
+        }
+        state.fifo.push_back(temp as u8);
+        state.fifo.extend(timestamp.to_be_bytes());
+    }
+    /// Write three big-endian 16 bit values starting at `reg`.
+    pub fn set_vector(&self, reg: u8, raw: [i16; 3]) {
+        let mut state = self.state();
+        for (i, value) in raw.iter().enumerate() {
+            let [hi, lo] = value.to_be_bytes();
+            state.imu[reg as usize + 2 * i] = hi;
+            state.imu[reg as usize + 2 * i + 1] = lo;
+        }
+    }
+    /// Script the next SHTC3 result from raw temperature and humidity ticks.
+    pub fn set_ambient(&self, temperature: u16, humidity: u16) {
+        let [t0, t1] = temperature.to_be_bytes();
+        let [h0, h1] = humidity.to_be_bytes();
+        self.state().ambient = [t0, t1, crc8(&[t0, t1]), h0, h1, crc8(&[h0, h1])];
+    }
+
+    fn imu_transaction(state: &mut State, operations: &mut [Operation<'_>]) {
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    let Some((&reg, data)) = bytes.split_first() else {
+                        continue;
+                    };
+                    state.pointer = reg;
+                    for &byte in data {
+                        let pointer = state.pointer;
+                        Self::imu_write(state, pointer, byte);
+                        state.pointer = state.pointer.wrapping_add(1);
+                    }
+                }
+                Operation::Read(buffer) => {
+                    for byte in buffer.iter_mut() {
+                        let pointer = state.pointer;
+                        *byte = Self::imu_read(state, pointer);
+                        if state.pointer != reg::FIFO_DATA {
+                            state.pointer = state.pointer.wrapping_add(1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn imu_write(state: &mut State, reg: u8, value: u8) {
+        match reg {
+            reg::M_W => {
+                let addr = state.imu[reg::MADDR_W as usize];
+                state.mreg1[addr as usize] = value;
+            }
+            reg::SIGNAL_PATH_RESET if value & reg::FIFO_FLUSH != 0 => state.fifo.clear(),
+            _ => state.imu[reg as usize] = value,
+        }
+    }
+
+    fn imu_read(state: &mut State, reg: u8) -> u8 {
+        match reg {
+            reg::FIFO_DATA => state.fifo.pop_front().unwrap_or(0xFF),
+            reg::FIFO_COUNTH => (state.fifo.len() >> 8) as u8,
+            reg::FIFO_COUNTL => state.fifo.len() as u8,
+            reg::M_R => {
+                let addr = state.imu[reg::MADDR_R as usize];
+                state.mreg1[addr as usize]
+            }
+            _ => state.imu[reg as usize],
+        }
+    }
+
+    fn ambient_transaction(state: &mut State, operations: &mut [Operation<'_>]) {
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) if bytes.len() == 2 => {
+                    state.ambient_command = Some(u16::from_be_bytes([bytes[0], bytes[1]]));
+                }
+                Operation::Write(_) => {}
+                Operation::Read(buffer) => {
+                    let len = buffer.len().min(state.ambient.len());
+                    buffer[..len].copy_from_slice(&state.ambient[..len]);
+                }
+            }
+        }
+    }
+}
+
+impl ErrorType for MockBus {
+    type Error = MockError;
+}
+
+impl I2c for MockBus {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut state = self.state();
+        if state.fail_after.is_some_and(|n| state.transactions >= n) {
+            return Err(MockError(ErrorKind::NoAcknowledge(
+                NoAcknowledgeSource::Address,
+            )));
+        }
+        match address {
+            IMU_ADDRESS => Self::imu_transaction(&mut state, operations),
+            AMBIENT_ADDRESS => Self::ambient_transaction(&mut state, operations),
+            _ => {
+                return Err(MockError(ErrorKind::NoAcknowledge(
+                    NoAcknowledgeSource::Address,
+                )));
+            }
+        }
+        state.transactions += 1;
+        Ok(())
+    }
+}
+
+/// Sensirion CRC-8 (polynomial 0x31, initial value 0xFF).
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xFF_u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Measurements for the detector tests, built up from the board lying still.
+impl Measurement {
+    /// The board lying still and face up, at boot.
+    pub fn still() -> Self {
+        Self {
+            at: Instant::MIN,
+            sequence: 0,
+            accel: F32x3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            gyro: F32x3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            inclination: None,
+            orientation: None,
+            linear_accel: None,
+            temperature: 25.0,
+        }
+    }
+    /// With the acceleration in g.
+    pub fn with_accel(self, [x, y, z]: [f32; 3]) -> Self {
+        Self {
+            accel: F32x3 { x, y, z },
+            ..self
+        }
+    }
+    /// With the angular rate in °/s.
+    pub fn with_gyro(self, [x, y, z]: [f32; 3]) -> Self {
+        Self {
+            gyro: F32x3 { x, y, z },
+            ..self
+        }
+    }
+    /// With the inclination from the gimbal, in degrees.
+    pub fn with_inclination(self, inclination: F32x3) -> Self {
+        Self {
+            inclination: Some(inclination),
+            ..self
+        }
+    }
+    /// With the die temperature in °C.
+    pub fn with_temperature(self, temperature: f32) -> Self {
+        Self {
+            temperature,
+            ..self
+        }
+    }
+    /// Numbered `sequence`.
+    pub fn numbered(self, sequence: u32) -> Self {
+        Self { sequence, ..self }
+    }
+    /// Taken at `at`.
+    pub fn at(self, at: Instant) -> Self {
+        Self { at, ..self }
+    }
+}
@@ -0,0 +1,157 @@
+//! Power-on self-test of the board's sensors.
+//!
+//! [`run`] checks each sensor before the application starts using them, and gathers the
+//! results into a [`Report`]. The report is logged, shown on the LED with
+//! [`Report::pattern`], and kept for the BLE self-test service to read with [`latest_report`].
+//!
+//! The checks, in the order the LED shows them, are:
+//!
+//! 1. The IMU answers as an ICM42670 on `WHO_AM_I`
+//! 2. Every axis of the IMU passes its built-in self-test
+//! 3. The ambient sensor answers as an SHTC3, with a plausible measurement
+
+use core::cell::Cell;
+
+#[cfg(feature = "hardware")]
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use log::{info, warn};
+use smart_leds::{
+    RGB8,
+    colors::{BLACK, GREEN, RED},
+};
+
+use crate::ambient::AmbientSelfTest;
+#[cfg(feature = "hardware")]
+use crate::ambient::AmbientSensor;
+#[cfg(feature = "hardware")]
+use crate::bsp::I2cBus;
+#[cfg(feature = "hardware")]
+use crate::imu;
+use crate::imu::ImuSelfTest;
+
+/// Number of checks in a report.
+pub const CHECKS: usize = 3;
+
+/// LED pattern for each combination of failed checks, indexed by [`Report::failed`].
+static PATTERNS: [[RGB8; 2 * CHECKS]; 1 << CHECKS] = {
+    let mut patterns = [[BLACK; 2 * CHECKS]; 1 << CHECKS];
+    let mut failed = 0;
+    while failed < patterns.len() {
+        let mut check = 0;
+        while check < CHECKS {
+            let colour = if failed & (1 << check) == 0 {
+                GREEN
+            } else {
+                RED
+            };
+            patterns[failed][2 * check] = colour;
+            check += 1;
+        }
+        failed += 1;
+    }
+    patterns
+};
+
+static LATEST: Mutex<CriticalSectionRawMutex, Cell<Option<Report>>> = Mutex::new(Cell::new(None));
+
+/// Results of every check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    /// The IMU's identity and built-in self-test
+    pub imu: ImuSelfTest,
+    /// The ambient sensor's identity and plausibility read
+    pub ambient: AmbientSelfTest,
+}
+
+impl Report {
+    /// Whether each check passed, in order.
+    pub fn checks(&self) -> [bool; CHECKS] {
+        [
+            self.imu.identified(),
+            self.imu.axes_passed(),
+            self.ambient.passed(),
+        ]
+    }
+    /// Whether every check passed.
+    pub fn passed(&self) -> bool {
+        self.checks().iter().all(|&passed| passed)
+    }
+    /// The checks that failed, the first in bit 0.
+    pub fn failed(&self) -> u8 {
+        self.checks()
+            .iter()
+            .enumerate()
+            .filter(|&(_, &passed)| !passed)
+            .fold(0, |failed, (check, _)| failed | (1 << check))
+    }
+    /// A flash for each check in order, green if it passed and red if it failed.
+    pub fn pattern(&self) -> &'static [RGB8] {
+        &PATTERNS[self.failed() as usize]
+    }
+    /// Log the result of each check.
+    pub fn log(&self) {
+        let (imu, ambient) = (self.imu, self.ambient);
+        info!("IMU WHO_AM_I: {:02x?}", imu.device_id);
+        info!(
+            "IMU self-test: completed {}, accelerometer axes {:03b}, gyroscope axes {:03b}",
+            imu.completed, imu.accel_axes, imu.gyro_axes
+        );
+        info!(
+            "Ambient ID: {:04x?}, {:?}°C, {:?}%RH",
+            ambient.id, ambient.temperature, ambient.humidity
+        );
+        if self.passed() {
+            info!("Self-test passed");
+        } else {
+            warn!("Self-test failed: {:03b}", self.failed());
+        }
+    }
+}
+
+/// Check every sensor on the board's shared I2C bus, and log and keep the report.
+#[cfg(feature = "hardware")]
+pub async fn run(i2c_bus: &'static I2cBus<'static>) -> Report {
+    let report = Report {
+        imu: imu::self_test(I2cDevice::new(i2c_bus)).await,
+        ambient: AmbientSensor::new(i2c_bus).self_test().await,
+    };
+    report.log();
+    LATEST.lock(|latest| latest.set(Some(report)));
+    report
+}
+
+/// The report of the last self-test, if one has been run.
+pub fn latest_report() -> Option<Report> {
+    LATEST.lock(Cell::get)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imu;
+
+    #[test]
+    fn shows_each_check() {
+        let mut report = Report {
+            imu: ImuSelfTest {
+                device_id: Some(0x67),
+                completed: true,
+                accel_axes: imu::SELF_TEST_AXES,
+                gyro_axes: imu::SELF_TEST_AXES,
+            },
+            ambient: AmbientSelfTest {
+                id: Some(0x0807),
+                temperature: Some(21.5),
+                humidity: Some(40.0),
+            },
+        };
+        assert!(report.passed());
+        assert_eq!(report.pattern(), [GREEN, BLACK, GREEN, BLACK, GREEN, BLACK]);
+
+        report.imu.gyro_axes = 0b011;
+        report.ambient.humidity = None;
+        assert_eq!(report.failed(), 0b110);
+        assert_eq!(report.pattern(), [GREEN, BLACK, RED, BLACK, RED, BLACK]);
+    }
+}
@@ -0,0 +1,109 @@
+#!/usr/bin/env python3
+"""Split a stream of labelled IMU samples from the capture example into a CSV file per label.
+
+The stream is read from the board's serial port, or from a file it was saved to, until it
+ends or is interrupted with Ctrl-C. Frames are found among the log text by their sync word
+and checked against their checksum, see `src/imu/capture.rs` for the layout. Each label's
+samples are appended to `<output>/<label>.csv`, with the columns
+
+    sequence, time_us, accel_x, accel_y, accel_z, gyro_x, gyro_y, gyro_z, temperature
+
+in g, °/s and °C. Reading straight from the port on Linux needs it in raw mode first:
+
+    stty -F /dev/ttyACM0 raw
+"""
+
+import argparse
+import csv
+import struct
+import sys
+from pathlib import Path
+
+SYNC = b"\xa5\x5a"
+PAYLOAD = struct.Struct("<BIQ7f")
+FRAME_LEN = len(SYNC) + 1 + PAYLOAD.size + 4
+COLUMNS = [
+    "sequence",
+    "time_us",
+    "accel_x",
+    "accel_y",
+    "accel_z",
+    "gyro_x",
+    "gyro_y",
+    "gyro_z",
+    "temperature",
+]
+DEFAULT_LABELS = ["none", "shake", "circle", "flick_left", "flick_right"]
+
+
+def fnv1a(data: bytes) -> int:
+    value = 0x811C_9DC5
+    for byte in data:
+        value = ((value ^ byte) * 0x0100_0193) & 0xFFFF_FFFF
+    return value
+
+
+def frames(stream):
+    """Yield the payload of every valid frame in a binary stream, skipping anything else."""
+    buffer = b""
+    while chunk := stream.read(256):
+        buffer += chunk
+        while True:
+            start = buffer.find(SYNC)
+            if start < 0:
+                # keep a trailing byte that may be the start of a sync word
+                buffer = buffer[-1:]
+                break
+            if len(buffer) < start + FRAME_LEN:
+                buffer = buffer[start:]
+                break
+            frame = buffer[start : start + FRAME_LEN]
+            body, (checksum,) = frame[2:-4], struct.unpack("<I", frame[-4:])
+            if body[0] == PAYLOAD.size and fnv1a(body) == checksum:
+                yield PAYLOAD.unpack(body[1:])
+                buffer = buffer[start + FRAME_LEN :]
+            else:
+                # not a frame, or a corrupted one, so look for the next sync word
+                buffer = buffer[start + 1 :]
+
+
+def main() -> None:
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("input", help="serial port or saved stream to read")
+    parser.add_argument("output", help="directory to write the CSV files to")
+    parser.add_argument(
+        "--labels",
+        nargs="+",
+        default=DEFAULT_LABELS,
+        help="name of each label, in order from 0",
+    )
+    args = parser.parse_args()
+    output = Path(args.output)
+    output.mkdir(parents=True, exist_ok=True)
+
+    files, writers, counts = {}, {}, {}
+    try:
+        with open(args.input, "rb", buffering=0) as stream:
+            for label, *sample in frames(stream):
+                name = args.labels[label] if label < len(args.labels) else f"label_{label}"
+                if name not in writers:
+                    path = output / f"{name}.csv"
+                    new = not path.exists()
+                    files[name] = open(path, "a", newline="")
+                    writers[name] = csv.writer(files[name])
+                    if new:
+                        writers[name].writerow(COLUMNS)
+                    counts[name] = 0
+                writers[name].writerow(sample)
+                counts[name] += 1
+    except KeyboardInterrupt:
+        pass
+    finally:
+        for file in files.values():
+            file.close()
+    for name, count in counts.items():
+        print(f"{name}: {count} samples", file=sys.stderr)
+
+
+if __name__ == "__main__":
+    main()
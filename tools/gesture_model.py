@@ -0,0 +1,89 @@
+#!/usr/bin/env python3
+"""Pack quantised gesture network weights into the blob read by `GestureModel`.
+
+The weights come from a JSON file:
+
+    {
+      "sample_rate": 50,
+      "shift": 6,
+      "output_scale": 0.01,
+      "hidden_weights": [[...window * 6 int8...], ...],
+      "hidden_biases": [...int32 per hidden unit...],
+      "output_weights": [[...int8 per hidden unit...], ...],
+      "output_biases": [...int32 per output...]
+    }
+
+with one output per class: none, shake, circle, flick left, flick right. The window
+is taken from the length of the hidden rows. Flash the result with
+
+    espflash write-bin 0xa000 gesture.bin
+"""
+
+import argparse
+import json
+import struct
+
+MAGIC = 0x3154_5347  # "GST1"
+CHANNELS = 6
+CLASSES = 5
+MAX_WINDOW = 64
+MAX_HIDDEN = 64
+
+
+def fnv1a(data: bytes) -> int:
+    value = 0x811C_9DC5
+    for byte in data:
+        value = ((value ^ byte) * 0x0100_0193) & 0xFFFF_FFFF
+    return value
+
+
+def pack(model: dict) -> bytes:
+    hidden_weights = model["hidden_weights"]
+    output_weights = model["output_weights"]
+    hidden = len(hidden_weights)
+    inputs = len(hidden_weights[0])
+    if inputs % CHANNELS:
+        raise ValueError(f"hidden rows must be a multiple of {CHANNELS} inputs")
+    window = inputs // CHANNELS
+    if not 1 <= window <= MAX_WINDOW or not 1 <= hidden <= MAX_HIDDEN:
+        raise ValueError(f"window {window} or hidden units {hidden} out of range")
+    if len(output_weights) != CLASSES:
+        raise ValueError(f"expected {CLASSES} outputs, got {len(output_weights)}")
+    if any(len(row) != inputs for row in hidden_weights) or any(
+        len(row) != hidden for row in output_weights
+    ):
+        raise ValueError("weight rows have inconsistent lengths")
+
+    blob = struct.pack(
+        "<IHHHBBf",
+        MAGIC,
+        window,
+        model["sample_rate"],
+        hidden,
+        CLASSES,
+        model["shift"],
+        model["output_scale"],
+    )
+    for row in hidden_weights:
+        blob += struct.pack(f"<{inputs}b", *row)
+    blob += struct.pack(f"<{hidden}i", *model["hidden_biases"])
+    for row in output_weights:
+        blob += struct.pack(f"<{hidden}b", *row)
+    blob += struct.pack(f"<{CLASSES}i", *model["output_biases"])
+    return blob + struct.pack("<I", fnv1a(blob))
+
+
+def main() -> None:
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("weights", help="JSON file of quantised weights")
+    parser.add_argument("output", help="binary blob to write")
+    args = parser.parse_args()
+    with open(args.weights) as file:
+        blob = pack(json.load(file))
+    with open(args.output, "wb") as file:
+        file.write(blob)
+    print(f"Wrote {len(blob)} bytes to {args.output}")
+
+
+if __name__ == "__main__":
+    main()